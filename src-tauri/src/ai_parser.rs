@@ -44,12 +44,26 @@ pub fn scan_for_patterns(names: Vec<String>) -> Vec<DetectedPattern> {
         .collect();
 
     // Sort by count descending
-    patterns.sort_by(|a, b| b.count.cmp(&a.count));
+    patterns.sort_by_key(|p| std::cmp::Reverse(p.count));
 
     patterns
 }
 
-fn extract_prefix(name: &str) -> Option<String> {
+pub fn extract_prefix(name: &str) -> Option<String> {
+    find_prefix(name).map(|(prefix, _)| prefix)
+}
+
+/// Name with its country or quality prefix removed, if it has one
+pub fn strip_prefix(name: &str) -> &str {
+    let trimmed = name.trim();
+    match find_prefix(trimmed) {
+        Some((_, end)) => trimmed[end..].trim(),
+        None => trimmed,
+    }
+}
+
+/// Normalized prefix of a name, with where it ends in the trimmed name
+fn find_prefix(name: &str) -> Option<(String, usize)> {
     let trimmed = name.trim();
 
     // 1. Check for Vertical Bar "AR | Channel"
@@ -57,7 +71,7 @@ fn extract_prefix(name: &str) -> Option<String> {
         if let Some(m) = caps.name("prefix") {
             let p = m.as_str().trim();
             if is_valid_prefix(p) {
-                return Some(format!("{} |", p)); // Normalized form with 1 trailing space
+                return Some((format!("{} |", p), caps[0].len())); // Normalized form with 1 trailing space
             }
         }
     }
@@ -67,7 +81,7 @@ fn extract_prefix(name: &str) -> Option<String> {
         if let Some(m) = caps.name("prefix") {
             let p = m.as_str().trim();
             if is_valid_prefix(p) {
-                return Some(format!("[{}]", p));
+                return Some((format!("[{}]", p), caps[0].len()));
             }
         }
     }
@@ -77,7 +91,7 @@ fn extract_prefix(name: &str) -> Option<String> {
         if let Some(m) = caps.name("prefix") {
             let p = m.as_str().trim();
             if is_valid_prefix(p) {
-                return Some(format!("{}:", p));
+                return Some((format!("{}:", p), caps[0].len()));
             }
         }
     }
@@ -87,7 +101,7 @@ fn extract_prefix(name: &str) -> Option<String> {
         if let Some(m) = caps.name("prefix") {
             let p = m.as_str().trim();
             if is_valid_prefix(p) {
-                return Some(format!("{} -", p));
+                return Some((format!("{} -", p), caps[0].len()));
             }
         }
     }
@@ -98,7 +112,7 @@ fn extract_prefix(name: &str) -> Option<String> {
             let p = m.as_str().trim();
             // Stricter check for parens to avoid (2024) year tags being treated as prefixes unless very frequent
             if is_valid_prefix(p) && p.len() < 10 { 
-                return Some(format!("({})", p));
+                return Some((format!("({})", p), caps[0].len()));
             }
         }
     }
@@ -109,13 +123,13 @@ fn extract_prefix(name: &str) -> Option<String> {
 fn is_valid_prefix(p: &str) -> bool {
     let len = p.len();
     // Too short or too long is likely noise or part of the title
-    if len < 2 || len > 12 {
+    if !(2..=12).contains(&len) {
         return false;
     }
     
     // Ignore if it looks like a year
-    if let Ok(_) = p.parse::<u16>() {
-        if len == 4 { return false; }
+    if len == 4 && p.parse::<u16>().is_ok() {
+        return false;
     }
 
     true
@@ -130,10 +144,13 @@ mod tests {
         let names = vec![
             "AR | BeIN Sports 1".to_string(),
             "AR | BeIN Sports 2".to_string(),
+            "AR | BeIN Sports 3".to_string(),
             "UK | Sky Sports".to_string(),
             "UK | BBC One".to_string(),
+            "UK | BBC Two".to_string(),
             "[USA] CNN".to_string(),
             "[USA] FOX".to_string(),
+            "[USA] NBC".to_string(),
             "Nothing here".to_string(),
             "FR - TF1".to_string(),
         ];
@@ -144,10 +161,8 @@ mod tests {
         assert!(patterns.iter().any(|p| p.prefix == "UK |"));
         assert!(patterns.iter().any(|p| p.prefix == "[USA]"));
         
-        // "FR -" only appears once, should be filtered out by threshold if list was longer, 
-        // but here threshold is 3.max(0) = 3. Wait, threshold logic: 3.max(8/200) = 3. 
-        // So actually ALL should be filtered out in this small test unless I tweak threshold for tests or add more items.
-        // Let's re-verify threshold logic for small lists. 
+        // "FR -" only appears once, below the threshold of 3.max(11 / 200) = 3
+        assert!(!patterns.iter().any(|p| p.prefix == "FR -"));
     }
     
     #[test]
@@ -160,4 +175,12 @@ mod tests {
         assert_eq!(extract_prefix("(4K) Movie"), Some("(4K)".to_string()));
         assert_eq!(extract_prefix("Movie 2024"), None); // Should not match
     }
+
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(strip_prefix(" UK | BBC One HD"), "BBC One HD");
+        assert_eq!(strip_prefix("[USA] CNN"), "CNN");
+        assert_eq!(strip_prefix("FR - TF1"), "TF1");
+        assert_eq!(strip_prefix("Movie 2024"), "Movie 2024");
+    }
 }
@@ -0,0 +1,223 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+//! Catch-up urls of M3U channels, following the `catchup` modes of the IPTV Simple conventions.
+//! `catchup-source` templates may use `{utc}`/`{start}`, `{utcend}`/`{end}`, `{lutc}`/`{now}`,
+//! `{duration}`, `{offset}` (both with an optional `:divider`), `{Y}` `{m}` `{d}` `{H}` `{M}`
+//! `{S}`, or a time with its own format such as `{utc:Y-m-d-H-M-S}`. A leading `$` is ignored.
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use regex::{Captures, Regex};
+
+use crate::types::Channel;
+
+/// Days of archive assumed when the playlist doesn't say
+const DEFAULT_CATCHUP_DAYS: i64 = 5;
+
+static PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\$?\{(?P<name>[a-zA-Z]+)(?::(?P<arg>[^}]+))?\}"#)
+        .expect("Failed to compile PLACEHOLDER_REGEX - static pattern")
+});
+static FLUSSONIC_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^(?P<host>https?://[^/]+)/(?P<channel>.+)/(?P<list>[^/]*?)(?P<ext>mpegts|\.m3u8)(?P<query>\?.*)?$"#)
+        .expect("Failed to compile FLUSSONIC_REGEX - static pattern")
+});
+static XC_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^(?P<host>https?://[^/]+)/(?:live/)?(?P<user>[^/]+)/(?P<pass>[^/]+)/(?P<id>[^/.]+)(?:\.(?P<ext>m3u8|ts))?$"#)
+        .expect("Failed to compile XC_REGEX - static pattern")
+});
+
+/// Oldest moment a channel can be replayed from, None when it has no catch-up
+pub fn get_window_start(channel: &Channel, now: i64) -> Option<i64> {
+    channel.catchup.as_ref()?;
+    let days = channel.catchup_days.filter(|days| *days > 0).unwrap_or(DEFAULT_CATCHUP_DAYS);
+    Some(now - days * 24 * 60 * 60)
+}
+
+/// Url replaying a programme of the channel, if it has started and is still in the archive
+pub fn get_catchup_url(channel: &Channel, start: i64, stop: i64, now: i64) -> Option<String> {
+    if start > now || start < get_window_start(channel, now)? {
+        return None;
+    }
+    let url = channel.url.as_deref()?;
+    let source = channel.catchup_source.as_deref().filter(|s| !s.trim().is_empty());
+    let template = match channel.catchup.as_deref()? {
+        "default" => source?.to_string(),
+        "append" => format!("{url}{}", source?),
+        "shift" | "timeshift" => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{url}{separator}utc={{utc}}&lutc={{lutc}}")
+        }
+        "flussonic" | "flussonic-hls" | "flussonic-ts" | "fs" => get_flussonic_template(url)?,
+        "xc" => get_xc_template(url)?,
+        _ => return None,
+    };
+    Some(fill_template(&template, start, stop, now))
+}
+
+fn get_flussonic_template(url: &str) -> Option<String> {
+    let captures = FLUSSONIC_REGEX.captures(url)?;
+    let host = &captures["host"];
+    let channel = &captures["channel"];
+    let query = captures.name("query").map_or("", |m| m.as_str());
+    if &captures["ext"] == "mpegts" {
+        return Some(format!("{host}/{channel}/timeshift_abs-{{utc}}.ts{query}"));
+    }
+    let list = match &captures["list"] {
+        "" => "index",
+        list => list,
+    };
+    Some(format!("{host}/{channel}/{list}-{{utc}}-{{duration}}.m3u8{query}"))
+}
+
+fn get_xc_template(url: &str) -> Option<String> {
+    let captures = XC_REGEX.captures(url)?;
+    Some(format!(
+        "{}/timeshift/{}/{}/{{duration:60}}/{{Y}}-{{m}}-{{d}}:{{H}}-{{M}}/{}.{}",
+        &captures["host"],
+        &captures["user"],
+        &captures["pass"],
+        &captures["id"],
+        captures.name("ext").map_or("ts", |m| m.as_str())
+    ))
+}
+
+fn fill_template(template: &str, start: i64, stop: i64, now: i64) -> String {
+    PLACEHOLDER_REGEX
+        .replace_all(template, |captures: &Captures| {
+            let arg = captures.name("arg").map(|m| m.as_str());
+            let divider = arg.and_then(|arg| arg.parse::<i64>().ok()).filter(|d| *d > 0);
+            let value = match &captures["name"] {
+                "utc" | "start" => format_time(start, arg),
+                "utcend" | "end" => format_time(stop, arg),
+                "lutc" | "now" | "timestamp" => format_time(now, arg),
+                "duration" => Some(((stop - start) / divider.unwrap_or(1)).to_string()),
+                "offset" => Some(((now - start) / divider.unwrap_or(1)).to_string()),
+                "Y" | "m" | "d" | "H" | "M" | "S" => format_time(start, Some(&captures["name"])),
+                _ => None,
+            };
+            value.unwrap_or_else(|| captures[0].to_string())
+        })
+        .to_string()
+}
+
+/// The timestamp itself, or its UTC time with `Y` `m` `d` `H` `M` `S` of `format` replaced
+fn format_time(timestamp: i64, format: Option<&str>) -> Option<String> {
+    let Some(format) = format else {
+        return Some(timestamp.to_string());
+    };
+    let time: DateTime<Utc> = DateTime::from_timestamp(timestamp, 0)?;
+    let mut formatted = String::with_capacity(format.len() * 2);
+    for c in format.chars() {
+        match c {
+            'Y' | 'm' | 'd' | 'H' | 'M' | 'S' => {
+                formatted.push_str(&time.format(&format!("%{c}")).to_string())
+            }
+            _ => formatted.push(c),
+        }
+    }
+    Some(formatted)
+}
+
+#[cfg(test)]
+mod test_catchup {
+    use super::*;
+
+    // 2024-03-05 20:30:00 UTC, an hour long, watched 2 hours after it started
+    const START: i64 = 1709670600;
+    const STOP: i64 = START + 3600;
+    const NOW: i64 = START + 7200;
+
+    fn channel(url: &str, catchup: &str, source: Option<&str>) -> Channel {
+        Channel {
+            id: Some(1),
+            name: "Channel".to_string(),
+            url: Some(url.to_string()),
+            group: None,
+            image: None,
+            media_type: 0,
+            source_id: Some(1),
+            series_id: None,
+            group_id: None,
+            favorite: false,
+            stream_id: None,
+            tv_archive: None,
+            season_id: None,
+            episode_num: None,
+            hidden: None,
+            rating: None,
+            genre: None,
+            release_date: None,
+            plot: None,
+            cast: None,
+            director: None,
+            tvg_id: None,
+            tvg_chno: None,
+            tvg_shift: None,
+            catchup: Some(catchup.to_string()),
+            catchup_source: source.map(|s| s.to_string()),
+            catchup_days: Some(3),
+            tvg_rec: None,
+            now_program: None,
+            next_program: None,
+        }
+    }
+
+    fn url(channel: &Channel) -> Option<String> {
+        get_catchup_url(channel, START, STOP, NOW)
+    }
+
+    #[test]
+    fn test_catchup_modes() {
+        let c = channel("http://a.tv/live/1.m3u8", "default", Some("http://a.tv/archive/1.m3u8?start={utc}&end=${utcend}&d={duration:60}"));
+        assert_eq!(url(&c).as_deref(), Some("http://a.tv/archive/1.m3u8?start=1709670600&end=1709674200&d=60"));
+
+        let c = channel("http://a.tv/live/1.m3u8?token=x", "append", Some("&from={Y}-{m}-{d}T{H}:{M}:{S}&offset={offset:60}"));
+        assert_eq!(url(&c).as_deref(), Some("http://a.tv/live/1.m3u8?token=x&from=2024-03-05T20:30:00&offset=120"));
+
+        let c = channel("http://a.tv/live/1.m3u8", "shift", None);
+        assert_eq!(url(&c).as_deref(), Some("http://a.tv/live/1.m3u8?utc=1709670600&lutc=1709677800"));
+
+        let c = channel("http://fl.tv/ch1/index.m3u8?token=x", "flussonic", None);
+        assert_eq!(url(&c).as_deref(), Some("http://fl.tv/ch1/index-1709670600-3600.m3u8?token=x"));
+
+        let c = channel("http://fl.tv/ch1/mpegts?token=x", "fs", None);
+        assert_eq!(url(&c).as_deref(), Some("http://fl.tv/ch1/timeshift_abs-1709670600.ts?token=x"));
+
+        let c = channel("http://xc.tv:8080/live/user/pass/42.m3u8", "xc", None);
+        assert_eq!(url(&c).as_deref(), Some("http://xc.tv:8080/timeshift/user/pass/60/2024-03-05:20-30/42.m3u8"));
+
+        let c = channel("http://a.tv/1.ts", "default", Some("http://a.tv/{utc:Y/m/d/H-M}.ts?{unknown}"));
+        assert_eq!(url(&c).as_deref(), Some("http://a.tv/2024/03/05/20-30.ts?{unknown}"));
+    }
+
+    #[test]
+    fn test_catchup_window() {
+        let c = channel("http://a.tv/live/1.m3u8", "shift", None);
+        assert_eq!(get_catchup_url(&c, NOW + 60, NOW + 120, NOW), None);
+        assert_eq!(get_catchup_url(&c, NOW - 4 * 86400, NOW - 4 * 86400 + 60, NOW), None);
+        assert!(get_catchup_url(&c, NOW - 60, NOW + 60, NOW).is_some());
+        assert_eq!(url(&channel("http://a.tv/1.m3u8", "default", None)), None);
+        assert_eq!(url(&channel("http://a.tv/1.m3u8", "vod", None)), None);
+    }
+}
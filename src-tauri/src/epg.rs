@@ -19,130 +19,324 @@
  * This project is a fork of Open TV by Fredolx.
  */
 
-use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering::Relaxed},
-    },
-    thread::{self, sleep},
-    time::Duration,
-};
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::{Context, Result};
-use chrono::Local;
-use tauri::{AppHandle, State};
-use tauri_plugin_notification::NotificationExt;
-use tokio::sync::Mutex;
+use chrono::{DateTime, Days, Local, NaiveDate};
 
 use crate::{
-    log, sql,
-    types::{AppState, EPGNotify},
-    utils,
+    catchup, epg_origin, log, scheduler, source_type, sql,
+    types::{
+        ArchiveDay, Channel, EPG, EPGGridChannel, EPGNotify, EPGProgram, EPGSearchResult, Source,
+    },
+    xtream,
 };
 
-pub fn poll(mut to_watch: Vec<EPGNotify>, stop: Arc<AtomicBool>, app: AppHandle) -> Result<()> {
-    while !stop.load(Relaxed) && !to_watch.is_empty() {
-        to_watch.retain(|epg| {
-            let is_timestamp_over = match is_timestamp_over(epg.start_timestamp) {
-                Ok(v) => v,
-                Err(e) => {
-                    log::log(format!("{:?}", e));
-                    return false;
-                }
-            };
-            if is_timestamp_over {
-                match notify(epg, &app).context("Failed to notify EPG") {
-                    Ok(_) => {}
-                    Err(e) => log::log(format!("{:?}", e)),
-                }
-                return false;
-            }
-            return true;
-        });
-        sleep(Duration::from_secs(1));
-    }
+/// Xtream guides are served from the cache this long before the panel is asked again
+const XTREAM_EPG_TTL_SECONDS: i64 = 4 * 60 * 60;
+const EPG_PREFETCH_DELAY: Duration = Duration::from_millis(500);
+const EPG_SEARCH_LIMIT: u32 = 200;
+/// Most archive days offered when the panel doesn't say how long it keeps the archive
+const MAX_ARCHIVE_DAYS: i64 = 30;
+
+pub fn add_epg(epg: EPGNotify) -> Result<()> {
+    sql::add_epg(epg)?;
+    scheduler::wake();
     Ok(())
 }
 
-fn notify(epg: &EPGNotify, app: &AppHandle) -> Result<()> {
-    app.notification()
-        .builder()
-        .title(format!("LIVE: {}", epg.title))
-        .body(format!("Watch on {}", epg.channel_name))
-        .show()?;
+pub fn remove_epg(epg_id: String) -> Result<()> {
+    sql::remove_epg(epg_id)?;
+    scheduler::wake();
     Ok(())
 }
 
-fn is_timestamp_over(timestamp: i64) -> Result<bool> {
-    let time = utils::get_local_time(timestamp)?;
-    let current_time = Local::now();
-    Ok(current_time >= time)
-}
-
-pub async fn add_epg(
-    state: State<'_, Mutex<AppState>>,
-    app: AppHandle,
-    epg: EPGNotify,
-) -> Result<()> {
-    let mut state = state.lock().await;
-    if state.thread_handle.is_some() {
-        state.notify_stop.store(true, Relaxed);
-        let _ = state
-            .thread_handle
-            .take()
-            .context("no thread in option")?
-            .join();
-    }
-    state.notify_stop.store(false, Relaxed);
-    let stop = state.notify_stop.clone();
-    sql::clean_epgs()?;
-    sql::add_epg(epg)?;
-    let list = sql::get_epgs()?;
-    state
-        .thread_handle
-        .replace(thread::spawn(|| poll(list, stop, app)));
-    Ok(())
+/// Guide of a channel. Imported XMLTV programmes win, Xtream channels otherwise use the panel's
+/// listing, cached for `XTREAM_EPG_TTL_SECONDS`.
+pub async fn get_epg(channel: Channel) -> Result<Vec<EPG>> {
+    let now = Local::now().timestamp();
+    let channel_id = channel.id.context("no channel id")?;
+    let mut source = sql::get_source_from_id(channel.source_id.context("no source id")?)?;
+    let xtream_stream_id = channel
+        .stream_id
+        .filter(|_| source.source_type == source_type::XTREAM);
+    let mut fetch_error = None;
+    if xtream_stream_id.is_some() && !sql::has_epg_programs(channel_id, epg_origin::XMLTV)? {
+        fetch_error = fetch_expired_xtream_epg(&channel, &source, now).await?;
+    }
+    // Ended programmes are kept while M3U catch-up can still replay them
+    let catchup_from = match xtream_stream_id {
+        Some(_) => None,
+        None => catchup::get_window_start(&channel, now),
+    };
+    let programs = sql::get_epg_programs(channel_id, catchup_from.unwrap_or(now))?;
+    // A stale guide beats no guide when the panel can't be reached
+    if let (true, Some(e)) = (programs.is_empty(), fetch_error) {
+        return Err(e);
+    }
+    let mut epgs = Vec::with_capacity(programs.len());
+    for program in programs {
+        let timeshift_url = match xtream_stream_id {
+            Some(stream_id) => xtream::get_program_timeshift_url(&mut source, &program, stream_id)?,
+            None => catchup::get_catchup_url(&channel, program.start, program.stop, now),
+        };
+        let mut epg = program_to_epg(program, now);
+        epg.has_archive |= timeshift_url.is_some();
+        epg.timeshift_url = timeshift_url;
+        epgs.push(epg);
+    }
+    Ok(epgs)
 }
 
-pub async fn remove_epg(
-    state: State<'_, Mutex<AppState>>,
-    app: AppHandle,
-    epg_id: String,
-) -> Result<()> {
-    let mut state = state.lock().await;
-    if state.thread_handle.is_some() {
-        state.notify_stop.store(true, Relaxed);
-        let _ = state
-            .thread_handle
-            .take()
-            .context("no thread in option")?
-            .join();
-    }
-    state.notify_stop.store(false, Relaxed);
-    let stop = state.notify_stop.clone();
-    sql::clean_epgs()?;
-    sql::remove_epg(epg_id)?;
-    let list = sql::get_epgs()?;
-    if list.len() == 0 {
-        return Ok(());
+/// Fetches the panel's guide of a channel when its cached copy expired. A failed fetch is
+/// returned rather than raised, since the stale copy may still do.
+async fn fetch_expired_xtream_epg(
+    channel: &Channel,
+    source: &Source,
+    now: i64,
+) -> Result<Option<anyhow::Error>> {
+    let channel_id = channel.id.context("no channel id")?;
+    let expired = sql::get_epg_fetch_expiry(channel_id)?.is_none_or(|expires_at| expires_at <= now);
+    if !expired {
+        return Ok(None);
+    }
+    match xtream::fetch_epg(channel, source.clone(), XTREAM_EPG_TTL_SECONDS).await {
+        Ok(_) => Ok(None),
+        Err(e) => {
+            log::log(format!("Failed to fetch EPG for {}: {:?}", channel.name, e));
+            Ok(Some(e))
+        }
     }
-    state
-        .thread_handle
-        .replace(thread::spawn(|| poll(list, stop, app)));
-    Ok(())
 }
 
-pub async fn on_start_check_epg(state: State<'_, Mutex<AppState>>, app: AppHandle) -> Result<()> {
-    sql::clean_epgs()?;
-    let list = sql::get_epgs()?;
-    if list.len() == 0 {
-        return Ok(());
+/// Archive of an Xtream channel for each of the last `days` days, today first, capped by how
+/// long the panel keeps it. Days the panel's guide doesn't cover are offered as hourly slots.
+pub async fn get_xtream_archive(channel: Channel, days: Option<i64>) -> Result<Vec<ArchiveDay>> {
+    if channel.tv_archive != Some(true) {
+        anyhow::bail!("{} has no archive", channel.name);
+    }
+    let now = Local::now().timestamp();
+    let channel_id = channel.id.context("no channel id")?;
+    let stream_id = channel.stream_id.context("no stream id")?;
+    let mut source = sql::get_source_from_id(channel.source_id.context("no source id")?)?;
+    let days = get_archive_days(days, channel.catchup_days)?;
+    let fetch_error = fetch_expired_xtream_epg(&channel, &source, now).await?;
+    let archive_start = now - days * 24 * 60 * 60;
+    let programs = sql::get_xtream_archive_programs(channel_id, archive_start, now)?;
+    if let (true, Some(e)) = (programs.is_empty(), fetch_error) {
+        return Err(e);
+    }
+    let server_offset = xtream::get_server_utc_offset(&source, programs.first());
+    let mut by_date: HashMap<NaiveDate, Vec<EPG>> = HashMap::new();
+    for program in programs {
+        let timeshift_url = xtream::get_program_timeshift_url(&mut source, &program, stream_id)?;
+        let Some(timeshift_url) = timeshift_url else {
+            continue;
+        };
+        let date = local_date(program.start)?;
+        let mut epg = program_to_epg(program, now);
+        epg.timeshift_url = Some(timeshift_url);
+        by_date.entry(date).or_default().push(epg);
+    }
+    let today = Local::now().date_naive();
+    let mut archive = Vec::with_capacity(days as usize);
+    // The oldest day is only partly in the archive, so the last `days` days span one more date
+    for date in (0..=days as u64).filter_map(|i| today.checked_sub_days(Days::new(i))) {
+        let programs = match by_date.remove(&date) {
+            Some(programs) => programs,
+            None => get_hourly_slots(
+                &mut source,
+                channel_id,
+                stream_id,
+                date,
+                archive_start,
+                now,
+                server_offset,
+            )?,
+        };
+        if !programs.is_empty() {
+            archive.push(ArchiveDay {
+                date: date.format("%Y-%m-%d").to_string(),
+                programs,
+            });
+        }
+    }
+    Ok(archive)
+}
+
+/// Days of archive to list, capped by the panel's archive duration or else `MAX_ARCHIVE_DAYS`
+fn get_archive_days(days: Option<i64>, catchup_days: Option<i64>) -> Result<i64> {
+    let max_days = catchup_days.filter(|days| *days > 0);
+    match days {
+        Some(days) if days < 1 => anyhow::bail!("At least one archive day is needed, got {days}"),
+        Some(days) => Ok(days.min(max_days.unwrap_or(MAX_ARCHIVE_DAYS))),
+        None => Ok(max_days.unwrap_or(1)),
+    }
+}
+
+/// Hour long archive slots of a day, within the archive and starting before `now`
+fn get_hourly_slots(
+    source: &mut Source,
+    channel_id: i64,
+    stream_id: u64,
+    date: NaiveDate,
+    archive_start: i64,
+    now: i64,
+    server_offset: i64,
+) -> Result<Vec<EPG>> {
+    let mut slots = Vec::new();
+    for hour in 0..24 {
+        let Some(start) = date
+            .and_hms_opt(hour, 0, 0)
+            .and_then(|time| time.and_local_timezone(Local).earliest())
+            .map(|time| time.timestamp())
+        else {
+            continue;
+        };
+        if start < archive_start || start >= now {
+            continue;
+        }
+        let stop = start + 60 * 60;
+        let server_start = DateTime::from_timestamp(start + server_offset, 0)
+            .context("invalid slot start")?
+            .naive_utc();
+        let mut epg = program_to_epg(
+            EPGProgram {
+                channel_id,
+                start,
+                stop,
+                title: format!("{} - {}", format_clock(start), format_clock(stop)),
+                has_archive: true,
+                ..Default::default()
+            },
+            now,
+        );
+        epg.timeshift_url = Some(xtream::get_archive_timeshift_url(
+            source,
+            stream_id,
+            server_start,
+            60,
+        )?);
+        slots.push(epg);
+    }
+    Ok(slots)
+}
+
+fn local_date(timestamp: i64) -> Result<NaiveDate> {
+    Ok(DateTime::from_timestamp(timestamp, 0)
+        .context("invalid timestamp")?
+        .with_timezone(&Local)
+        .date_naive())
+}
+
+fn format_clock(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.with_timezone(&Local).format("%H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Keeps the guide of favorited Xtream channels cached, so it is there offline
+pub async fn prefetch_favorites_epg() {
+    loop {
+        if let Err(e) = prefetch_expired_favorites_epg().await {
+            log::log(format!("Failed to prefetch favorites EPG: {:?}", e));
+        }
+        tokio::time::sleep(Duration::from_secs(XTREAM_EPG_TTL_SECONDS as u64)).await;
+    }
+}
+
+async fn prefetch_expired_favorites_epg() -> Result<()> {
+    let mut sources: HashMap<i64, Source> = HashMap::new();
+    for channel in sql::get_epg_prefetch_channels(Local::now().timestamp())? {
+        let source_id = channel.source_id.context("no source id")?;
+        let source = match sources.get(&source_id) {
+            Some(source) => source.clone(),
+            None => {
+                let source = sql::get_source_from_id(source_id)?;
+                sources.insert(source_id, source.clone());
+                source
+            }
+        };
+        if let Err(e) = xtream::fetch_epg(&channel, source, XTREAM_EPG_TTL_SECONDS).await {
+            log::log(format!("Failed to prefetch EPG for {}: {:?}", channel.name, e));
+        }
+        // One channel at a time, panels rate limit bursts of guide requests
+        tokio::time::sleep(EPG_PREFETCH_DELAY).await;
     }
-    let mut state = state.lock().await;
-    state.notify_stop.store(false, Relaxed);
-    let stop = state.notify_stop.clone();
-    state
-        .thread_handle
-        .replace(thread::spawn(|| poll(list, stop, app)));
     Ok(())
 }
+
+/// Cached guide of a page of channels between `start` and `end`, in the order the ids were given.
+/// Channels without guide data come back with no programmes.
+pub fn get_epg_grid(channel_ids: Vec<i64>, start: i64, end: i64) -> Result<Vec<EPGGridChannel>> {
+    if end <= start {
+        anyhow::bail!("The EPG grid window must end after it starts");
+    }
+    let now = Local::now().timestamp();
+    let mut programs: HashMap<i64, Vec<EPG>> = HashMap::new();
+    for program in sql::get_epg_programs_in_window(&channel_ids, start, end)? {
+        programs
+            .entry(program.channel_id)
+            .or_default()
+            .push(program_to_epg(program, now));
+    }
+    Ok(channel_ids
+        .into_iter()
+        .map(|channel_id| EPGGridChannel {
+            channel_id,
+            programs: programs.remove(&channel_id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Upcoming and replayable airings across every channel's stored guide matching `query`
+pub fn search_epg(query: String) -> Result<Vec<EPGSearchResult>> {
+    let now = Local::now().timestamp();
+    Ok(sql::search_epg_programs(&query, now, EPG_SEARCH_LIMIT)?
+        .into_iter()
+        .map(|(program, channel_name, channel_image)| EPGSearchResult {
+            channel_id: program.channel_id,
+            channel_name,
+            channel_image,
+            source_id: program.source_id,
+            program: program_to_epg(program, now),
+        })
+        .collect())
+}
+
+fn program_to_epg(program: EPGProgram, now: i64) -> EPG {
+    EPG {
+        epg_id: format!("{}-{}", program.channel_id, program.start),
+        title: program.title,
+        description: program.description.unwrap_or_default(),
+        start_time: format_epg_time(program.start),
+        start_timestamp: program.start,
+        end_time: format_epg_time(program.stop),
+        end_timestamp: program.stop,
+        timeshift_url: None,
+        has_archive: program.has_archive,
+        now_playing: program.start <= now && now < program.stop,
+    }
+}
+
+fn format_epg_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.with_timezone(&Local).format("%B %d, %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test_epg {
+    use super::*;
+
+    #[test]
+    fn test_get_archive_days() {
+        assert_eq!(get_archive_days(Some(3), Some(7)).unwrap(), 3);
+        assert_eq!(get_archive_days(Some(10), Some(7)).unwrap(), 7);
+        assert_eq!(get_archive_days(None, Some(7)).unwrap(), 7);
+        assert_eq!(get_archive_days(None, None).unwrap(), 1);
+        assert_eq!(get_archive_days(Some(i64::MAX), None).unwrap(), MAX_ARCHIVE_DAYS);
+        assert!(get_archive_days(Some(0), Some(7)).is_err());
+        assert!(get_archive_days(Some(-2), None).is_err());
+    }
+}
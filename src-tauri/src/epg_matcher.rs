@@ -0,0 +1,293 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+//! Proposes which XMLTV guide channel each live channel of a source should use. Names are compared
+//! without the country and quality tags IPTV providers decorate them with, and the mappings users
+//! confirm are kept by channel, which survives refreshes since those update channels in place.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use anyhow::Result;
+use regex::{Captures, Regex};
+
+use crate::{
+    ai_parser, sql, tags,
+    types::{EPGChannelMatch, EPGChannelSuggestion},
+};
+
+/// Suggestions at or above this are what a guide import maps on its own
+const AUTO_MATCH_CONFIDENCE: f32 = 0.9;
+const TVG_ID_CONFIDENCE: f32 = 1.0;
+/// Similar names are never trusted as much as identical ones
+const SIMILAR_NAME_WEIGHT: f32 = 0.8;
+const MIN_SIMILARITY: f32 = 0.6;
+const MAX_SUGGESTIONS: usize = 3;
+const QUALITY_TAGS: &[&str] = &[
+    "SD", "HD", "FHD", "UHD", "4K", "8K", "HQ", "LQ", "HEVC", "H264", "H265", "1080P", "720P",
+    "576P", "50FPS", "60FPS", "RAW", "BACKUP",
+];
+
+static TAG_GROUP_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[(?P<bracket>[^\]]*)\]|\((?P<paren>[^)]*)\)")
+        .expect("Failed to compile TAG_GROUP_REGEX - static pattern")
+});
+
+/// Guide channels of a source, indexed by id, name and name bigrams
+struct GuideIndex {
+    channels: Vec<(String, Vec<String>)>,
+    by_id: HashMap<String, usize>,
+    by_key: HashMap<String, Vec<usize>>,
+    /// Every name key with its channel and number of distinct bigrams
+    keys: Vec<(usize, usize)>,
+    by_bigram: HashMap<(char, char), Vec<usize>>,
+}
+
+impl GuideIndex {
+    fn new(channels: Vec<(String, Vec<String>)>) -> Self {
+        let mut by_id = HashMap::new();
+        let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut keys = Vec::new();
+        let mut by_bigram: HashMap<(char, char), Vec<usize>> = HashMap::new();
+        for (index, (xmltv_id, display_names)) in channels.iter().enumerate() {
+            by_id.insert(xmltv_id.trim().to_lowercase(), index);
+            let mut channel_keys: HashSet<String> = display_names
+                .iter()
+                .map(|name| get_match_key(name))
+                .collect();
+            channel_keys.insert(get_id_key(xmltv_id));
+            for key in channel_keys.into_iter().filter(|key| !key.is_empty()) {
+                let bigrams = get_bigrams(&key);
+                for bigram in &bigrams {
+                    by_bigram.entry(*bigram).or_default().push(keys.len());
+                }
+                keys.push((index, bigrams.len()));
+                let indexes = by_key.entry(key).or_default();
+                if !indexes.contains(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        GuideIndex {
+            channels,
+            by_id,
+            by_key,
+            keys,
+            by_bigram,
+        }
+    }
+
+    /// Best guide channels for a live channel, most likely first
+    fn suggest(&self, name: &str, tvg_id: Option<&str>) -> Vec<EPGChannelSuggestion> {
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        let mut add_score = |index: usize, score: f32| {
+            let best = scores.entry(index).or_insert(0.0);
+            *best = best.max(score);
+        };
+        if let Some(index) = tvg_id.and_then(|id| self.by_id.get(&id.trim().to_lowercase())) {
+            add_score(*index, TVG_ID_CONFIDENCE);
+        }
+        let key = get_match_key(name);
+        for index in self.by_key.get(&key).into_iter().flatten() {
+            add_score(*index, AUTO_MATCH_CONFIDENCE);
+        }
+        // Dice coefficient over the distinct character pairs of both keys
+        let bigrams = get_bigrams(&key);
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for bigram in &bigrams {
+            for key_index in self.by_bigram.get(bigram).into_iter().flatten() {
+                *shared.entry(*key_index).or_insert(0) += 1;
+            }
+        }
+        for (key_index, shared) in shared {
+            let (index, count) = self.keys[key_index];
+            let similarity = 2.0 * shared as f32 / (bigrams.len() + count) as f32;
+            if similarity >= MIN_SIMILARITY {
+                add_score(index, similarity * SIMILAR_NAME_WEIGHT);
+            }
+        }
+        let mut suggestions: Vec<EPGChannelSuggestion> = scores
+            .into_iter()
+            .map(|(index, confidence)| {
+                let (xmltv_id, display_names) = &self.channels[index];
+                EPGChannelSuggestion {
+                    xmltv_id: xmltv_id.clone(),
+                    display_name: display_names.first().cloned(),
+                    confidence,
+                }
+            })
+            .collect();
+        suggestions.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then_with(|| a.xmltv_id.cmp(&b.xmltv_id))
+        });
+        suggestions.truncate(MAX_SUGGESTIONS);
+        suggestions
+    }
+}
+
+/// Live channels of a source with the guide channel they use and the best candidates, from the
+/// guide imported last
+pub fn get_epg_channel_matches(source_id: i64) -> Result<Vec<EPGChannelMatch>> {
+    let (channels, mappings) = sql::do_tx(|tx| {
+        Ok((
+            sql::get_epg_channel_targets(tx, source_id)?,
+            sql::get_epg_channel_mappings(tx, source_id)?,
+        ))
+    })?;
+    let index = GuideIndex::new(sql::get_epg_channels(source_id)?);
+    Ok(channels
+        .into_iter()
+        .map(|(channel_id, channel_name, tvg_id)| {
+            let suggestions = index.suggest(&channel_name, tvg_id.as_deref());
+            let (xmltv_id, confirmed) = match mappings.get(&channel_id) {
+                Some(xmltv_id) => (xmltv_id.clone(), true),
+                None => (
+                    suggestions
+                        .first()
+                        .filter(|s| s.confidence >= AUTO_MATCH_CONFIDENCE)
+                        .map(|s| s.xmltv_id.clone()),
+                    false,
+                ),
+            };
+            EPGChannelMatch {
+                channel_id,
+                channel_name,
+                tvg_id,
+                xmltv_id,
+                confirmed,
+                suggestions,
+            }
+        })
+        .collect())
+}
+
+/// Pins the guide channel of a channel, or no guide at all when `xmltv_id` is None. Takes effect
+/// on the next guide import.
+pub fn set_epg_channel_mapping(channel_id: i64, xmltv_id: Option<String>) -> Result<()> {
+    let xmltv_id = xmltv_id.map(|id| id.trim().to_string());
+    if xmltv_id.as_deref() == Some("") {
+        anyhow::bail!("A guide channel id is required");
+    }
+    sql::set_epg_channel_mapping(channel_id, xmltv_id.as_deref())
+}
+
+/// Hands a channel back to automatic matching
+pub fn remove_epg_channel_mapping(channel_id: i64) -> Result<()> {
+    sql::delete_epg_channel_mapping(channel_id)
+}
+
+/// Name reduced to what identifies the channel: no country or quality prefix, tags or suffixes,
+/// lowercase alphanumerics only. "+" is kept as a word, so timeshifted channels stay apart.
+pub fn get_match_key(name: &str) -> String {
+    let name = ai_parser::strip_prefix(name);
+    let name = TAG_GROUP_REGEX.replace_all(name, |captures: &Captures| {
+        let tag = captures
+            .name("bracket")
+            .or_else(|| captures.name("paren"))
+            .map_or("", |m| m.as_str().trim());
+        if is_quality_tag(tag) || tags::is_valid_tag(tag) {
+            " ".to_string()
+        } else {
+            format!(" {tag} ")
+        }
+    });
+    let mut words: Vec<String> = name
+        .replace('+', " plus ")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !is_quality_tag(word))
+        .map(|word| word.to_lowercase())
+        .collect();
+    // "BBC One UK", the trailing country is a tag unless it is the whole name
+    while words.len() > 1 && words.last().is_some_and(|word| tags::is_valid_tag(word)) {
+        words.pop();
+    }
+    words.concat()
+}
+
+/// Key of an XMLTV id such as "BBCOne.uk", without its country suffix
+fn get_id_key(xmltv_id: &str) -> String {
+    let xmltv_id = xmltv_id.trim();
+    let xmltv_id = match xmltv_id.rsplit_once('.') {
+        Some((name, suffix)) if !name.is_empty() && suffix.len() <= 3 => name,
+        _ => xmltv_id,
+    };
+    get_match_key(xmltv_id)
+}
+
+fn is_quality_tag(tag: &str) -> bool {
+    QUALITY_TAGS.iter().any(|q| q.eq_ignore_ascii_case(tag))
+}
+
+fn get_bigrams(key: &str) -> HashSet<(char, char)> {
+    let chars: Vec<char> = key.chars().collect();
+    chars.windows(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+#[cfg(test)]
+mod test_epg_matcher {
+    use super::*;
+
+    #[test]
+    fn test_get_match_key() {
+        assert_eq!(get_match_key("UK | BBC One HD"), "bbcone");
+        assert_eq!(get_match_key("[USA] CNN (FHD)"), "cnn");
+        assert_eq!(get_match_key("Film4 +1 [UK]"), "film4plus1");
+        assert_eq!(get_match_key("Discovery (East) 4K"), "discoveryeast");
+        assert_eq!(get_match_key("Sky News UK"), "skynews");
+        assert_eq!(get_match_key("UK"), "uk");
+        assert_eq!(get_id_key("BBCOne.uk"), "bbcone");
+        assert_eq!(get_id_key("Sky.News"), "skynews");
+    }
+
+    #[test]
+    fn test_suggest() {
+        let index = GuideIndex::new(vec![
+            ("BBCOne.uk".to_string(), vec!["BBC One".to_string()]),
+            ("BBCTwo.uk".to_string(), vec!["BBC Two".to_string()]),
+            ("cnn.us".to_string(), vec!["CNN International".to_string()]),
+            ("film4.uk".to_string(), vec!["Film4".to_string()]),
+            ("film4plus1.uk".to_string(), vec!["Film4 +1".to_string()]),
+        ]);
+
+        let suggestions = index.suggest("UK: BBC One FHD", None);
+        assert_eq!(suggestions[0].xmltv_id, "BBCOne.uk");
+        assert_eq!(suggestions[0].display_name.as_deref(), Some("BBC One"));
+        assert_eq!(suggestions[0].confidence, AUTO_MATCH_CONFIDENCE);
+
+        let suggestions = index.suggest("Anything", Some("CNN.us"));
+        assert_eq!(suggestions[0].xmltv_id, "cnn.us");
+        assert_eq!(suggestions[0].confidence, TVG_ID_CONFIDENCE);
+
+        let suggestions = index.suggest("Film4 +1 HD", None);
+        assert_eq!(suggestions[0].xmltv_id, "film4plus1.uk");
+        assert!(suggestions[1].confidence < AUTO_MATCH_CONFIDENCE);
+
+        let suggestions = index.suggest("CNN International Europe", None);
+        assert_eq!(suggestions[0].xmltv_id, "cnn.us");
+        assert!(suggestions[0].confidence < AUTO_MATCH_CONFIDENCE);
+
+        assert!(index.suggest("Totally Unrelated", None).is_empty());
+    }
+}
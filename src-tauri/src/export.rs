@@ -0,0 +1,397 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+//! Writes live channels out as an M3U8 playlist, with a matching XMLTV guide from the stored
+//! programmes, so other players such as Kodi or Jellyfin can be fed from the same providers.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use quick_xml::escape::escape;
+
+use crate::{
+    source_type, sql, stream_option_type,
+    types::{Channel, ChannelHttpHeaders, ChannelStreamOption, EPGProgram, PlaylistExport},
+};
+
+const XMLTV_TIME_FORMAT: &str = "%Y%m%d%H%M%S +0000";
+
+/// Writes the channels of `export` to `m3u_path`, and their guide to `xmltv_path` when given.
+/// Returns how many channels were exported.
+pub fn export_playlist(
+    export: PlaylistExport,
+    m3u_path: String,
+    xmltv_path: Option<String>,
+) -> Result<usize> {
+    for source_id in &export.source_ids {
+        let source = sql::get_source_from_id(*source_id)?;
+        check_exportable(&source.name, source.source_type)?;
+    }
+    let channels = sql::get_export_channels(&export)?;
+    if channels.is_empty() {
+        anyhow::bail!("No channels to export");
+    }
+    let xmltv_ids: Vec<String> = channels.iter().map(get_xmltv_id).collect();
+    let mut user_agents: HashMap<i64, Option<String>> = HashMap::new();
+    let mut m3u = BufWriter::new(File::create(&m3u_path)?);
+    writeln!(m3u, "#EXTM3U")?;
+    let mut count = 0;
+    for (channel, xmltv_id) in channels.iter().zip(&xmltv_ids) {
+        let channel_id = channel.id.context("no channel id")?;
+        let source_id = channel.source_id.context("no source id")?;
+        let user_agent = match user_agents.get(&source_id) {
+            Some(user_agent) => user_agent.clone(),
+            None => {
+                let user_agent = sql::get_source_from_id(source_id)?.stream_user_agent;
+                user_agents.insert(source_id, user_agent.clone());
+                user_agent
+            }
+        };
+        let headers = sql::get_channel_headers_by_id(channel_id)?;
+        let options = sql::get_channel_options_by_id(channel_id)?;
+        let written = write_m3u_entry(
+            &mut m3u,
+            channel,
+            xmltv_id,
+            headers.as_ref(),
+            &options,
+            user_agent.as_deref(),
+        )?;
+        count += written as usize;
+    }
+    m3u.flush()?;
+    if let Some(xmltv_path) = xmltv_path {
+        write_xmltv(&channels, &xmltv_ids, xmltv_path)?;
+    }
+    Ok(count)
+}
+
+/// Stalker portal urls are commands that only the portal can turn into links, at play time
+fn check_exportable(source_name: &str, source_type: u8) -> Result<()> {
+    if source_type == source_type::STALKER {
+        anyhow::bail!("{source_name} is a Stalker portal, its channels can't be exported");
+    }
+    Ok(())
+}
+
+/// The channel's own tvg-id, so guides the player already has keep working, or one made up
+fn get_xmltv_id(channel: &Channel) -> String {
+    match channel
+        .tvg_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+    {
+        Some(tvg_id) => tvg_id.to_string(),
+        None => format!("{}.beatstv", channel.id.unwrap_or_default()),
+    }
+}
+
+fn write_m3u_entry(
+    out: &mut impl Write,
+    channel: &Channel,
+    xmltv_id: &str,
+    headers: Option<&ChannelHttpHeaders>,
+    options: &[ChannelStreamOption],
+    source_user_agent: Option<&str>,
+) -> Result<bool> {
+    let Some(url) = channel.url.as_deref() else {
+        return Ok(false);
+    };
+    let mut attributes = vec![
+        ("tvg-id", Some(xmltv_id.to_string())),
+        ("tvg-name", Some(channel.name.clone())),
+        ("tvg-logo", channel.image.clone()),
+        ("tvg-chno", channel.tvg_chno.map(|chno| chno.to_string())),
+        ("group-title", channel.group.clone()),
+    ];
+    // Xtream archives replay through the panel's timeshift urls
+    let catchup = match (&channel.catchup, channel.tv_archive) {
+        (Some(catchup), _) => Some(catchup.clone()),
+        (None, Some(true)) => Some("xc".to_string()),
+        (None, _) => None,
+    };
+    if catchup.is_some() {
+        attributes.push(("catchup", catchup));
+        attributes.push(("catchup-source", channel.catchup_source.clone()));
+        attributes.push(("catchup-days", channel.catchup_days.map(|d| d.to_string())));
+    }
+    write!(out, "#EXTINF:-1")?;
+    for (name, value) in attributes {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            write!(
+                out,
+                " {}=\"{}\"",
+                name,
+                clean_m3u_value(&value).replace('"', "'")
+            )?;
+        }
+    }
+    writeln!(out, ",{}", clean_m3u_value(&channel.name))?;
+    let user_agent = headers
+        .and_then(|h| h.user_agent.as_deref())
+        .or(source_user_agent);
+    let header_options = [
+        ("http-user-agent", user_agent),
+        ("http-referrer", headers.and_then(|h| h.referrer.as_deref())),
+        (
+            "http-origin",
+            headers.and_then(|h| h.http_origin.as_deref()),
+        ),
+    ];
+    for (key, value) in header_options {
+        if let Some(value) = value.filter(|v| !v.trim().is_empty()) {
+            writeln!(out, "#EXTVLCOPT:{}={}", key, clean_m3u_value(value))?;
+        }
+    }
+    for option in options {
+        let directive = match option.option_type {
+            stream_option_type::KODI => "#KODIPROP",
+            _ => "#EXTVLCOPT",
+        };
+        writeln!(
+            out,
+            "{}:{}={}",
+            directive,
+            clean_m3u_value(&option.key),
+            clean_m3u_value(&option.value)
+        )?;
+    }
+    writeln!(out, "{}", clean_m3u_value(url))?;
+    Ok(true)
+}
+
+/// A line break inside a value would start a new M3U directive
+fn clean_m3u_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ").trim().to_string()
+}
+
+/// Channels sharing a tvg-id share one guide, the first one with the id provides it
+fn write_xmltv(channels: &[Channel], xmltv_ids: &[String], path: String) -> Result<()> {
+    let mut out = BufWriter::new(File::create(&path)?);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<!DOCTYPE tv SYSTEM "xmltv.dtd">"#)?;
+    writeln!(out, r#"<tv generator-info-name="Beats TV">"#)?;
+    let mut written: HashSet<&str> = HashSet::new();
+    let guide_channels: Vec<(&Channel, &String)> = channels
+        .iter()
+        .zip(xmltv_ids)
+        .filter(|(_, xmltv_id)| written.insert(xmltv_id.as_str()))
+        .collect();
+    // Every channel comes before the first programme, as the format requires
+    for (channel, xmltv_id) in &guide_channels {
+        write_xmltv_channel(&mut out, channel, xmltv_id)?;
+    }
+    for (channel, xmltv_id) in guide_channels {
+        let channel_id = channel.id.context("no channel id")?;
+        // XMLTV listings win over cached Xtream ones, so programmes of a channel don't overlap
+        for program in sql::get_epg_programs(channel_id, 0)? {
+            write_xmltv_programme(&mut out, &program, xmltv_id)?;
+        }
+    }
+    writeln!(out, "</tv>")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_xmltv_channel(out: &mut impl Write, channel: &Channel, xmltv_id: &str) -> Result<()> {
+    writeln!(out, r#"  <channel id="{}">"#, escape(xmltv_id))?;
+    writeln!(
+        out,
+        "    <display-name>{}</display-name>",
+        escape(&channel.name)
+    )?;
+    if let Some(image) = channel.image.as_deref().filter(|i| !i.is_empty()) {
+        writeln!(out, r#"    <icon src="{}"/>"#, escape(image))?;
+    }
+    writeln!(out, "  </channel>")?;
+    Ok(())
+}
+
+fn write_xmltv_programme(out: &mut impl Write, program: &EPGProgram, xmltv_id: &str) -> Result<()> {
+    writeln!(
+        out,
+        r#"  <programme start="{}" stop="{}" channel="{}">"#,
+        format_xmltv_time(program.start)?,
+        format_xmltv_time(program.stop)?,
+        escape(xmltv_id)
+    )?;
+    writeln!(out, "    <title>{}</title>", escape(&program.title))?;
+    let elements = [
+        ("sub-title", &program.subtitle),
+        ("desc", &program.description),
+        ("category", &program.category),
+    ];
+    for (name, value) in elements {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            writeln!(out, "    <{name}>{}</{name}>", escape(value))?;
+        }
+    }
+    // Imported numbers were made readable, so they are no longer in xmltv_ns form
+    if let Some(episode_num) = program.episode_num.as_deref() {
+        writeln!(
+            out,
+            r#"    <episode-num system="onscreen">{}</episode-num>"#,
+            escape(episode_num)
+        )?;
+    }
+    if let Some(icon) = program.icon.as_deref() {
+        writeln!(out, r#"    <icon src="{}"/>"#, escape(icon))?;
+    }
+    writeln!(out, "  </programme>")?;
+    Ok(())
+}
+
+fn format_xmltv_time(timestamp: i64) -> Result<String> {
+    Ok(DateTime::from_timestamp(timestamp, 0)
+        .context("invalid programme time")?
+        .format(XMLTV_TIME_FORMAT)
+        .to_string())
+}
+
+#[cfg(test)]
+mod test_export {
+    use super::*;
+
+    fn channel() -> Channel {
+        Channel {
+            id: Some(7),
+            name: "News \"24\"".to_string(),
+            url: Some("http://xc.tv/live/user/pass/42.ts".to_string()),
+            group: Some("News".to_string()),
+            image: Some("http://xc.tv/news.png".to_string()),
+            media_type: 0,
+            source_id: Some(1),
+            series_id: None,
+            group_id: Some(3),
+            favorite: false,
+            stream_id: Some(42),
+            tv_archive: Some(true),
+            season_id: None,
+            episode_num: None,
+            hidden: None,
+            rating: None,
+            genre: None,
+            release_date: None,
+            plot: None,
+            cast: None,
+            director: None,
+            tvg_id: None,
+            tvg_chno: Some(101),
+            tvg_shift: None,
+            catchup: None,
+            catchup_source: None,
+            catchup_days: Some(3),
+            tvg_rec: None,
+            now_program: None,
+            next_program: None,
+        }
+    }
+
+    #[test]
+    fn test_write_m3u_entry() {
+        let channel = channel();
+        let headers = ChannelHttpHeaders {
+            referrer: Some("http://ref.tv/".to_string()),
+            ..Default::default()
+        };
+        let options = vec![ChannelStreamOption {
+            id: None,
+            channel_id: None,
+            option_type: stream_option_type::KODI,
+            key: "inputstream".to_string(),
+            value: "inputstream.adaptive".to_string(),
+        }];
+        let mut out = Vec::new();
+        let xmltv_id = get_xmltv_id(&channel);
+        let written = write_m3u_entry(
+            &mut out,
+            &channel,
+            &xmltv_id,
+            Some(&headers),
+            &options,
+            Some("Agent/1.0"),
+        )
+        .unwrap();
+        assert!(written);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "#EXTINF:-1 tvg-id=\"7.beatstv\" tvg-name=\"News '24'\" tvg-logo=\"http://xc.tv/news.png\" ",
+                "tvg-chno=\"101\" group-title=\"News\" catchup=\"xc\" catchup-days=\"3\",News \"24\"\n",
+                "#EXTVLCOPT:http-user-agent=Agent/1.0\n",
+                "#EXTVLCOPT:http-referrer=http://ref.tv/\n",
+                "#KODIPROP:inputstream=inputstream.adaptive\n",
+                "http://xc.tv/live/user/pass/42.ts\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_m3u_entry_without_url() {
+        let channel = Channel {
+            url: None,
+            ..channel()
+        };
+        let mut out = Vec::new();
+        let written = write_m3u_entry(&mut out, &channel, "7.beatstv", None, &[], None).unwrap();
+        assert!(!written);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_check_exportable() {
+        assert!(check_exportable("Provider", source_type::XTREAM).is_ok());
+        assert!(check_exportable("Playlist", source_type::M3U_LINK).is_ok());
+        let error = check_exportable("Portal", source_type::STALKER).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Portal is a Stalker portal, its channels can't be exported"
+        );
+    }
+
+    #[test]
+    fn test_write_xmltv_programme() {
+        let program = EPGProgram {
+            start: 1706733000,
+            stop: 1706736600,
+            title: "News & Weather".to_string(),
+            episode_num: Some("S01E05".to_string()),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_xmltv_programme(&mut out, &program, "news.uk").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "  <programme start=\"20240131203000 +0000\" stop=\"20240131213000 +0000\" channel=\"news.uk\">\n",
+                "    <title>News &amp; Weather</title>\n",
+                "    <episode-num system=\"onscreen\">S01E05</episode-num>\n",
+                "  </programme>\n"
+            )
+        );
+    }
+}
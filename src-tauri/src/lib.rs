@@ -26,8 +26,9 @@ use anyhow::Context;
 use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::Mutex;
 use types::{
-    AppState, Channel, CustomChannel, CustomChannelExtraData, EPG, EPGNotify, Filters, Group,
-    IdName, NetworkInfo, Settings, Source,
+    AppState, ArchiveDay, Channel, CustomChannel, CustomChannelExtraData, EPG, EPGChannelMatch,
+    EPGGridChannel, EPGNotify, EPGSearchResult, Filters, Group, IdName, NetworkInfo,
+    PlaylistExport, Recording, RecordingRule, RefreshReport, Settings, Source,
 };
 #[cfg(any(target_os = "macos", target_os = "windows"))]
 use {
@@ -38,26 +39,39 @@ use {
     },
 };
 
+pub mod ai_parser;
 pub mod bulk_action_type;
+pub mod catchup;
 pub mod deps;
 pub mod epg;
+pub mod epg_matcher;
+pub mod epg_origin;
+pub mod export;
 pub mod log;
 pub mod m3u;
 pub mod media_type;
 pub mod mpv;
 pub mod omdb;
+pub mod recording;
+pub mod recording_rules;
+pub mod recording_status;
 pub mod restream;
+pub mod scheduler;
 pub mod security;
+pub mod series_prefetch_type;
 pub mod settings;
 pub mod share;
 pub mod sort_type;
 pub mod source_type;
 pub mod sql;
+pub mod stalker;
+pub mod stream_option_type;
 pub mod tags;
 pub mod tmdb;
 pub mod types;
 pub mod utils;
 pub mod view_type;
+pub mod xmltv;
 pub mod xtream;
 
 #[cfg(any(target_os = "macos", target_os = "windows"))]
@@ -92,7 +106,24 @@ pub fn run() {
             search,
             bulk_update,
             get_xtream,
+            get_stalker,
             refresh_source,
+            get_refresh_reports,
+            refresh_epg,
+            get_epg_grid,
+            get_xtream_archive,
+            search_epg,
+            get_epg_channel_matches,
+            set_epg_channel_mapping,
+            remove_epg_channel_mapping,
+            schedule_recording,
+            schedule_epg_recording,
+            cancel_recording,
+            get_recordings,
+            add_recording_rule,
+            get_recording_rules,
+            set_recording_rule_enabled,
+            delete_recording_rule,
             get_episodes,
             favorite_channel,
             unfavorite_channel,
@@ -117,6 +148,7 @@ pub fn run() {
             group_exists,
             share_custom_group,
             share_custom_source,
+            export_playlist,
             import,
             channel_exists,
             update_source,
@@ -158,6 +190,8 @@ pub fn run() {
             app.manage(Mutex::new(AppState {
                 ..Default::default()
             }));
+            tauri::async_runtime::spawn(epg::prefetch_favorites_epg());
+            tauri::async_runtime::spawn(scheduler::run(app.handle().clone()));
             #[cfg(any(target_os = "macos", target_os = "windows"))]
             if *ENABLE_TRAY_ICON {
                 let _ = build_tray_icon(app);
@@ -242,12 +276,12 @@ fn map_err_frontend(e: Error) -> String {
 
 #[tauri::command(async)]
 fn get_m3u8(source: Source) -> Result<(), String> {
-    m3u::read_m3u8(source, false).map_err(map_err_frontend)
+    m3u::read_m3u8(source, false, &mut RefreshReport::default()).map_err(map_err_frontend)
 }
 
 #[tauri::command]
 async fn get_m3u8_from_link(source: Source) -> Result<(), String> {
-    m3u::get_m3u8_from_link(source, false)
+    m3u::get_m3u8_from_link(source, false, &mut RefreshReport::default())
         .await
         .map_err(map_err_frontend)
 }
@@ -288,13 +322,20 @@ fn bulk_update(filters: Filters, action: u8) -> Result<(), String> {
 
 #[tauri::command]
 async fn get_xtream(app: AppHandle, source: Source) -> Result<(), String> {
-    xtream::get_xtream(&app, source, false)
+    xtream::get_xtream(&app, source, false, &mut RefreshReport::default())
         .await
         .map_err(map_err_frontend)
 }
 
 #[tauri::command]
-async fn refresh_source(app: AppHandle, source: Source) -> Result<(), String> {
+async fn get_stalker(app: AppHandle, source: Source) -> Result<(), String> {
+    stalker::get_stalker(&app, source, false, &mut RefreshReport::default())
+        .await
+        .map_err(map_err_frontend)
+}
+
+#[tauri::command]
+async fn refresh_source(app: AppHandle, source: Source) -> Result<RefreshReport, String> {
     let _ = app.emit("refresh-progress", serde_json::json!({
         "playlist": source.name,
         "activity": "Starting refresh...",
@@ -309,6 +350,93 @@ async fn refresh_source(app: AppHandle, source: Source) -> Result<(), String> {
     result
 }
 
+#[tauri::command(async)]
+fn get_refresh_reports(source_id: i64) -> Result<Vec<RefreshReport>, String> {
+    sql::get_refresh_reports(source_id).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn get_epg_grid(channel_ids: Vec<i64>, start: i64, end: i64) -> Result<Vec<EPGGridChannel>, String> {
+    epg::get_epg_grid(channel_ids, start, end).map_err(map_err_frontend)
+}
+
+#[tauri::command]
+async fn get_xtream_archive(channel: Channel, days: Option<i64>) -> Result<Vec<ArchiveDay>, String> {
+    epg::get_xtream_archive(channel, days)
+        .await
+        .map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn search_epg(query: String) -> Result<Vec<EPGSearchResult>, String> {
+    epg::search_epg(query).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn get_epg_channel_matches(source_id: i64) -> Result<Vec<EPGChannelMatch>, String> {
+    epg_matcher::get_epg_channel_matches(source_id).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn set_epg_channel_mapping(channel_id: i64, xmltv_id: Option<String>) -> Result<(), String> {
+    epg_matcher::set_epg_channel_mapping(channel_id, xmltv_id).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn remove_epg_channel_mapping(channel_id: i64) -> Result<(), String> {
+    epg_matcher::remove_epg_channel_mapping(channel_id).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn schedule_recording(recording: Recording) -> Result<Recording, String> {
+    recording::schedule_recording(recording).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn schedule_epg_recording(channel_id: i64, epg: EPG) -> Result<Recording, String> {
+    recording::schedule_epg_recording(channel_id, epg).map_err(map_err_frontend)
+}
+
+#[tauri::command]
+async fn cancel_recording(id: i64, app: AppHandle) -> Result<(), String> {
+    recording::cancel_recording(id, app)
+        .await
+        .map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn get_recordings() -> Result<Vec<Recording>, String> {
+    sql::get_recordings().map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn add_recording_rule(rule: RecordingRule) -> Result<RecordingRule, String> {
+    recording_rules::add_recording_rule(rule).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn get_recording_rules() -> Result<Vec<RecordingRule>, String> {
+    sql::get_recording_rules().map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn set_recording_rule_enabled(id: i64, enabled: bool) -> Result<(), String> {
+    recording_rules::set_recording_rule_enabled(id, enabled).map_err(map_err_frontend)
+}
+
+#[tauri::command(async)]
+fn delete_recording_rule(id: i64) -> Result<(), String> {
+    recording_rules::delete_recording_rule(id).map_err(map_err_frontend)
+}
+
+#[tauri::command]
+async fn refresh_epg(source_id: i64) -> Result<usize, String> {
+    let source = sql::get_source_from_id(source_id).map_err(map_err_frontend)?;
+    xmltv::import_source_epg(&source)
+        .await
+        .map_err(map_err_frontend)
+}
+
 #[tauri::command]
 async fn refresh_all(app: AppHandle) -> Result<(), String> {
     utils::refresh_all(&app).await.map_err(map_err_frontend)
@@ -323,9 +451,17 @@ async fn get_xtream_source_details(source: Source) -> Result<xtream::XtreamPanel
 
 #[tauri::command]
 async fn get_episodes(channel: Channel) -> Result<(), String> {
-    xtream::get_episodes(channel)
-        .await
-        .map_err(map_err_frontend)
+    let kind = channel
+        .source_id
+        .map(sql::get_source_from_id)
+        .transpose()
+        .map_err(map_err_frontend)?
+        .map(|source| source.source_type);
+    match kind {
+        Some(source_type::STALKER) => stalker::get_episodes(channel).await,
+        _ => xtream::get_episodes(channel).await,
+    }
+    .map_err(map_err_frontend)
 }
 
 #[tauri::command(async)]
@@ -476,6 +612,15 @@ fn share_custom_source(source: Source, path: String) -> Result<(), String> {
     share::share_custom_source(source, path).map_err(map_err_frontend)
 }
 
+#[tauri::command(async)]
+fn export_playlist(
+    export: PlaylistExport,
+    m3u_path: String,
+    xmltv_path: Option<String>,
+) -> Result<usize, String> {
+    export::export_playlist(export, m3u_path, xmltv_path).map_err(map_err_frontend)
+}
+
 #[tauri::command(async)]
 fn import(
     path: String,
@@ -492,12 +637,15 @@ fn channel_exists(name: String, url: String, source_id: i64) -> Result<bool, Str
 
 #[tauri::command(async)]
 fn update_source(source: Source) -> Result<(), String> {
-    sql::update_source(source).map_err(map_err_frontend)
+    sql::update_source(source).map_err(map_err_frontend)?;
+    // Reminders follow the programmes a new EPG offset moved
+    scheduler::wake();
+    Ok(())
 }
 
 #[tauri::command]
 async fn get_epg(channel: Channel) -> Result<Vec<EPG>, String> {
-    xtream::get_epg(channel).await.map_err(map_err_frontend)
+    epg::get_epg(channel).await.map_err(map_err_frontend)
 }
 
 #[tauri::command]
@@ -524,26 +672,14 @@ async fn abort_download(
         .map_err(map_err_frontend)
 }
 
-#[tauri::command]
-async fn add_epg(
-    state: State<'_, Mutex<AppState>>,
-    app: AppHandle,
-    epg: EPGNotify,
-) -> Result<(), String> {
-    epg::add_epg(state, app, epg)
-        .await
-        .map_err(map_err_frontend)
+#[tauri::command(async)]
+fn add_epg(epg: EPGNotify) -> Result<(), String> {
+    epg::add_epg(epg).map_err(map_err_frontend)
 }
 
 #[tauri::command(async)]
-async fn remove_epg(
-    state: State<'_, Mutex<AppState>>,
-    app: AppHandle,
-    epg_id: String,
-) -> Result<(), String> {
-    epg::remove_epg(state, app, epg_id)
-        .await
-        .map_err(map_err_frontend)
+fn remove_epg(epg_id: String) -> Result<(), String> {
+    epg::remove_epg(epg_id).map_err(map_err_frontend)
 }
 
 #[tauri::command(async)]
@@ -552,13 +688,8 @@ fn get_epg_ids() -> Result<Vec<String>, String> {
 }
 
 #[tauri::command]
-async fn on_start_check_epg(
-    state: State<'_, Mutex<AppState>>,
-    app: AppHandle,
-) -> Result<(), String> {
-    epg::on_start_check_epg(state, app)
-        .await
-        .map_err(map_err_frontend)
+fn on_start_check_epg() {
+    scheduler::wake();
 }
 
 #[tauri::command]
@@ -20,24 +20,26 @@
  */
 
 use std::io::Write;
+use std::path::Path;
 use std::sync::LazyLock;
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader},
-};
+use std::{collections::HashMap, io::BufRead};
 
 use anyhow::{Context, Result};
 use regex::{Captures, Regex};
+use reqwest::StatusCode;
 use rusqlite::Transaction;
-use types::{Channel, Source};
+use types::{Channel, Season, Source};
 
-use crate::types::ChannelPreserve;
 use crate::{
-    log, media_type, source_type,
+    log, media_type,
     sql::{self, set_channel_group_id},
-    types::{self, ChannelHttpHeaders},
-    utils::get_user_agent_from_source,
+    stream_option_type,
+    types::{self, ChannelHttpHeaders, ChannelStreamOption, RefreshReport},
+    utils::{
+        add_refresh_skipped_line, add_validator_headers, check_refresh_drop, get_refresh_drop_threshold,
+        get_response_validators, get_tmp_path, get_unique_key, get_user_agent_from_source,
+        open_compressed_file, set_refresh_report_counts, TempFile,
+    },
 };
 
 static NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| {
@@ -55,6 +57,34 @@ static LOGO_REGEX: LazyLock<Regex> = LazyLock::new(|| {
 static GROUP_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"group-title="(?P<group>[^"]*)""#).expect("Failed to compile GROUP_REGEX - static pattern")
 });
+static CHNO_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"tvg-chno="(?P<chno>[^"]*)""#).expect("Failed to compile CHNO_REGEX - static pattern")
+});
+static SHIFT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"tvg-shift="(?P<shift>[^"]*)""#).expect("Failed to compile SHIFT_REGEX - static pattern")
+});
+static REC_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"tvg-rec="(?P<rec>[^"]*)""#).expect("Failed to compile REC_REGEX - static pattern")
+});
+static CATCHUP_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\scatchup="(?P<catchup>[^"]*)""#).expect("Failed to compile CATCHUP_REGEX - static pattern")
+});
+static CATCHUP_SOURCE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"catchup-source="(?P<source>[^"]*)""#).expect("Failed to compile CATCHUP_SOURCE_REGEX - static pattern")
+});
+static CATCHUP_DAYS_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"catchup-days="(?P<days>[^"]*)""#).expect("Failed to compile CATCHUP_DAYS_REGEX - static pattern")
+});
+
+static EPISODE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)^(?P<name>.*?)\bS(?P<season>\d{1,3})[\s._-]?E(?P<episode>\d{1,4})\b"#).expect("Failed to compile EPISODE_REGEX - static pattern")
+});
+static EPISODE_REGEX_ALT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)^(?P<name>.*?)\b(?P<season>\d{1,2})x(?P<episode>\d{2,3})\b"#).expect("Failed to compile EPISODE_REGEX_ALT - static pattern")
+});
+static EPG_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?:url-tvg|x-tvg-url)="(?P<url>[^"]*)""#).expect("Failed to compile EPG_URL_REGEX - static pattern")
+});
 
 static HTTP_ORIGIN_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"http-origin=(?P<origin>.+)"#).expect("Failed to compile HTTP_ORIGIN_REGEX - static pattern")
@@ -66,49 +96,90 @@ static HTTP_USER_AGENT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"http-user-agent=(?P<user_agent>.+)"#).expect("Failed to compile HTTP_USER_AGENT_REGEX - static pattern")
 });
 
+#[derive(Default)]
+struct M3UEntryExtras {
+    headers: Option<ChannelHttpHeaders>,
+    headers_set: bool,
+    options: Vec<ChannelStreamOption>,
+    group: Option<String>,
+}
+
+const HTTP_CACHE_RESOURCE: &str = "playlist";
+
+/// Ids already inserted during this refresh, by name, and the refresh keys handed out so far
+#[derive(Default)]
+struct M3URefreshState {
+    last_seen: i64,
+    groups: HashMap<String, i64>,
+    series: HashMap<String, u64>,
+    seasons: HashMap<(u64, i64), i64>,
+    keys: HashMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq)]
+struct EpisodeInfo {
+    series_name: String,
+    season: i64,
+    episode: i64,
+}
+
 struct M3UProcessing {
     channel_line: Option<String>,
-    channel_headers: Option<ChannelHttpHeaders>,
-    channel_headers_set: bool,
+    channel_line_number: usize,
+    extras: M3UEntryExtras,
     last_non_empty_line: Option<String>,
-    groups: HashMap<String, i64>,
+    state: M3URefreshState,
     source_id: i64,
     use_tvg_id: Option<bool>,
     line_count: usize,
 }
 
-pub fn read_m3u8(mut source: Source, wipe: bool) -> Result<()> {
-    let path = match source.source_type {
-        source_type::M3U_LINK => get_tmp_path(),
-        _ => source.url.clone().context("no file path found")?,
-    };
-    let file = File::open(path).context("Failed to open m3u8 file")?;
-    let reader = BufReader::new(file);
+pub fn read_m3u8(source: Source, refresh: bool, report: &mut RefreshReport) -> Result<()> {
+    let path = source.url.clone().context("no file path found")?;
+    read_m3u8_from_path(source, refresh, Path::new(&path), report)
+}
+
+fn read_m3u8_from_path(
+    source: Source,
+    refresh: bool,
+    path: &Path,
+    report: &mut RefreshReport,
+) -> Result<()> {
+    let reader = open_compressed_file(path).context("Failed to open m3u8 file")?;
+    process_m3u8(source, refresh, reader, report)
+}
+
+fn process_m3u8(
+    mut source: Source,
+    refresh: bool,
+    reader: impl BufRead,
+    report: &mut RefreshReport,
+) -> Result<()> {
     let mut lines = reader.lines().enumerate();
     let mut sql = sql::get_conn()?;
     
-    // Enable optimizations for bulk insert
+    // Enable optimizations for bulk insert, but keep WAL: leaving it needs exclusive access
+    // and refreshes now run while other pooled connections are open
     sql.execute_batch(
         "PRAGMA synchronous = OFF;
-         PRAGMA journal_mode = MEMORY;
          PRAGMA temp_store = MEMORY;
          PRAGMA cache_size = 10000;"
     )?;
     
-    let mut channel_preserve: Vec<ChannelPreserve> = Vec::new();
     let tx = sql.transaction()?;
-    if wipe {
-        channel_preserve =
-            sql::get_preserve(&tx, source.id.context("no source id")?).unwrap_or_default();
-        sql::wipe(&tx, source.id.context("no source id")?)?;
-    } else {
+    if !refresh {
         source.id = Some(sql::create_or_find_source_by_name(&tx, &source)?);
     }
+    let previous_counts = sql::get_refresh_counts(&tx, source.id.context("no source id")?, None)?;
+    let max_id = sql::get_max_channel_id(&tx)?;
     let mut processing = M3UProcessing {
-        channel_headers: None,
-        channel_headers_set: false,
+        extras: M3UEntryExtras::default(),
         channel_line: None,
-        groups: HashMap::new(),
+        channel_line_number: 0,
+        state: M3URefreshState {
+            last_seen: chrono::Utc::now().timestamp_millis(),
+            ..Default::default()
+        },
         last_non_empty_line: None,
         source_id: source.id.context("no source id")?,
         use_tvg_id: source.use_tvg_id,
@@ -120,34 +191,67 @@ pub fn read_m3u8(mut source: Source, wipe: bool) -> Result<()> {
             Ok(r) => r,
             Err(e) => {
                 log::log(format!("{:?}", e));
+                add_refresh_skipped_line(report, Some(c1 + 1), format!("{:#}", e));
                 continue;
             }
         };
         let l1_upper = l1.to_uppercase();
-        if l1_upper.starts_with("#EXTINF") {
-            try_commit_channel(&mut processing, &tx);
+        if l1_upper.starts_with("#EXTM3U") {
+            let epg_urls = get_epg_urls(&l1);
+            if !epg_urls.is_empty() {
+                sql::set_source_epg_url(&tx, processing.source_id, &epg_urls.join(","))?;
+            }
+        } else if l1_upper.starts_with("#EXTINF") {
+            try_commit_channel(&mut processing, &tx, report);
             processing.channel_line = Some(l1);
-            processing.channel_headers_set = false;
+            processing.channel_line_number = c1 + 1;
+            processing.extras.headers_set = false;
         } else if l1_upper.starts_with("#EXTVLCOPT") {
-            if processing.channel_headers.is_none() {
-                processing.channel_headers = Some(ChannelHttpHeaders {
+            let extras = &mut processing.extras;
+            if extras.headers.is_none() {
+                extras.headers = Some(ChannelHttpHeaders {
                     ..Default::default()
                 });
             }
-            if set_http_headers(
-                &l1,
-                processing.channel_headers.as_mut().context("no headers")?,
-            ) {
-                processing.channel_headers_set = true;
+            if set_http_headers(&l1, extras.headers.as_mut().context("no headers")?) {
+                extras.headers_set = true;
+            } else if let Some(option) = get_stream_option(&l1, stream_option_type::VLC) {
+                extras.options.push(option);
             }
+        } else if l1_upper.starts_with("#KODIPROP") {
+            if let Some(option) = get_stream_option(&l1, stream_option_type::KODI) {
+                processing.extras.options.push(option);
+            }
+        } else if l1_upper.starts_with("#EXTGRP") {
+            processing.extras.group = l1
+                .split_once(':')
+                .map(|(_, group)| group.trim().to_string())
+                .filter(|group| !group.is_empty());
         } else if !l1.trim().is_empty() {
             processing.last_non_empty_line = Some(l1);
         }
     }
-    try_commit_channel(&mut processing, &tx);
-    if wipe {
-        sql::restore_preserve(&tx, source.id.context("no source id")?, channel_preserve)?;
+    try_commit_channel(&mut processing, &tx, report);
+    let media_types = [media_type::LIVESTREAM, media_type::MOVIE, media_type::SERIE];
+    let counts =
+        sql::get_refresh_counts(&tx, processing.source_id, Some(processing.state.last_seen))?;
+    if let Err(e) =
+        check_refresh_drop(&previous_counts, &counts, &media_types, get_refresh_drop_threshold())
+    {
+        tx.rollback()?;
+        return Err(e);
     }
+    let changes =
+        sql::get_refresh_changes(&tx, processing.source_id, processing.state.last_seen, max_id)?;
+    set_refresh_report_counts(report, &previous_counts, &changes, &media_types);
+    sql::delete_stale_channels(
+        &tx,
+        processing.source_id,
+        &media_types,
+        processing.state.last_seen,
+        true,
+    )?;
+    sql::delete_refresh_orphans(&tx, processing.source_id)?;
     sql::analyze(&tx)?;
     tx.commit()?;
     
@@ -157,16 +261,17 @@ pub fn read_m3u8(mut source: Source, wipe: bool) -> Result<()> {
     Ok(())
 }
 
-fn try_commit_channel(processing: &mut M3UProcessing, tx: &Transaction) {
+fn try_commit_channel(processing: &mut M3UProcessing, tx: &Transaction, report: &mut RefreshReport) {
     if let Some(channel) = processing.channel_line.take() {
-        if !processing.channel_headers_set {
-            processing.channel_headers = None;
+        let mut extras = std::mem::take(&mut processing.extras);
+        if !extras.headers_set {
+            extras.headers = None;
         }
         commit_channel(
             channel,
             processing.last_non_empty_line.take(),
-            &mut processing.groups,
-            processing.channel_headers.take(),
+            &mut processing.state,
+            extras,
             processing.source_id,
             processing.use_tvg_id,
             &tx,
@@ -179,6 +284,8 @@ fn try_commit_channel(processing: &mut M3UProcessing, tx: &Transaction) {
         })
         .unwrap_or_else(|e| {
             log::log(format!("{:?}", e));
+            let reason = e.root_cause().to_string();
+            add_refresh_skipped_line(report, Some(processing.channel_line_number), reason);
         });
     }
 }
@@ -186,8 +293,8 @@ fn try_commit_channel(processing: &mut M3UProcessing, tx: &Transaction) {
 fn commit_channel(
     channel_line: String,
     last_line: Option<String>,
-    groups: &mut HashMap<String, i64>,
-    headers: Option<ChannelHttpHeaders>,
+    state: &mut M3URefreshState,
+    extras: M3UEntryExtras,
     source_id: i64,
     use_tvg_id: Option<bool>,
     tx: &Transaction,
@@ -198,25 +305,171 @@ fn commit_channel(
         source_id,
         use_tvg_id,
     )?;
-    set_channel_group_id(groups, &mut channel, tx, &source_id).unwrap_or_else(|e| {
-        log::log(format!(
-            "Failed to set group id for channel: {}, Error: {:?}",
-            channel.name, e
-        ))
-    });
-    sql::insert_channel(tx, channel)?;
-    if let Some(mut headers) = headers {
-        headers.channel_id = Some(tx.last_insert_rowid());
-        sql::insert_channel_headers(tx, headers)?;
+    if channel.group.is_none() {
+        channel.group = extras.group;
+    }
+    match get_episode_info(&channel.name) {
+        Some(episode) => set_episode_ids(&mut channel, episode, state, tx, source_id)?,
+        None => set_channel_group_id(&mut state.groups, &mut channel, tx, &source_id)
+            .unwrap_or_else(|e| {
+                log::log(format!(
+                    "Failed to set group id for channel: {}, Error: {:?}",
+                    channel.name, e
+                ))
+            }),
     }
+    let key = get_unique_key(&mut state.keys, get_stable_key(&channel)?);
+    let channel_id = sql::upsert_channel(tx, channel, &key, state.last_seen)?;
+    sql::set_channel_headers(tx, channel_id, extras.headers)?;
+    sql::set_channel_options(tx, channel_id, &extras.options)?;
     Ok(())
 }
 
-pub async fn get_m3u8_from_link(source: Source, wipe: bool) -> Result<()> {
+/// HD, SD and backup variants often share a tvg-id, so the name is part of the key.
+/// Only true duplicates are told apart by their order in the playlist.
+fn get_stable_key(channel: &Channel) -> Result<String> {
+    match (channel.tvg_id.as_ref(), channel.url.as_ref()) {
+        (Some(id), _) => Ok(format!("tvg:{id}:{}", channel.name)),
+        (None, Some(url)) => Ok(format!("url:{url}")),
+        (None, None) => anyhow::bail!("no tvg-id or url"),
+    }
+}
+
+/// Turns the channel into an episode, creating its series and season rows on first sight.
+/// Layout matches Xtream: the series row keeps its id in `url`, episodes are grouped by season.
+fn set_episode_ids(
+    channel: &mut Channel,
+    episode: EpisodeInfo,
+    state: &mut M3URefreshState,
+    tx: &Transaction,
+    source_id: i64,
+) -> Result<()> {
+    let series_key = episode.series_name.to_lowercase();
+    let series_id = match state.series.get(&series_key) {
+        Some(id) => *id,
+        None => {
+            let series_id = get_series_id(&series_key);
+            let mut series = Channel {
+                id: None,
+                name: episode.series_name,
+                group: channel.group.clone(),
+                image: channel.image.clone(),
+                url: Some(series_id.to_string()),
+                media_type: media_type::SERIE,
+                source_id: Some(source_id),
+                series_id: None,
+                group_id: None,
+                favorite: false,
+                stream_id: None,
+                tv_archive: None,
+                season_id: None,
+                episode_num: None,
+                hidden: Some(false),
+                rating: None,
+                genre: None,
+                release_date: None,
+                plot: None,
+                cast: None,
+                director: None,
+                tvg_id: None,
+                tvg_chno: None,
+                tvg_shift: None,
+                catchup: None,
+                catchup_source: None,
+                catchup_days: None,
+                tvg_rec: None,
+                now_program: None,
+                next_program: None,
+            };
+            set_channel_group_id(&mut state.groups, &mut series, tx, &source_id).unwrap_or_else(
+                |e| {
+                    log::log(format!(
+                        "Failed to set group id for series: {}, Error: {:?}",
+                        series.name, e
+                    ))
+                },
+            );
+            let key = format!("series:{series_id}");
+            sql::upsert_channel(tx, series, &key, state.last_seen)?;
+            state.series.insert(series_key, series_id);
+            series_id
+        }
+    };
+    let season_id = match state.seasons.get(&(series_id, episode.season)) {
+        Some(id) => *id,
+        None => {
+            let id = sql::insert_season(
+                tx,
+                Season {
+                    name: format!("Season {}", episode.season),
+                    season_number: episode.season,
+                    image: channel.image.clone(),
+                    series_id,
+                    source_id,
+                    ..Default::default()
+                },
+            )?;
+            state.seasons.insert((series_id, episode.season), id);
+            id
+        }
+    };
+    channel.media_type = media_type::MOVIE;
+    channel.group = None;
+    channel.series_id = Some(series_id);
+    channel.season_id = Some(season_id);
+    channel.episode_num = Some(episode.episode);
+    Ok(())
+}
+
+fn get_episode_info(name: &str) -> Option<EpisodeInfo> {
+    let caps = EPISODE_REGEX
+        .captures(name)
+        .or_else(|| EPISODE_REGEX_ALT.captures(name))?;
+    let series_name = caps["name"]
+        .trim_end_matches(|c: char| c.is_whitespace() || "-_.|:([".contains(c))
+        .trim()
+        .to_string();
+    if series_name.is_empty() {
+        return None;
+    }
+    Some(EpisodeInfo {
+        series_name,
+        season: caps["season"].parse().ok()?,
+        episode: caps["episode"].parse().ok()?,
+    })
+}
+
+/// Stable across refreshes and kept within the integer range the frontend can represent
+fn get_series_id(series_key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in series_key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash & ((1 << 53) - 1)
+}
+
+pub async fn get_m3u8_from_link(
+    source: Source,
+    refresh: bool,
+    report: &mut RefreshReport,
+) -> Result<()> {
     let user_agent = get_user_agent_from_source(&source)?;
     let client = reqwest::Client::builder().user_agent(user_agent).build()?;
     let url = source.url.clone().context("Invalid source")?;
-    let mut response = client.get(&url).send().await?;
+    let source_id = source.id.filter(|_| refresh);
+    let validators = match source_id {
+        Some(id) => sql::get_http_validators(id, HTTP_CACHE_RESOURCE)?,
+        None => None,
+    };
+    let mut response = add_validator_headers(client.get(&url), validators.as_ref())
+        .send()
+        .await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log::log(format!("Playlist {} unchanged since the last refresh, skipping", source.name));
+        report.not_modified = true;
+        return Ok(());
+    }
     if !response.status().is_success() {
         log::log(format!(
             "Failed to get m3u8 from link, status: {}",
@@ -227,23 +480,22 @@ pub async fn get_m3u8_from_link(source: Source, wipe: bool) -> Result<()> {
             response.status()
         );
     }
-    let mut file = std::fs::File::create(get_tmp_path())?;
+    let tmp_file = TempFile(get_tmp_path(
+        &format!("get-{}", source.id.map(|id| id.to_string()).unwrap_or("new".to_string())),
+        "m3u",
+    )?);
+    let mut file = std::fs::File::create(&tmp_file.0)?;
     while let Some(chunk) = response.chunk().await? {
         file.write_all(&chunk)?;
     }
-    read_m3u8(source, wipe)
-}
-
-fn get_tmp_path() -> String {
-    let mut path = directories::ProjectDirs::from("com", "beatstv", "app")
-        .expect("Failed to get project directories")
-        .cache_dir()
-        .to_owned();
-    if !path.exists() {
-        std::fs::create_dir_all(&path).expect("Failed to create cache directory");
+    file.flush()?;
+    drop(file);
+    let validators = get_response_validators(&response);
+    read_m3u8_from_path(source, refresh, &tmp_file.0, report)?;
+    if let Some(id) = source_id {
+        sql::do_tx(|tx| sql::set_http_validators(tx, id, HTTP_CACHE_RESOURCE, &validators))?;
     }
-    path.push("get.m3u");
-    return path.to_string_lossy().to_string();
+    Ok(())
 }
 
 fn extract_non_empty_capture(caps: Captures) -> Option<String> {
@@ -275,6 +527,36 @@ fn set_http_headers(line: &str, headers: &mut ChannelHttpHeaders) -> bool {
     return false;
 }
 
+fn get_epg_urls(header: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for caps in EPG_URL_REGEX.captures_iter(header) {
+        for url in caps["url"].split(',') {
+            let url = url.trim();
+            if !url.is_empty() && !urls.iter().any(|u| u == url) {
+                urls.push(url.to_string());
+            }
+        }
+    }
+    urls
+}
+
+fn get_stream_option(line: &str, option_type: u8) -> Option<ChannelStreamOption> {
+    let (_, option) = line.split_once(':')?;
+    let (key, value) = option.split_once('=')?;
+    let key = key.trim();
+    let value = value.trim();
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some(ChannelStreamOption {
+        id: None,
+        channel_id: None,
+        option_type,
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
 fn get_channel_from_lines(
     first: String,
     mut second: String,
@@ -312,6 +594,12 @@ fn get_channel_from_lines(
     let image = LOGO_REGEX
         .captures(&first)
         .and_then(extract_non_empty_capture);
+    let capture = |regex: &Regex| {
+        regex
+            .captures(&first)
+            .and_then(extract_non_empty_capture)
+            .map(|x| x.trim().to_string())
+    };
     let channel = Channel {
         id: None,
         name: name.trim().to_string(),
@@ -334,12 +622,21 @@ fn get_channel_from_lines(
         plot: None,
         cast: None,
         director: None,
+        tvg_id: capture(&ID_REGEX),
+        tvg_chno: capture(&CHNO_REGEX).and_then(|x| x.parse().ok()),
+        tvg_shift: capture(&SHIFT_REGEX).and_then(|x| x.parse().ok()),
+        catchup: capture(&CATCHUP_REGEX).map(|x| x.to_lowercase()),
+        catchup_source: capture(&CATCHUP_SOURCE_REGEX),
+        catchup_days: capture(&CATCHUP_DAYS_REGEX).and_then(|x| x.parse().ok()),
+        tvg_rec: capture(&REC_REGEX).and_then(|x| x.parse().ok()),
+        now_program: None,
+        next_program: None,
     };
     Ok(channel)
 }
 
 fn get_media_type(url: String) -> u8 {
-    let media_type = if url.ends_with(".mp4") || url.ends_with(".mkv") {
+    let media_type = if url.ends_with(".mp4") || url.ends_with(".mkv") || url.contains("/series/") {
         media_type::MOVIE
     } else {
         media_type::LIVESTREAM
@@ -349,7 +646,12 @@ fn get_media_type(url: String) -> u8 {
 
 #[cfg(test)]
 mod test_m3u {
-    use crate::m3u::get_channel_from_lines;
+    use crate::m3u::{
+        get_channel_from_lines, get_epg_urls, get_episode_info, get_media_type, get_stable_key,
+        get_stream_option, EpisodeInfo,
+    };
+    use crate::media_type;
+    use crate::stream_option_type;
 
     #[test]
     fn test_get_channel_from_lines() {
@@ -365,4 +667,83 @@ mod test_m3u {
         assert!(get_channel_from_lines(r#"#EXTINF:-1 tvg-id="Id Of Channel" tvg-name="" tvg-logo="http://myurl.local/amazing/stuff.png" group-title="|EU| FRANCE HEVC",Alt Name Of Channel"#.to_string(), "http://myurl.local/1111/1111.ts".to_string(), 0, Some(true)).unwrap().name == "Id Of Channel");
         assert!(get_channel_from_lines(r#"#EXTINF:-1 tvg-id="Id Of Channel" tvg-name="" tvg-logo="http://myurl.local/amazing/stuff.png" group-title="|EU| FRANCE HEVC",Alt Name Of Channel"#.to_string(), "http://myurl.local/1111/1111.ts".to_string(), 0, Some(false)).unwrap().name == "Alt Name Of Channel");
     }
+
+    #[test]
+    fn test_get_channel_extended_attributes() {
+        let channel = get_channel_from_lines(r#"#EXTINF:-1 tvg-id="amazing.fr" tvg-name="Amazing Channel" tvg-chno="104" tvg-shift="-1.5" catchup="Shift" catchup-source="?utc={utc}&lutc={lutc}" catchup-days="7" tvg-rec="3" group-title="The Best Channels",Amazing Channel"#.to_string()
+       , r#"http://myurl.local/1234/1234/1234"#.to_string(), 0, Some(false)).unwrap();
+        assert_eq!(channel.tvg_id.as_deref(), Some("amazing.fr"));
+        assert_eq!(channel.tvg_chno, Some(104));
+        assert_eq!(channel.tvg_shift, Some(-1.5));
+        assert_eq!(channel.catchup.as_deref(), Some("shift"));
+        assert_eq!(channel.catchup_source.as_deref(), Some("?utc={utc}&lutc={lutc}"));
+        assert_eq!(channel.catchup_days, Some(7));
+        assert_eq!(channel.tvg_rec, Some(3));
+
+        let channel = get_channel_from_lines(r#"#EXTINF:-1 tvg-name="Plain Channel" tvg-chno="" catchup-days="7",Plain Channel"#.to_string()
+       , r#"http://myurl.local/1234/1234/1234"#.to_string(), 0, Some(false)).unwrap();
+        assert_eq!(channel.tvg_id, None);
+        assert_eq!(channel.tvg_chno, None);
+        assert_eq!(channel.catchup, None);
+        assert_eq!(channel.catchup_days, Some(7));
+    }
+
+    #[test]
+    fn test_get_stable_key() {
+        let hd = get_channel_from_lines(r#"#EXTINF:-1 tvg-id="bbc1.uk",BBC One HD"#.to_string()
+       , r#"http://myurl.local/1/hd.ts"#.to_string(), 0, Some(false)).unwrap();
+        let sd = get_channel_from_lines(r#"#EXTINF:-1 tvg-id="bbc1.uk",BBC One"#.to_string()
+       , r#"http://myurl.local/1/sd.ts"#.to_string(), 0, Some(false)).unwrap();
+        let plain = get_channel_from_lines(r#"#EXTINF:-1,Plain Channel"#.to_string()
+       , r#"http://myurl.local/2.ts"#.to_string(), 0, Some(false)).unwrap();
+        assert_eq!(get_stable_key(&hd).unwrap(), "tvg:bbc1.uk:BBC One HD");
+        assert_eq!(get_stable_key(&sd).unwrap(), "tvg:bbc1.uk:BBC One");
+        assert_eq!(get_stable_key(&plain).unwrap(), "url:http://myurl.local/2.ts");
+    }
+
+    #[test]
+    fn test_get_stream_option() {
+        let option = get_stream_option(
+            "#KODIPROP:inputstream.adaptive.license_key=https://lic.local/key?token=a=b",
+            stream_option_type::KODI,
+        )
+        .unwrap();
+        assert_eq!(option.option_type, stream_option_type::KODI);
+        assert_eq!(option.key, "inputstream.adaptive.license_key");
+        assert_eq!(option.value, "https://lic.local/key?token=a=b");
+        let option =
+            get_stream_option("#EXTVLCOPT:http-header=X-Token: abc", stream_option_type::VLC).unwrap();
+        assert_eq!(option.key, "http-header");
+        assert_eq!(option.value, "X-Token: abc");
+        assert!(get_stream_option("#KODIPROP:no-value=", stream_option_type::KODI).is_none());
+        assert!(get_stream_option("#KODIPROP", stream_option_type::KODI).is_none());
+    }
+
+    #[test]
+    fn test_get_epg_urls() {
+        assert_eq!(
+            get_epg_urls(r#"#EXTM3U url-tvg="http://epg.local/a.xml.gz, http://epg.local/b.xml" x-tvg-url="http://epg.local/a.xml.gz" refresh="3600""#),
+            vec!["http://epg.local/a.xml.gz", "http://epg.local/b.xml"]
+        );
+        assert_eq!(get_epg_urls(r#"#EXTM3U x-tvg-url="http://epg.local/c.xml""#), vec!["http://epg.local/c.xml"]);
+        assert!(get_epg_urls("#EXTM3U").is_empty());
+        assert!(get_epg_urls(r#"#EXTM3U url-tvg="""#).is_empty());
+    }
+
+    #[test]
+    fn test_get_episode_info() {
+        let episode = |series_name: &str, season, episode| EpisodeInfo {
+            series_name: series_name.to_string(),
+            season,
+            episode,
+        };
+        assert_eq!(get_episode_info("Show Name S02E05"), Some(episode("Show Name", 2, 5)));
+        assert_eq!(get_episode_info("Show.Name.s01.e10.1080p"), Some(episode("Show.Name", 1, 10)));
+        assert_eq!(get_episode_info("|EN| Show Name - 3x07"), Some(episode("|EN| Show Name", 3, 7)));
+        assert_eq!(get_episode_info("S01E01"), None);
+        assert_eq!(get_episode_info("Amazing Channel HD"), None);
+        assert_eq!(get_episode_info("Movie 1920x1080"), None);
+        assert_eq!(get_media_type("http://myurl.local/series/user/pass/1.ts".to_string()), media_type::MOVIE);
+        assert_eq!(get_media_type("http://myurl.local/live/user/pass/1.ts".to_string()), media_type::LIVESTREAM);
+    }
 }
@@ -20,11 +20,11 @@
  */
 
 use crate::settings::get_default_record_path;
-use crate::types::{AppState, ChannelHttpHeaders, Source};
+use crate::types::{AppState, ChannelHttpHeaders, ChannelStreamOption, Source};
 #[cfg(target_os = "macos")]
 use crate::utils::find_macos_bin;
-use crate::utils::get_bin;
-use crate::{log, sql};
+use crate::utils::{get_bin, get_option_http_headers};
+use crate::{log, source_type, sql, stalker};
 use crate::{media_type, settings::get_settings, types::Channel};
 use anyhow::{Context, Result};
 use chrono::Local;
@@ -50,6 +50,7 @@ const ARG_MSG_LEVEL: &str = "--msg-level=all=error";
 const ARG_YTDLP_PATH: &str = "--script-opts=ytdl_hook-ytdl_path=";
 const ARG_VOLUME: &str = "--volume=";
 const ARG_HTTP_HEADERS: &str = "--http-header-fields=";
+const ARG_HTTP_HEADERS_APPEND: &str = "--http-header-fields-append=";
 const ARG_USER_AGENT: &str = "--user-agent=";
 const ARG_IGNORE_SSL: &str = "--ytdl-raw-options=no-check-certificates=True";
 const ARG_PREFETCH_PLAYLIST: &str = "--prefetch-playlist=yes";
@@ -76,7 +77,7 @@ static YTDLP_PATH: LazyLock<String> = LazyLock::new(|| find_macos_bin(YTDLP_BIN_
 static YTDLP_PATH: LazyLock<String> = LazyLock::new(|| get_bin(YTDLP_BIN_NAME));
 
 pub async fn play(
-    channel: Channel,
+    mut channel: Channel,
     record: bool,
     record_path: Option<String>,
     state: State<'_, Mutex<AppState>>,
@@ -88,13 +89,12 @@ pub async fn play(
                 .with_context(|| format!("failed to fetch source with id {}", id))
                 .ok()
         });
+    stalker::resolve_stream_url(&mut channel, source.as_ref()).await?;
 
     let args = get_play_args(&channel, record, record_path, &source)?;
 
     if let Some(source) = source.as_ref() {
-        _ = crate::utils::handle_max_streams(source, &state)
-            .await
-            .map_err(|e| log::log(format!("{:?}", e)));
+        crate::utils::handle_max_streams(source, &state).await?;
     }
 
     let mut cmd_builder = Command::new(MPV_PATH.clone());
@@ -198,10 +198,17 @@ fn get_play_args(
     let mut args = Vec::new();
     let settings = get_settings()?;
     let headers = sql::get_channel_headers_by_id(channel.id.context("no channel id?")?)?;
+    let options = sql::get_channel_options_by_id(channel.id.context("no channel id?")?)?;
     args.push(channel.url.clone().context("no url")?);
     if channel.episode_num.is_some() {
-        for url in sql::find_all_episodes_after(channel)? {
-            args.push(url);
+        // Stalker episodes only hold the season command, each link has to be created at play time
+        let is_stalker = source
+            .as_ref()
+            .is_some_and(|s| s.source_type == source_type::STALKER);
+        if !is_stalker {
+            for url in sql::find_all_episodes_after(channel)? {
+                args.push(url);
+            }
         }
         args.push(ARG_NO_RESUME_PLAYBACK.to_string());
     }
@@ -242,8 +249,8 @@ fn get_play_args(
     if let Some(volume) = settings.volume {
         args.push(format!("{ARG_VOLUME}{volume}"));
     }
-    if headers.is_some() || source.is_some() {
-        set_headers(headers, &mut args, source);
+    if headers.is_some() || source.is_some() || !options.is_empty() {
+        set_headers(headers, &options, &mut args, source);
     }
     if let Some(mpv_params) = settings.mpv_params {
         #[cfg(not(target_os = "windows"))]
@@ -274,19 +281,30 @@ fn get_play_args(
 
 fn set_headers(
     headers: Option<ChannelHttpHeaders>,
+    options: &[ChannelStreamOption],
     args: &mut Vec<String>,
     source: &Option<Source>,
 ) {
     let headers = headers.unwrap_or_default();
     let mut headers_vec: Vec<String> = Vec::with_capacity(2);
+    let mut option_user_agent = None;
+    let mut option_headers: Vec<String> = Vec::new();
     if let Some(origin) = headers.http_origin {
         headers_vec.push(format!("{HTTP_ORIGIN}{origin}"));
     }
     if let Some(referrer) = headers.referrer {
         headers_vec.push(format!("{HTTP_REFERRER}{referrer}"));
     }
+    for (name, value) in get_option_http_headers(options) {
+        if name.eq_ignore_ascii_case("user-agent") {
+            option_user_agent.get_or_insert(value);
+        } else {
+            option_headers.push(format!("{name}: {value}"));
+        }
+    }
     if let Some(user_agent) = headers
         .user_agent
+        .or(option_user_agent)
         .or_else(|| source.as_ref().and_then(|f| f.stream_user_agent.clone()))
     {
         args.push(format!("{ARG_USER_AGENT}{user_agent}"));
@@ -300,6 +318,10 @@ fn set_headers(
         let headers = headers_vec.join(",");
         args.push(format!("{ARG_HTTP_HEADERS}{headers}"));
     }
+    // Appended one by one (and after the list is set) since values may contain commas
+    for header in option_headers {
+        args.push(format!("{ARG_HTTP_HEADERS_APPEND}{header}"));
+    }
 }
 
 fn get_path(path_str: String) -> String {
@@ -0,0 +1,257 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::Mutex,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    log, recording_status,
+    restream::{FFMPEG_BIN_NAME, get_ffmpeg_header_args},
+    scheduler,
+    settings::get_settings,
+    sql, stalker,
+    types::{AppState, EPG, Recording},
+    utils::{self, get_bin, get_download_path, sanitize},
+};
+
+/// Recordings share the playback streams map of their source under this key prefix
+const RECORDING_KEY_PREFIX: &str = "recording-";
+const FFMPEG_ERROR_LINES_KEPT: usize = 10;
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+pub fn is_recording_key(key: &str) -> bool {
+    key.starts_with(RECORDING_KEY_PREFIX)
+}
+
+fn get_recording_key(id: i64) -> String {
+    format!("{RECORDING_KEY_PREFIX}{id}")
+}
+
+/// Schedules a recording of a channel, padding defaulting to the settings
+pub fn schedule_recording(mut recording: Recording) -> Result<Recording> {
+    if recording.stop <= recording.start {
+        anyhow::bail!("A recording must end after it starts");
+    }
+    let channel = sql::get_channel_by_id(recording.channel_id)?;
+    let settings = get_settings()?;
+    recording.source_id = channel.source_id;
+    recording.channel_name = channel.name;
+    recording.pre_padding = recording
+        .pre_padding
+        .or(settings.recording_pre_padding.map(i64::from));
+    recording.post_padding = recording
+        .post_padding
+        .or(settings.recording_post_padding.map(i64::from));
+    if get_end(&recording) <= Local::now().timestamp() {
+        anyhow::bail!("{} has already ended", recording.title);
+    }
+    recording.status = recording_status::SCHEDULED;
+    recording.file_path = None;
+    recording.error = None;
+    recording.id = Some(sql::insert_recording(&recording)?);
+    scheduler::wake();
+    Ok(recording)
+}
+
+pub fn schedule_epg_recording(channel_id: i64, epg: EPG) -> Result<Recording> {
+    schedule_recording(Recording {
+        id: None,
+        channel_id,
+        source_id: None,
+        channel_name: String::new(),
+        title: epg.title,
+        subtitle: None,
+        episode_num: None,
+        start: epg.start_timestamp,
+        stop: epg.end_timestamp,
+        pre_padding: None,
+        post_padding: None,
+        status: recording_status::SCHEDULED,
+        file_path: None,
+        error: None,
+        rule_id: None,
+    })
+}
+
+/// Unschedules a recording, stops it while it runs or removes it from the history otherwise
+pub async fn cancel_recording(id: i64, app: AppHandle) -> Result<()> {
+    let recording = sql::get_recording(id)?;
+    if recording.status != recording_status::RECORDING {
+        // Rules would schedule a deleted airing again, so theirs are kept as skipped
+        if recording.status == recording_status::SCHEDULED && recording.rule_id.is_some() {
+            sql::set_recording_status(id, recording_status::CANCELLED, None, None)?;
+        } else {
+            sql::delete_recording(id)?;
+        }
+        scheduler::wake();
+        return Ok(());
+    }
+    let source_id = recording.source_id.context("no source id")?;
+    let state = app.state::<Mutex<AppState>>();
+    utils::remove_from_play_stop(state, &source_id, &get_recording_key(id))
+        .await?
+        .context("Recording isn't running")?
+        .cancel();
+    Ok(())
+}
+
+/// When the scheduler should start a recording
+pub fn get_start(recording: &Recording) -> i64 {
+    recording.start - recording.pre_padding.unwrap_or(0) * 60
+}
+
+fn get_end(recording: &Recording) -> i64 {
+    recording.stop + recording.post_padding.unwrap_or(0) * 60
+}
+
+/// Starts a due recording in the background, its status following along
+pub fn start(recording: Recording, app: &AppHandle) -> Result<()> {
+    let id = recording.id.context("no recording id")?;
+    set_status(app, id, recording_status::RECORDING, None, None)?;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let path = get_recording_path(&recording);
+        let result = match &path {
+            Ok(path) => record(&recording, path, &app).await,
+            Err(e) => Err(anyhow::anyhow!("{:?}", e)),
+        };
+        let path = path.ok().filter(|path| std::fs::metadata(path).is_ok_and(|m| m.len() > 0));
+        let (status, error) = match result {
+            Ok(_) if path.is_some() => (recording_status::DONE, None),
+            Ok(_) => (recording_status::FAILED, Some("Nothing was recorded".to_string())),
+            Err(e) => {
+                log::log(format!("Recording {} failed: {:?}", recording.title, e));
+                (recording_status::FAILED, Some(e.to_string()))
+            }
+        };
+        if let Err(e) = set_status(&app, id, status, path.as_deref(), error.as_deref()) {
+            log::log(format!("Failed to save recording status: {:?}", e));
+        }
+    });
+    Ok(())
+}
+
+fn set_status(
+    app: &AppHandle,
+    id: i64,
+    status: u8,
+    file_path: Option<&str>,
+    error: Option<&str>,
+) -> Result<()> {
+    sql::set_recording_status(id, status, file_path, error)?;
+    let _ = app.emit("recording-status", sql::get_recording(id)?);
+    Ok(())
+}
+
+fn get_recording_path(recording: &Recording) -> Result<String> {
+    let start = DateTime::from_timestamp(recording.start, 0)
+        .context("invalid recording start")?
+        .with_timezone(&Local)
+        .format("%Y-%m-%d-%H-%M");
+    get_download_path(sanitize(format!("{}_{}.ts", recording.title, start)))
+}
+
+/// Records until the end of the window, taking one of the source's streams meanwhile
+async fn record(recording: &Recording, path: &str, app: &AppHandle) -> Result<()> {
+    let id = recording.id.context("no recording id")?;
+    let mut channel = sql::get_channel_by_id(recording.channel_id)?;
+    let source = channel.source_id.map(sql::get_source_from_id).transpose()?;
+    stalker::resolve_stream_url(&mut channel, source.as_ref()).await?;
+    let url = channel.url.context("The channel has no stream url")?;
+    let state = app.state::<Mutex<AppState>>();
+    let token = CancellationToken::new();
+    let key = get_recording_key(id);
+    if let (Some(source_id), Some(source)) = (channel.source_id, &source) {
+        utils::handle_max_streams(source, &state).await?;
+        utils::insert_play_token(source_id, key.clone(), token.clone(), &state).await?;
+    }
+    let duration = get_end(recording) - Local::now().timestamp();
+    let result = run_ffmpeg(recording.channel_id, &url, path, duration, token).await;
+    if let Some(source_id) = channel.source_id {
+        _ = utils::remove_from_play_stop(state, &source_id, &key)
+            .await
+            .map_err(|e| log::log(format!("{:?}", e)));
+    }
+    result
+}
+
+async fn run_ffmpeg(
+    channel_id: i64,
+    url: &str,
+    path: &str,
+    duration: i64,
+    token: CancellationToken,
+) -> Result<()> {
+    let mut command = Command::new(get_bin(FFMPEG_BIN_NAME));
+    command
+        .args(get_ffmpeg_header_args(channel_id)?)
+        .args(["-loglevel", "error"])
+        .args(["-reconnect", "1", "-reconnect_streamed", "1"])
+        .args(["-reconnect_on_network_error", "1", "-reconnect_delay_max", "30"])
+        .arg("-i")
+        .arg(url)
+        .arg("-t")
+        .arg(duration.max(1).to_string())
+        .args(["-c", "copy", "-f", "mpegts", "-y"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+    let mut child = command.spawn().context("Failed to start ffmpeg")?;
+    let stderr = child.stderr.take().context("no stderr")?;
+    let stderr_handle = tokio::spawn(async move {
+        let mut reader = BufReader::new(stderr).lines();
+        let mut lines = Vec::new();
+        while let Ok(Some(line)) = reader.next_line().await {
+            if lines.len() == FFMPEG_ERROR_LINES_KEPT {
+                lines.remove(0);
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    });
+    tokio::select! {
+        status = child.wait() => {
+            let status = status?;
+            let err = stderr_handle.await.unwrap_or_default();
+            if !status.success() {
+                anyhow::bail!("ffmpeg exited with {}: {}", status, err);
+            }
+        },
+        _ = token.cancelled() => {
+            child.kill().await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+use anyhow::Result;
+use chrono::Local;
+
+use crate::{
+    log, recording, recording_status, scheduler, sql,
+    types::{Recording, RecordingRule},
+};
+
+pub fn add_recording_rule(mut rule: RecordingRule) -> Result<RecordingRule> {
+    rule.title = rule.title.trim().to_string();
+    if rule.title.is_empty() {
+        anyhow::bail!("A recording rule needs a title to match");
+    }
+    if let Some(channel_id) = rule.channel_id {
+        sql::get_channel_by_id(channel_id)?;
+    }
+    rule.id = Some(sql::insert_recording_rule(&rule)?);
+    scheduler::wake();
+    Ok(rule)
+}
+
+pub fn set_recording_rule_enabled(id: i64, enabled: bool) -> Result<()> {
+    sql::set_recording_rule_enabled(id, enabled)?;
+    scheduler::wake();
+    Ok(())
+}
+
+pub fn delete_recording_rule(id: i64) -> Result<()> {
+    sql::delete_recording_rule(id)?;
+    scheduler::wake();
+    Ok(())
+}
+
+/// Schedules the upcoming airings matching enabled rules, returning how many were added
+pub fn apply_recording_rules() -> Result<usize> {
+    let now = Local::now().timestamp();
+    let mut count = 0;
+    for rule in sql::get_recording_rules()?.into_iter().filter(|rule| rule.enabled) {
+        for program in sql::get_recording_rule_candidates(&rule, now)? {
+            let duplicate = sql::has_episode_recording(
+                &program.title,
+                program.episode_num.as_deref(),
+                program.subtitle.as_deref(),
+                program.start,
+            )?;
+            if duplicate {
+                continue;
+            }
+            let title = program.title.clone();
+            let result = recording::schedule_recording(Recording {
+                id: None,
+                channel_id: program.channel_id,
+                source_id: None,
+                channel_name: String::new(),
+                title: program.title,
+                subtitle: program.subtitle,
+                episode_num: program.episode_num,
+                start: program.start,
+                stop: program.stop,
+                pre_padding: rule.pre_padding,
+                post_padding: rule.post_padding,
+                status: recording_status::SCHEDULED,
+                file_path: None,
+                error: None,
+                rule_id: rule.id,
+            });
+            match result {
+                Ok(_) => count += 1,
+                Err(e) => log::log(format!(
+                    "Failed to schedule {} for rule {}: {:?}",
+                    title, rule.title, e
+                )),
+            }
+        }
+    }
+    Ok(count)
+}
@@ -41,40 +41,20 @@ use tokio::{
 use crate::{
     mpv,
     settings::get_settings,
-    sql,
+    sql, stalker,
     types::{AppState, Channel, CustomChannel, NetworkInfo},
-    utils::{get_bin, serialize_to_file},
+    utils::{get_bin, get_option_http_headers, serialize_to_file},
 };
 
 const WAN_IP_API: &str = "https://api.ipify.org";
-const FFMPEG_BIN_NAME: &str = "ffmpeg";
+pub const FFMPEG_BIN_NAME: &str = "ffmpeg";
 #[cfg(target_os = "windows")]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
 
 fn start_ffmpeg_listening(channel: Channel, restream_dir: PathBuf) -> Result<Child> {
-    let headers = sql::get_channel_headers_by_id(channel.id.context("no channel id")?)?;
     let playlist_dir = get_playlist_dir(restream_dir);
     let mut command = Command::new(get_bin(FFMPEG_BIN_NAME));
-    if let Some(headers) = headers {
-        if let Some(referrer) = headers.referrer {
-            command.arg("-headers");
-            command.arg(format!("Referer: {referrer}"));
-        }
-        if let Some(user_agent) = headers.user_agent {
-            command.arg("-headers");
-            command.arg(format!("User-Agent: {user_agent}"));
-        }
-        if let Some(origin) = headers.http_origin {
-            command.arg("-headers");
-            command.arg(format!("Origin: {origin}"));
-        }
-        if let Some(ignore_ssl) = headers.ignore_ssl {
-            if ignore_ssl {
-                command.arg("-tls_verify");
-                command.arg("0");
-            }
-        }
-    }
+    command.args(get_ffmpeg_header_args(channel.id.context("no channel id")?)?);
     #[cfg(target_os = "windows")]
     command.creation_flags(CREATE_NO_WINDOW);
     let child = command
@@ -105,6 +85,38 @@ fn start_ffmpeg_listening(channel: Channel, restream_dir: PathBuf) -> Result<Chi
     Ok(child)
 }
 
+/// Input options passing a channel's http headers and stream options to ffmpeg
+pub fn get_ffmpeg_header_args(channel_id: i64) -> Result<Vec<String>> {
+    let headers = sql::get_channel_headers_by_id(channel_id)?;
+    let options = sql::get_channel_options_by_id(channel_id)?;
+    let mut args = Vec::new();
+    if let Some(headers) = headers {
+        if let Some(referrer) = headers.referrer {
+            args.push("-headers".to_string());
+            args.push(format!("Referer: {referrer}"));
+        }
+        if let Some(user_agent) = headers.user_agent {
+            args.push("-headers".to_string());
+            args.push(format!("User-Agent: {user_agent}"));
+        }
+        if let Some(origin) = headers.http_origin {
+            args.push("-headers".to_string());
+            args.push(format!("Origin: {origin}"));
+        }
+        if let Some(ignore_ssl) = headers.ignore_ssl {
+            if ignore_ssl {
+                args.push("-tls_verify".to_string());
+                args.push("0".to_string());
+            }
+        }
+    }
+    for (name, value) in get_option_http_headers(&options) {
+        args.push("-headers".to_string());
+        args.push(format!("{name}: {value}"));
+    }
+    Ok(args)
+}
+
 async fn start_web_server(
     restream_dir: PathBuf,
     port: u16,
@@ -123,8 +135,10 @@ pub async fn start_restream(
     port: u16,
     state: State<'_, Mutex<AppState>>,
     app: AppHandle,
-    channel: Channel,
+    mut channel: Channel,
 ) -> Result<()> {
+    let source = channel.source_id.map(sql::get_source_from_id).transpose()?;
+    stalker::resolve_stream_url(&mut channel, source.as_ref()).await?;
     let stop = state.lock().await.restream_stop_signal.clone();
     stop.store(false, std::sync::atomic::Ordering::Relaxed);
     let restream_dir = get_restream_folder()?;
@@ -202,6 +216,15 @@ pub async fn watch_self(port: u16, state: State<'_, Mutex<AppState>>) -> Result<
         plot: None,
         cast: None,
         director: None,
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        catchup_days: None,
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
     };
     mpv::play(channel, false, None, state).await
 }
@@ -231,6 +254,15 @@ pub fn share_restream(address: String, channel: Channel, path: String) -> Result
             plot: None,
             cast: None,
             director: None,
+            tvg_id: None,
+            tvg_chno: None,
+            tvg_shift: None,
+            catchup: None,
+            catchup_source: None,
+            catchup_days: None,
+            tvg_rec: None,
+            now_program: None,
+            next_program: None,
         },
     };
     serialize_to_file(channel, path)
@@ -0,0 +1,231 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+use std::{sync::LazyLock, time::Duration};
+
+use anyhow::Result;
+use chrono::Local;
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::Notify;
+
+use crate::{
+    log, recording, recording_rules, sql,
+    types::{EPGNotify, Recording},
+};
+
+/// Upper bound on a single sleep, so suspends and clock changes are caught up with quickly
+const MAX_SLEEP: Duration = Duration::from_secs(5 * 60);
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How far a programme may move before a reminder stops following it
+const RESCHEDULE_WINDOW_SECONDS: i64 = 6 * 60 * 60;
+/// How long after its programme started a missed reminder still fires before it is dropped
+const REMINDER_GRACE_SECONDS: i64 = 15 * 60;
+
+static WAKE: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// Work the scheduler fires at a point in time
+enum Job {
+    Reminder(EPGNotify),
+    Recording(Recording),
+}
+
+impl Job {
+    fn due_at(&self) -> i64 {
+        match self {
+            Job::Reminder(epg) => epg.start_timestamp - epg.lead_minutes.unwrap_or(0) * 60,
+            Job::Recording(recording) => recording::get_start(recording),
+        }
+    }
+
+    /// Reminders are too late to fire once their programme is past the grace period.
+    /// Missed recordings are failed separately.
+    fn is_stale(&self, now: i64) -> bool {
+        match self {
+            Job::Reminder(epg) => epg.start_timestamp < now - REMINDER_GRACE_SECONDS,
+            Job::Recording(_) => false,
+        }
+    }
+
+    fn run(&self, app: &AppHandle) -> Result<()> {
+        match self {
+            Job::Reminder(epg) => {
+                notify(epg, app)?;
+                sql::set_reminder_notified(&epg.epg_id)?;
+            }
+            Job::Recording(recording) => recording::start(recording.clone(), app)?,
+        }
+        Ok(())
+    }
+}
+
+/// Tells the scheduler its jobs changed, so it recomputes when to wake up next
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+/// Runs for the lifetime of the app, sleeping until the next job is due or `wake` is called
+pub async fn run(app: AppHandle) {
+    if let Err(e) = sql::fail_interrupted_recordings() {
+        log::log(format!("Failed to clean up interrupted recordings: {:?}", e));
+    }
+    loop {
+        let sleep = match run_due_jobs(&app) {
+            Ok(Some(next)) => Duration::from_secs((next - Local::now().timestamp()).max(0) as u64),
+            Ok(None) => MAX_SLEEP,
+            Err(e) => {
+                log::log(format!("Scheduler failed: {:?}", e));
+                RETRY_DELAY
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(sleep.min(MAX_SLEEP)) => {}
+            _ = WAKE.notified() => {}
+        }
+    }
+}
+
+/// Fires every job that is due and returns when the next one will be
+fn run_due_jobs(app: &AppHandle) -> Result<Option<i64>> {
+    reschedule_reminders()?;
+    let now = Local::now().timestamp();
+    sql::fail_missed_recordings(now)?;
+    let scheduled = recording_rules::apply_recording_rules()?;
+    if scheduled > 0 {
+        log::log(format!("Recording rules scheduled {} airings", scheduled));
+    }
+    let (due, next) = split_due_jobs(get_pending_jobs()?, now);
+    for job in due {
+        if let Err(e) = job.run(app) {
+            log::log(format!("Failed to run scheduled job: {:?}", e));
+        }
+    }
+    sql::clean_epgs(now, now - REMINDER_GRACE_SECONDS)?;
+    Ok(next)
+}
+
+/// The jobs to fire now, leaving out stale ones, and when the next one is due
+fn split_due_jobs(jobs: Vec<Job>, now: i64) -> (Vec<Job>, Option<i64>) {
+    let mut due = Vec::new();
+    let mut next: Option<i64> = None;
+    for job in jobs {
+        let due_at = job.due_at();
+        if due_at > now {
+            next = Some(next.map_or(due_at, |next| next.min(due_at)));
+        } else if !job.is_stale(now) {
+            due.push(job);
+        }
+    }
+    (due, next)
+}
+
+fn get_pending_jobs() -> Result<Vec<Job>> {
+    let reminders = sql::get_pending_reminders()?.into_iter().map(Job::Reminder);
+    let recordings = sql::get_scheduled_recordings()?.into_iter().map(Job::Recording);
+    Ok(reminders.chain(recordings).collect())
+}
+
+/// Moves reminders along with their programme when the stored guide now airs it at another time
+fn reschedule_reminders() -> Result<()> {
+    for epg in sql::get_epgs()? {
+        let Some(channel_id) = epg.channel_id else {
+            continue;
+        };
+        let start = sql::get_rescheduled_start(
+            channel_id,
+            &epg.title,
+            epg.start_timestamp,
+            RESCHEDULE_WINDOW_SECONDS,
+        )?;
+        if let Some(start) = start.filter(|start| *start != epg.start_timestamp) {
+            log::log(format!(
+                "Reminder for {} moved from {} to {}",
+                epg.title, epg.start_timestamp, start
+            ));
+            sql::reschedule_reminder(&epg, channel_id, start)?;
+        }
+    }
+    Ok(())
+}
+
+fn notify(epg: &EPGNotify, app: &AppHandle) -> Result<()> {
+    let lead_minutes = epg.lead_minutes.unwrap_or(0);
+    let title = match lead_minutes {
+        0 => format!("LIVE: {}", epg.title),
+        _ => format!("In {} min: {}", lead_minutes, epg.title),
+    };
+    app.notification()
+        .builder()
+        .title(title)
+        .body(format!("Watch on {}", epg.channel_name))
+        .show()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_scheduler {
+    use super::*;
+
+    fn reminder(epg_id: &str, start_timestamp: i64, lead_minutes: i64) -> Job {
+        Job::Reminder(EPGNotify {
+            epg_id: epg_id.to_string(),
+            title: "News".to_string(),
+            start_timestamp,
+            channel_name: "BBC One".to_string(),
+            channel_id: Some(1),
+            lead_minutes: Some(lead_minutes),
+        })
+    }
+
+    fn epg_ids(jobs: &[Job]) -> Vec<&str> {
+        jobs.iter()
+            .filter_map(|job| match job {
+                Job::Reminder(epg) => Some(epg.epg_id.as_str()),
+                Job::Recording(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_due_jobs() {
+        let now = 1_700_000_000;
+        let jobs = vec![
+            reminder("lead", now + 5 * 60, 10),
+            reminder("later", now + 20 * 60, 5),
+            reminder("soon", now + 60, 0),
+        ];
+        let (due, next) = split_due_jobs(jobs, now);
+        assert_eq!(epg_ids(&due), vec!["lead"]);
+        assert_eq!(next, Some(now + 60));
+    }
+
+    #[test]
+    fn test_late_reminder_fires() {
+        let now = 1_700_000_000;
+        let jobs = vec![
+            reminder("late", now - 90, 0),
+            reminder("stale", now - REMINDER_GRACE_SECONDS - 1, 0),
+        ];
+        let (due, next) = split_due_jobs(jobs, now);
+        assert_eq!(epg_ids(&due), vec!["late"]);
+        assert_eq!(next, None);
+    }
+}
@@ -24,7 +24,7 @@ use std::{collections::HashMap, env::consts::OS};
 use anyhow::{Context, Result};
 use directories::UserDirs;
 
-use crate::{sql, types::Settings};
+use crate::{series_prefetch_type, sql, types::Settings};
 
 pub const MPV_PARAMS: &str = "mpvParams";
 pub const USE_STREAM_CACHING: &str = "useStreamingCaching";
@@ -49,6 +49,15 @@ pub const THEME: &str = "theme";
 pub const PERFORMANCE_MODE: &str = "performanceMode";
 pub const VPN_MODE: &str = "vpnMode";
 pub const TMDB_API_KEY: &str = "tmdbApiKey";
+pub const REFRESH_DROP_THRESHOLD: &str = "refreshDropThreshold";
+pub const DEFAULT_REFRESH_DROP_THRESHOLD: u8 = 50;
+pub const RECORDING_PRE_PADDING: &str = "recordingPrePadding";
+pub const RECORDING_POST_PADDING: &str = "recordingPostPadding";
+pub const DEFAULT_RECORDING_PRE_PADDING: u16 = 2;
+pub const DEFAULT_RECORDING_POST_PADDING: u16 = 5;
+pub const ACCOUNT_EXPIRY_WARNING_DAYS: &str = "accountExpiryWarningDays";
+pub const DEFAULT_ACCOUNT_EXPIRY_WARNING_DAYS: u16 = 7;
+pub const SERIES_PREFETCH: &str = "seriesPrefetch";
 
 pub fn get_settings() -> Result<Settings> {
     let map = sql::get_settings()?;
@@ -82,6 +91,26 @@ pub fn get_settings() -> Result<Settings> {
         vpn_mode: map.get(VPN_MODE).and_then(|s| s.parse().ok()).or(Some(true)),
         // TMDB API key for movie metadata
         tmdb_api_key: map.get(TMDB_API_KEY).map(|s| s.to_string()),
+        refresh_drop_threshold: map
+            .get(REFRESH_DROP_THRESHOLD)
+            .and_then(|s| s.parse().ok())
+            .or(Some(DEFAULT_REFRESH_DROP_THRESHOLD)),
+        recording_pre_padding: map
+            .get(RECORDING_PRE_PADDING)
+            .and_then(|s| s.parse().ok())
+            .or(Some(DEFAULT_RECORDING_PRE_PADDING)),
+        recording_post_padding: map
+            .get(RECORDING_POST_PADDING)
+            .and_then(|s| s.parse().ok())
+            .or(Some(DEFAULT_RECORDING_POST_PADDING)),
+        account_expiry_warning_days: map
+            .get(ACCOUNT_EXPIRY_WARNING_DAYS)
+            .and_then(|s| s.parse().ok())
+            .or(Some(DEFAULT_ACCOUNT_EXPIRY_WARNING_DAYS)),
+        series_prefetch: map
+            .get(SERIES_PREFETCH)
+            .and_then(|s| s.parse().ok())
+            .or(Some(series_prefetch_type::OFF)),
     };
 
     // Safety: Filter out incompatible or buggy parameters from previous sessions
@@ -138,6 +167,11 @@ pub fn update_settings(settings: Settings) -> Result<()> {
     insert_if_some!(THEME, settings.theme);
     insert_if_some!(VPN_MODE, settings.vpn_mode);
     insert_if_some!(TMDB_API_KEY, settings.tmdb_api_key);
+    insert_if_some!(REFRESH_DROP_THRESHOLD, settings.refresh_drop_threshold);
+    insert_if_some!(RECORDING_PRE_PADDING, settings.recording_pre_padding);
+    insert_if_some!(RECORDING_POST_PADDING, settings.recording_post_padding);
+    insert_if_some!(ACCOUNT_EXPIRY_WARNING_DAYS, settings.account_expiry_warning_days);
+    insert_if_some!(SERIES_PREFETCH, settings.series_prefetch);
     
     sql::update_settings(map)?;
     Ok(())
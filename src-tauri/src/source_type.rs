@@ -24,3 +24,5 @@ pub const M3U: u8 = 0;
 pub const M3U_LINK: u8 = 1;
 pub const XTREAM: u8 = 2;
 pub const CUSTOM: u8 = 3;
+/// Stalker middleware portal, as used by MAG boxes. 4 is taken by the frontend's custom import
+pub const STALKER: u8 = 5;
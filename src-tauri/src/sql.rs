@@ -24,12 +24,14 @@ use std::{collections::HashMap, sync::LazyLock};
 use crate::log::log;
 use crate::sort_type;
 use crate::types::{
-    ChannelPreserve, CustomChannel, CustomChannelExtraData, EPGNotify, ExportedGroup, Group,
-    IdName, Season,
+    ChannelPreserve, ChannelProgram, CustomChannel, CustomChannelExtraData, EPGNotify, EPGProgram, ExportedGroup,
+    Group,
+    HttpValidators, IdName, PlaylistExport, Recording, RecordingRule, RefreshReport, Season,
+    SourceAccount,
 };
 use crate::{
-    media_type, source_type,
-    types::{Channel, ChannelHttpHeaders, Filters, Source},
+    epg_origin, media_type, recording_status, source_type,
+    types::{Channel, ChannelHttpHeaders, ChannelStreamOption, Filters, Source},
     view_type,
 };
 use anyhow::{Context, Result};
@@ -44,6 +46,7 @@ use rusqlite_migration::{M, Migrations};
 // so 36 provides exactly 12 complete rows for optimal visual balance
 const PAGE_SIZE: u8 = 36;
 pub const DB_NAME: &str = "db.sqlite";
+const REFRESH_REPORTS_KEPT: usize = 20;
 static CONN: LazyLock<Pool<SqliteConnectionManager>> = LazyLock::new(|| {
     create_connection_pool().unwrap_or_else(|e| {
         eprintln!("CRITICAL: Failed to initialize database connection pool: {:?}", e);
@@ -349,6 +352,248 @@ fn apply_migrations() -> Result<()> {
               CREATE INDEX IF NOT EXISTS index_omdb_cache_fetched_at ON omdb_cache(fetched_at);
             "#,
         ),
+        // Migration 12: Extended EXTINF attributes (channel numbers, EPG shift, catch-up)
+        M::up(
+            r#"
+              ALTER TABLE channels ADD COLUMN tvg_id TEXT;
+              ALTER TABLE channels ADD COLUMN tvg_chno INTEGER;
+              ALTER TABLE channels ADD COLUMN tvg_shift REAL;
+              ALTER TABLE channels ADD COLUMN catchup TEXT;
+              ALTER TABLE channels ADD COLUMN catchup_source TEXT;
+              ALTER TABLE channels ADD COLUMN catchup_days INTEGER;
+              ALTER TABLE channels ADD COLUMN tvg_rec INTEGER;
+
+              CREATE INDEX index_channels_tvg_id ON channels(tvg_id);
+              CREATE INDEX index_channels_tvg_chno ON channels(tvg_chno);
+            "#,
+        ),
+        // Migration 13: Per-channel stream options (#EXTVLCOPT, #KODIPROP)
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "channel_stream_options" (
+                "id" INTEGER PRIMARY KEY,
+                "channel_id" integer,
+                "option_type" integer,
+                "key" varchar(200),
+                "value" TEXT,
+                FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE
+              );
+              CREATE UNIQUE INDEX IF NOT EXISTS index_channel_stream_options_unique ON channel_stream_options(channel_id, option_type, key);
+            "#,
+        ),
+        // Migration 14: XMLTV guide urls advertised by M3U playlists
+        M::up(
+            r#"
+              ALTER TABLE sources ADD COLUMN epg_url TEXT;
+            "#,
+        ),
+        // Migration 15: Stable refresh keys so refreshes update rows in place
+        M::up(
+            r#"
+              ALTER TABLE channels ADD COLUMN stable_key TEXT;
+              ALTER TABLE channels ADD COLUMN last_seen integer;
+              CREATE INDEX index_channels_stable_key ON channels(source_id, stable_key);
+            "#,
+        ),
+        // Migration 16: HTTP validators for conditional refreshes
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "source_http_cache" (
+                "id" INTEGER PRIMARY KEY,
+                "source_id" integer,
+                "resource" varchar(100),
+                "etag" TEXT,
+                "last_modified" TEXT,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+              CREATE UNIQUE INDEX IF NOT EXISTS index_source_http_cache_unique ON source_http_cache(source_id, resource);
+            "#,
+        ),
+        // Migration 17: Refresh report history
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "refresh_reports" (
+                "id" INTEGER PRIMARY KEY,
+                "source_id" integer,
+                "started_at" integer,
+                "success" integer,
+                "report" TEXT,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+              CREATE INDEX IF NOT EXISTS index_refresh_reports_source_id ON refresh_reports(source_id, started_at);
+            "#,
+        ),
+        // Migration 18: Programmes imported from XMLTV guides
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "epg_programs" (
+                "id" INTEGER PRIMARY KEY,
+                "channel_id" integer,
+                "source_id" integer,
+                "start" integer,
+                "stop" integer,
+                "title" TEXT,
+                "subtitle" TEXT,
+                "description" TEXT,
+                "episode_num" TEXT,
+                "category" TEXT,
+                "icon" TEXT,
+                FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+              CREATE INDEX IF NOT EXISTS index_epg_programs_channel_id ON epg_programs(channel_id, start);
+              CREATE INDEX IF NOT EXISTS index_epg_programs_source_id ON epg_programs(source_id);
+            "#,
+        ),
+        // Migration 19: Cached Xtream guide listings
+        M::up(
+            r#"
+              ALTER TABLE epg_programs ADD COLUMN origin integer DEFAULT 0;
+              ALTER TABLE epg_programs ADD COLUMN has_archive integer DEFAULT 0;
+              ALTER TABLE epg_programs ADD COLUMN server_start TEXT;
+              CREATE TABLE IF NOT EXISTS "epg_fetches" (
+                "id" INTEGER PRIMARY KEY,
+                "channel_id" integer UNIQUE,
+                "source_id" integer,
+                "fetched_at" integer,
+                "expires_at" integer,
+                FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+            "#,
+        ),
+        // Migration 20: Full-text index over programme titles and descriptions
+        M::up(
+            r#"
+              CREATE VIRTUAL TABLE IF NOT EXISTS epg_programs_fts USING fts5(
+                title,
+                description,
+                content='epg_programs',
+                content_rowid='id',
+                tokenize='unicode61 remove_diacritics 2'
+              );
+              CREATE TRIGGER IF NOT EXISTS epg_programs_fts_insert AFTER INSERT ON epg_programs BEGIN
+                INSERT INTO epg_programs_fts(rowid, title, description)
+                VALUES (new.id, new.title, new.description);
+              END;
+              CREATE TRIGGER IF NOT EXISTS epg_programs_fts_delete AFTER DELETE ON epg_programs BEGIN
+                INSERT INTO epg_programs_fts(epg_programs_fts, rowid, title, description)
+                VALUES ('delete', old.id, old.title, old.description);
+              END;
+              CREATE TRIGGER IF NOT EXISTS epg_programs_fts_update AFTER UPDATE ON epg_programs BEGIN
+                INSERT INTO epg_programs_fts(epg_programs_fts, rowid, title, description)
+                VALUES ('delete', old.id, old.title, old.description);
+                INSERT INTO epg_programs_fts(rowid, title, description)
+                VALUES (new.id, new.title, new.description);
+              END;
+              INSERT INTO epg_programs_fts(epg_programs_fts) VALUES ('rebuild');
+            "#,
+        ),
+        // Migration 21: Reminder lead times and rescheduling
+        M::up(
+            r#"
+              ALTER TABLE epg ADD COLUMN channel_id integer;
+              ALTER TABLE epg ADD COLUMN lead_minutes integer DEFAULT 0;
+              ALTER TABLE epg ADD COLUMN notified integer DEFAULT 0;
+            "#,
+        ),
+        // Migration 22: Scheduled recordings
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "recordings" (
+                "id" INTEGER PRIMARY KEY,
+                "channel_id" integer,
+                "source_id" integer,
+                "channel_name" TEXT,
+                "title" TEXT,
+                "start" integer,
+                "stop" integer,
+                "pre_padding" integer,
+                "post_padding" integer,
+                "status" integer DEFAULT 0,
+                "file_path" TEXT,
+                "error" TEXT,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+              CREATE INDEX IF NOT EXISTS index_recordings_status ON recordings(status, start);
+            "#,
+        ),
+        // Migration 23: Series link recording rules
+        M::up(
+            r#"
+              ALTER TABLE recordings ADD COLUMN subtitle TEXT;
+              ALTER TABLE recordings ADD COLUMN episode_num TEXT;
+              ALTER TABLE recordings ADD COLUMN rule_id integer;
+              CREATE TABLE IF NOT EXISTS "recording_rules" (
+                "id" INTEGER PRIMARY KEY,
+                "title" TEXT NOT NULL,
+                "channel_id" integer,
+                "pre_padding" integer,
+                "post_padding" integer,
+                "enabled" integer DEFAULT 1
+              );
+            "#,
+        ),
+        // Migration 24: Per-source guide offset and panel timezone
+        M::up(
+            r#"
+              ALTER TABLE sources ADD COLUMN epg_offset integer;
+              ALTER TABLE sources ADD COLUMN server_utc_offset integer;
+            "#,
+        ),
+        // Migration 25: XMLTV guide channels and user confirmed channel mappings
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "epg_channels" (
+                "id" INTEGER PRIMARY KEY,
+                "source_id" integer,
+                "xmltv_id" TEXT,
+                "display_names" TEXT,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+              CREATE INDEX IF NOT EXISTS index_epg_channels_source_id ON epg_channels(source_id);
+              CREATE TABLE IF NOT EXISTS "epg_channel_mappings" (
+                "id" INTEGER PRIMARY KEY,
+                "channel_id" integer UNIQUE,
+                "source_id" integer,
+                "xmltv_id" TEXT,
+                FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+            "#,
+        ),
+        // Migration 26: MAC address of Stalker portal sources
+        M::up(
+            r#"
+              ALTER TABLE sources ADD COLUMN mac_address TEXT;
+            "#,
+        ),
+        // Migration 27: Xtream account status, and the last warning notified about it
+        M::up(
+            r#"
+              ALTER TABLE sources ADD COLUMN account_status TEXT;
+              ALTER TABLE sources ADD COLUMN exp_date integer;
+              ALTER TABLE sources ADD COLUMN max_connections integer;
+              ALTER TABLE sources ADD COLUMN active_cons integer;
+              ALTER TABLE sources ADD COLUMN is_trial integer;
+              ALTER TABLE sources ADD COLUMN account_warning TEXT;
+            "#,
+        ),
+        // Migration 28: Series listing changes, to know which episode lists need fetching again
+        M::up(
+            r#"
+              CREATE TABLE IF NOT EXISTS "series_info" (
+                "id" INTEGER PRIMARY KEY,
+                "source_id" integer,
+                "series_id" integer,
+                "last_modified" integer,
+                "episodes_modified" integer,
+                "episodes_fetched" integer DEFAULT 0,
+                UNIQUE (source_id, series_id),
+                FOREIGN KEY (source_id) REFERENCES sources(id) ON DELETE CASCADE
+              );
+            "#,
+        ),
     ]);
     migrations.to_latest(&mut *sql)?;
     Ok(())
@@ -378,8 +623,8 @@ pub fn create_or_find_source_by_name(tx: &Transaction, source: &Source) -> Resul
     }
 
     tx.execute(
-    "INSERT INTO sources (name, source_type, url, username, password, use_tvg_id, user_agent, max_streams, last_updated) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
-    params![source.name, source.source_type.clone() as u8, source.url, source.username, Option::<String>::None, source.use_tvg_id, source.user_agent, source.max_streams, chrono::Utc::now().timestamp()],
+    "INSERT INTO sources (name, source_type, url, username, password, use_tvg_id, user_agent, max_streams, last_updated, mac_address) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    params![source.name, source.source_type.clone() as u8, source.url, source.username, Option::<String>::None, source.use_tvg_id, source.user_agent, source.max_streams, chrono::Utc::now().timestamp(), source.mac_address],
     )?;
     Ok(tx.last_insert_rowid())
 }
@@ -418,8 +663,8 @@ pub fn insert_season(tx: &Transaction, season: Season) -> Result<i64> {
 pub fn insert_channel(tx: &Transaction, channel: Channel) -> Result<()> {
     tx.execute(
         r#"
-INSERT INTO channels (name, group_id, image, url, source_id, media_type, series_id, favorite, stream_id, tv_archive, season_id, episode_num, rating, genre, release_date, plot, cast, director)
-VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+INSERT INTO channels (name, group_id, image, url, source_id, media_type, series_id, favorite, stream_id, tv_archive, season_id, episode_num, rating, genre, release_date, plot, cast, director, tvg_id, tvg_chno, tvg_shift, catchup, catchup_source, catchup_days, tvg_rec)
+VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
 ON CONFLICT (name, source_id, url, series_id, season_id)
 DO UPDATE SET
     url = excluded.url,
@@ -434,7 +679,14 @@ DO UPDATE SET
     release_date = excluded.release_date,
     plot = excluded.plot,
     cast = excluded.cast,
-    director = excluded.director;
+    director = excluded.director,
+    tvg_id = excluded.tvg_id,
+    tvg_chno = excluded.tvg_chno,
+    tvg_shift = excluded.tvg_shift,
+    catchup = excluded.catchup,
+    catchup_source = excluded.catchup_source,
+    catchup_days = excluded.catchup_days,
+    tvg_rec = excluded.tvg_rec;
 "#,
         params![
             channel.name,
@@ -454,12 +706,303 @@ DO UPDATE SET
             channel.release_date,
             channel.plot,
             channel.cast,
-            channel.director
+            channel.director,
+            channel.tvg_id,
+            channel.tvg_chno,
+            channel.tvg_shift,
+            channel.catchup,
+            channel.catchup_source,
+            channel.catchup_days,
+            channel.tvg_rec
         ],
     )?;
     Ok(())
 }
 
+/// Inserts or updates a channel by its refresh key, keeping the row id (and with it favorites,
+/// history, hidden state and headers) stable across refreshes. Returns the channel id.
+pub fn upsert_channel(
+    tx: &Transaction,
+    channel: Channel,
+    stable_key: &str,
+    last_seen: i64,
+) -> Result<i64> {
+    let params = params![
+        channel.name,
+        channel.group_id,
+        channel.image,
+        channel.url,
+        channel.source_id,
+        channel.media_type as u8,
+        channel.series_id,
+        channel.stream_id,
+        channel.tv_archive,
+        channel.season_id,
+        channel.episode_num,
+        channel.rating,
+        channel.genre,
+        channel.release_date,
+        channel.plot,
+        channel.cast,
+        channel.director,
+        channel.tvg_id,
+        channel.tvg_chno,
+        channel.tvg_shift,
+        channel.catchup,
+        channel.catchup_source,
+        channel.catchup_days,
+        channel.tvg_rec,
+        stable_key,
+        last_seen,
+    ];
+    // Enriched metadata is only overwritten when the provider actually sends some
+    let update_set = r#"
+    name = ?1,
+    group_id = ?2,
+    image = ?3,
+    url = ?4,
+    media_type = ?6,
+    series_id = ?7,
+    stream_id = ?8,
+    tv_archive = ?9,
+    season_id = ?10,
+    episode_num = ?11,
+    rating = COALESCE(?12, rating),
+    genre = COALESCE(?13, genre),
+    release_date = COALESCE(?14, release_date),
+    plot = COALESCE(?15, plot),
+    cast = COALESCE(?16, "cast"),
+    director = COALESCE(?17, director),
+    tvg_id = ?18,
+    tvg_chno = ?19,
+    tvg_shift = ?20,
+    catchup = ?21,
+    catchup_source = ?22,
+    catchup_days = ?23,
+    tvg_rec = ?24,
+    stable_key = ?25,
+    last_seen = ?26
+"#;
+    let id: Option<i64> = tx
+        .query_row(
+            &format!(
+                "UPDATE channels SET {update_set} WHERE source_id = ?5 AND stable_key = ?25 RETURNING id"
+            ),
+            params,
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = id {
+        return Ok(id);
+    }
+    // Rows created before refresh keys existed are adopted once, by name
+    let id: Option<i64> = tx
+        .query_row(
+            &format!(
+                r#"
+UPDATE channels SET {update_set}
+WHERE id = (
+    SELECT id FROM channels
+    WHERE source_id = ?5 AND stable_key IS NULL AND name = ?1 AND media_type = ?6
+    LIMIT 1
+)
+RETURNING id"#
+            ),
+            params,
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = id {
+        return Ok(id);
+    }
+    Ok(tx.query_row(
+        &format!(
+            r#"
+INSERT INTO channels (name, group_id, image, url, source_id, media_type, series_id, stream_id, tv_archive, season_id, episode_num, rating, genre, release_date, plot, cast, director, tvg_id, tvg_chno, tvg_shift, catchup, catchup_source, catchup_days, tvg_rec, stable_key, last_seen, favorite)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, 0)
+ON CONFLICT (name, source_id, url, series_id, season_id)
+DO UPDATE SET {update_set}
+RETURNING id"#
+        ),
+        params,
+        |row| row.get(0),
+    )?)
+}
+
+/// Removes the channels of the given media types that were not seen by the refresh stamped `last_seen`.
+/// Episodes are only swept when `include_episodes` is set, since Xtream loads them lazily.
+pub fn delete_stale_channels(
+    tx: &Transaction,
+    source_id: i64,
+    media_types: &[u8],
+    last_seen: i64,
+    include_episodes: bool,
+) -> Result<usize> {
+    if media_types.is_empty() {
+        return Ok(0);
+    }
+    let sql = format!(
+        r#"
+        DELETE FROM channels
+        WHERE source_id = ?
+        AND (last_seen IS NULL OR last_seen < ?)
+        AND media_type IN ({})
+        AND (? OR series_id IS NULL OR media_type != {})
+        "#,
+        generate_placeholders(media_types.len()),
+        media_type::MOVIE
+    );
+    let mut params: Vec<&dyn rusqlite::ToSql> = vec![&source_id, &last_seen];
+    for media_type in media_types {
+        params.push(media_type);
+    }
+    params.push(&include_episodes);
+    Ok(tx.execute(&sql, params_from_iter(params))?)
+}
+
+/// Counts a source's channels per media type, leaving out episodes.
+/// With `last_seen`, only the channels stamped by that refresh are counted.
+pub fn get_refresh_counts(
+    tx: &Transaction,
+    source_id: i64,
+    last_seen: Option<i64>,
+) -> Result<HashMap<u8, usize>> {
+    let counts = tx
+        .prepare(&format!(
+            r#"
+            SELECT media_type, COUNT(*)
+            FROM channels
+            WHERE source_id = ?1
+            AND (?2 IS NULL OR last_seen = ?2)
+            AND (series_id IS NULL OR media_type != {})
+            GROUP BY media_type
+            "#,
+            media_type::MOVIE
+        ))?
+        .query_map(params![source_id, last_seen], |row| {
+            Ok((row.get::<_, u8>(0)?, row.get::<_, usize>(1)?))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(counts)
+}
+
+pub fn get_max_channel_id(tx: &Transaction) -> Result<i64> {
+    Ok(tx.query_row("SELECT COALESCE(MAX(id), 0) FROM channels", [], |row| {
+        row.get(0)
+    })?)
+}
+
+/// Splits the channels stamped by a refresh into (added, updated) per media type, leaving out
+/// episodes. Nothing is deleted before the refresh ends, so new rows are those above `max_id`.
+pub fn get_refresh_changes(
+    tx: &Transaction,
+    source_id: i64,
+    last_seen: i64,
+    max_id: i64,
+) -> Result<HashMap<u8, (usize, usize)>> {
+    let changes = tx
+        .prepare(&format!(
+            r#"
+            SELECT media_type, SUM(id > ?3), SUM(id <= ?3)
+            FROM channels
+            WHERE source_id = ?1
+            AND last_seen = ?2
+            AND (series_id IS NULL OR media_type != {})
+            GROUP BY media_type
+            "#,
+            media_type::MOVIE
+        ))?
+        .query_map(params![source_id, last_seen, max_id], |row| {
+            Ok((
+                row.get::<_, u8>(0)?,
+                (row.get::<_, usize>(1)?, row.get::<_, usize>(2)?),
+            ))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(changes)
+}
+
+/// Cleans up whatever the removal of stale channels left dangling
+pub fn delete_refresh_orphans(tx: &Transaction, source_id: i64) -> Result<()> {
+    tx.execute(
+        &format!(
+            r#"
+            DELETE FROM channels
+            WHERE source_id = ?1
+            AND media_type = {}
+            AND series_id IS NOT NULL
+            AND CAST(series_id AS TEXT) NOT IN (
+                SELECT url FROM channels
+                WHERE source_id = ?1 AND media_type = {} AND url IS NOT NULL
+            )
+            "#,
+            media_type::MOVIE,
+            media_type::SERIE
+        ),
+        params![source_id],
+    )?;
+    tx.execute(
+        r#"
+        DELETE FROM seasons
+        WHERE source_id = ?1
+        AND id NOT IN (
+            SELECT season_id FROM channels
+            WHERE source_id = ?1 AND season_id IS NOT NULL
+        )
+        "#,
+        params![source_id],
+    )?;
+    tx.execute(
+        r#"
+        DELETE FROM groups
+        WHERE source_id = ?1
+        AND id NOT IN (
+            SELECT group_id FROM channels
+            WHERE source_id = ?1 AND group_id IS NOT NULL
+        )
+        "#,
+        params![source_id],
+    )?;
+    // Foreign keys aren't enforced, so per-channel data is removed by hand
+    tx.execute(
+        "DELETE FROM channel_http_headers WHERE channel_id NOT IN (SELECT id FROM channels)",
+        [],
+    )?;
+    tx.execute(
+        "DELETE FROM channel_stream_options WHERE channel_id NOT IN (SELECT id FROM channels)",
+        [],
+    )?;
+    tx.execute(
+        "DELETE FROM epg_programs WHERE source_id = ?1 AND channel_id NOT IN (SELECT id FROM channels WHERE source_id = ?1)",
+        params![source_id],
+    )?;
+    tx.execute(
+        "DELETE FROM epg_fetches WHERE source_id = ?1 AND channel_id NOT IN (SELECT id FROM channels WHERE source_id = ?1)",
+        params![source_id],
+    )?;
+    tx.execute(
+        "DELETE FROM epg_channel_mappings WHERE source_id = ?1 AND channel_id NOT IN (SELECT id FROM channels WHERE source_id = ?1)",
+        params![source_id],
+    )?;
+    tx.execute(
+        &format!(
+            r#"
+            DELETE FROM series_info
+            WHERE source_id = ?1
+            AND CAST(series_id AS TEXT) NOT IN (
+                SELECT url FROM channels
+                WHERE source_id = ?1 AND media_type = {} AND url IS NOT NULL
+            )
+            "#,
+            media_type::SERIE
+        ),
+        params![source_id],
+    )?;
+    Ok(())
+}
+
 pub fn insert_channel_headers(tx: &Transaction, headers: ChannelHttpHeaders) -> Result<()> {
     tx.execute(
         r#"
@@ -477,6 +1020,64 @@ VALUES (?, ?, ?, ?, ?);
     Ok(())
 }
 
+/// Replaces the headers of a refreshed channel, removing them when the playlist no longer sends any
+pub fn set_channel_headers(
+    tx: &Transaction,
+    channel_id: i64,
+    headers: Option<ChannelHttpHeaders>,
+) -> Result<()> {
+    let Some(headers) = headers.filter(|headers| !channel_headers_empty(headers)) else {
+        tx.execute(
+            "DELETE FROM channel_http_headers WHERE channel_id = ?",
+            params![channel_id],
+        )?;
+        return Ok(());
+    };
+    tx.execute(
+        r#"
+INSERT INTO channel_http_headers (channel_id, referrer, user_agent, http_origin, ignore_ssl)
+VALUES (?, ?, ?, ?, ?)
+ON CONFLICT (channel_id)
+DO UPDATE SET
+    referrer = excluded.referrer,
+    user_agent = excluded.user_agent,
+    http_origin = excluded.http_origin,
+    ignore_ssl = excluded.ignore_ssl;
+"#,
+        params![
+            channel_id,
+            headers.referrer,
+            headers.user_agent,
+            headers.http_origin,
+            headers.ignore_ssl
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn set_channel_options(
+    tx: &Transaction,
+    channel_id: i64,
+    options: &[ChannelStreamOption],
+) -> Result<()> {
+    tx.execute(
+        "DELETE FROM channel_stream_options WHERE channel_id = ?",
+        params![channel_id],
+    )?;
+    let mut stmt = tx.prepare(
+        r#"
+INSERT INTO channel_stream_options (channel_id, option_type, key, value)
+VALUES (?, ?, ?, ?)
+ON CONFLICT (channel_id, option_type, key)
+DO UPDATE SET value = excluded.value;
+"#,
+    )?;
+    for option in options {
+        stmt.execute(params![channel_id, option.option_type, option.key, option.value])?;
+    }
+    Ok(())
+}
+
 pub fn update_channel_enriched_data(
     id: i64,
     rating: Option<f32>,
@@ -587,6 +1188,26 @@ pub fn get_channel_headers_by_id(id: i64) -> Result<Option<ChannelHttpHeaders>>
     Ok(headers)
 }
 
+pub fn get_channel_options_by_id(id: i64) -> Result<Vec<ChannelStreamOption>> {
+    let sql = get_conn()?;
+    let options = sql
+        .prepare("SELECT * FROM channel_stream_options WHERE channel_id = ? ORDER BY id")?
+        .query_map(params![id], row_to_channel_option)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(options)
+}
+
+fn row_to_channel_option(row: &Row) -> Result<ChannelStreamOption, rusqlite::Error> {
+    Ok(ChannelStreamOption {
+        id: row.get("id")?,
+        channel_id: row.get("channel_id")?,
+        option_type: row.get("option_type")?,
+        key: row.get("key")?,
+        value: row.get("value")?,
+    })
+}
+
 fn row_to_channel_headers(row: &Row) -> Result<ChannelHttpHeaders, rusqlite::Error> {
     Ok(ChannelHttpHeaders {
         id: row.get("id")?,
@@ -664,13 +1285,19 @@ pub fn search(filters: Filters) -> Result<Vec<Channel>> {
         false => vec![format!("%{query}%")],
     };
 
+    // Live channels carry what's on now and next, XMLTV rows winning over Xtream ones
+    let with_now_next =
+        filters.series_id.is_none() && media_types.contains(&media_type::LIVESTREAM);
+    let now = chrono::Utc::now().timestamp();
     let mut sql_query = format!(
         r#"
-        SELECT * FROM CHANNELS
+        SELECT {} FROM CHANNELS{}
         WHERE ({})
-        AND media_type IN ({})
-        AND source_id IN ({})
+        AND CHANNELS.media_type IN ({})
+        AND CHANNELS.source_id IN ({})
         AND (url IS NOT NULL OR stream_id IS NOT NULL)"#,
+        if with_now_next { NOW_NEXT_COLUMNS } else { "*" },
+        if with_now_next { NOW_NEXT_JOINS } else { "" },
         get_keywords_sql(keywords.len()),
         generate_placeholders(media_types.len()),
         generate_placeholders(filters.source_ids.len()),
@@ -714,7 +1341,7 @@ pub fn search(filters: Filters) -> Result<Vec<Channel>> {
             sort_type::ALPHABETICAL_DESC => "DESC",
             _ => "ASC",
         };
-        sql_query += &format!("\nORDER BY episode_num {0}, name {0}", order)
+        sql_query += &format!("\nORDER BY CHANNELS.episode_num {0}, name {0}", order)
     } else {
         match filters.sort {
             sort_type::ALPHABETICAL_DESC => sql_query += "\nORDER BY name DESC",
@@ -732,9 +1359,15 @@ pub fn search(filters: Filters) -> Result<Vec<Channel>> {
     }
 
     sql_query += "\nLIMIT ?, ?";
+    if with_now_next {
+        baked_params += 1;
+    }
     let mut params: Vec<&dyn rusqlite::ToSql> = Vec::with_capacity(
         baked_params + media_types.len() + filters.source_ids.len() + keywords.len(),
     );
+    if with_now_next {
+        params.push(&now);
+    }
     params.extend(to_to_sql(&keywords));
     params.extend(to_to_sql(&media_types));
     params.extend(to_to_sql(&filters.source_ids));
@@ -829,6 +1462,15 @@ fn season_row_to_channel(row: &Row) -> std::result::Result<Channel, rusqlite::Er
         plot: None,
         cast: None,
         director: None,
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        catchup_days: None,
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
     })
 }
 
@@ -1122,6 +1764,30 @@ fn to_to_sql<T: rusqlite::ToSql>(values: &[T]) -> Vec<&dyn rusqlite::ToSql> {
     values.iter().map(|x| x as &dyn rusqlite::ToSql).collect()
 }
 
+const NOW_NEXT_COLUMNS: &str = r#"CHANNELS.*,
+        now_p.title AS now_title, now_p.start AS now_start, now_p.stop AS now_stop,
+        next_p.title AS next_title, next_p.start AS next_start, next_p.stop AS next_stop"#;
+
+const NOW_NEXT_JOINS: &str = r#"
+        LEFT JOIN epg_programs now_p ON now_p.id = (
+            SELECT id FROM epg_programs
+            WHERE channel_id = CHANNELS.id AND start <= ?1 AND stop > ?1
+            ORDER BY origin, start DESC LIMIT 1)
+        LEFT JOIN epg_programs next_p ON next_p.id = (
+            SELECT id FROM epg_programs
+            WHERE channel_id = CHANNELS.id AND start >= COALESCE(now_p.stop, ?1)
+            AND origin = COALESCE(now_p.origin, origin)
+            ORDER BY origin, start LIMIT 1)"#;
+
+fn row_to_channel_program(row: &Row, prefix: &str) -> Option<ChannelProgram> {
+    let title: Option<String> = row.get(format!("{prefix}_title").as_str()).ok().flatten();
+    Some(ChannelProgram {
+        title: title?,
+        start: row.get(format!("{prefix}_start").as_str()).ok()?,
+        stop: row.get(format!("{prefix}_stop").as_str()).ok()?,
+    })
+}
+
 fn get_keywords_sql(size: usize) -> String {
     std::iter::repeat("name LIKE ?")
         .take(size)
@@ -1136,17 +1802,30 @@ fn generate_placeholders(size: usize) -> String {
         .join(",")
 }
 
+/// Episodes loaded before the series was last refreshed don't count, so they get reloaded
 pub fn series_has_episodes(series_id: u64, source_id: i64) -> Result<bool> {
     let sql = get_conn()?;
     let series_exists = sql
         .query_row(
             r#"
       SELECT 1
-      FROM channels
-      WHERE series_id = ? AND source_id = ?
+      FROM channels e
+      WHERE e.series_id = ?1 AND e.source_id = ?2
+      AND (
+          COALESCE(e.last_seen, 0) >= COALESCE((
+              SELECT MAX(s.last_seen) FROM channels s
+              WHERE s.source_id = ?2 AND s.media_type = ?3 AND s.url = CAST(?1 AS TEXT)
+          ), 0)
+          -- Episodes fetched since the series last changed on the panel are still current
+          OR EXISTS (
+              SELECT 1 FROM series_info i
+              WHERE i.source_id = ?2 AND i.series_id = ?1 AND i.episodes_fetched = 1
+              AND i.last_modified IS NOT NULL AND i.episodes_modified = i.last_modified
+          )
+      )
       LIMIT 1
     "#,
-            params![series_id, source_id],
+            params![series_id, source_id, media_type::SERIE],
             |row| row.get::<_, u8>(0),
         )
         .optional()?
@@ -1154,13 +1833,72 @@ pub fn series_has_episodes(series_id: u64, source_id: i64) -> Result<bool> {
     Ok(series_exists)
 }
 
-fn to_sql_like(query: Option<String>) -> String {
-    query.map(|x| format!("%{x}%")).unwrap_or("%".to_string())
-}
-
-pub fn search_group(filters: Filters) -> Result<Vec<Channel>> {
-    let sql = get_conn()?;
-    let (offset, limit) = if filters.page == 0 {
+/// Remembers when the panel last changed a series, as listed by a refresh
+pub fn set_series_last_modified(
+    tx: &Transaction,
+    source_id: i64,
+    series_id: u64,
+    last_modified: Option<i64>,
+) -> Result<()> {
+    tx.execute(
+        r#"
+        INSERT INTO series_info (source_id, series_id, last_modified)
+        VALUES (?, ?, ?)
+        ON CONFLICT (source_id, series_id)
+        DO UPDATE SET last_modified = excluded.last_modified
+        "#,
+        params![source_id, series_id, last_modified],
+    )?;
+    Ok(())
+}
+
+pub fn set_series_episodes_fetched(tx: &Transaction, source_id: i64, series_id: u64) -> Result<()> {
+    tx.execute(
+        r#"
+        UPDATE series_info SET episodes_fetched = 1, episodes_modified = last_modified
+        WHERE source_id = ? AND series_id = ?
+        "#,
+        params![source_id, series_id],
+    )?;
+    Ok(())
+}
+
+/// Series whose episodes were never fetched, or changed on the panel since, with their image.
+/// Series the panel gives no modification time for are only fetched once.
+pub fn get_series_to_prefetch(
+    source_id: i64,
+    favorites_only: bool,
+) -> Result<Vec<(u64, Option<String>)>> {
+    let sql = get_conn()?;
+    let mut stmt = sql.prepare(
+        r#"
+        SELECT i.series_id, MAX(c.image)
+        FROM series_info i
+        JOIN channels c
+        ON c.source_id = i.source_id AND c.media_type = ?2 AND c.url = CAST(i.series_id AS TEXT)
+        WHERE i.source_id = ?1
+        AND (i.episodes_fetched = 0 OR i.episodes_modified IS NOT i.last_modified)
+        AND (?3 = 0 OR c.favorite = 1)
+        GROUP BY i.series_id
+        ORDER BY MAX(c.favorite) DESC, i.series_id
+        "#,
+    )?;
+    let series = stmt
+        .query_map(params![source_id, media_type::SERIE, favorites_only], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(series)
+}
+
+fn to_sql_like(query: Option<String>) -> String {
+    query.map(|x| format!("%{x}%")).unwrap_or("%".to_string())
+}
+
+pub fn search_group(filters: Filters) -> Result<Vec<Channel>> {
+    let sql = get_conn()?;
+    let (offset, limit) = if filters.page == 0 {
         (0, 100000) // Virtually unlimited for categories
     } else {
         (filters.page as i32 * PAGE_SIZE as i32 - PAGE_SIZE as i32, PAGE_SIZE as i32)
@@ -1240,6 +1978,15 @@ fn row_to_group(row: &Row) -> std::result::Result<Channel, rusqlite::Error> {
         plot: None,
         cast: None,
         director: None,
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        catchup_days: None,
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
     };
     Ok(channel)
 }
@@ -1267,11 +2014,28 @@ fn row_to_channel(row: &Row) -> std::result::Result<Channel, rusqlite::Error> {
         plot: row.get("plot").ok(),
         cast: row.get("cast").ok(),
         director: row.get("director").ok(),
+        tvg_id: row.get("tvg_id").ok(),
+        tvg_chno: row.get("tvg_chno").ok(),
+        tvg_shift: row.get("tvg_shift").ok(),
+        catchup: row.get("catchup").ok(),
+        catchup_source: row.get("catchup_source").ok(),
+        catchup_days: row.get("catchup_days").ok(),
+        tvg_rec: row.get("tvg_rec").ok(),
+        now_program: row_to_channel_program(row, "now"),
+        next_program: row_to_channel_program(row, "next"),
     };
     Ok(channel)
 }
 
 pub fn delete_channels_by_source(tx: &Transaction, source_id: i64) -> Result<()> {
+    // Foreign keys aren't enforced, so options must be removed before their channels
+    tx.execute(
+        r#"
+        DELETE FROM channel_stream_options
+        WHERE channel_id IN (SELECT id FROM channels WHERE source_id = ?)
+    "#,
+        params![source_id],
+    )?;
     tx.execute(
         r#"
         DELETE FROM channels
@@ -1306,6 +2070,13 @@ pub fn delete_groups_by_source(tx: &Transaction, source_id: i64) -> Result<()> {
 
 pub fn delete_source(id: i64) -> Result<()> {
     let sql = get_conn()?;
+    sql.execute(
+        r#"
+        DELETE FROM channel_stream_options
+        WHERE channel_id IN (SELECT id FROM channels WHERE source_id = ?);
+    "#,
+        params![id],
+    )?;
     sql.execute(
         r#"
         DELETE FROM channels
@@ -1327,6 +2098,62 @@ pub fn delete_source(id: i64) -> Result<()> {
     "#,
         params![id],
     )?;
+    sql.execute(
+        r#"
+        DELETE FROM source_http_cache
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM refresh_reports
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM series_info
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM epg_programs
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM epg_fetches
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM recordings
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM epg_channels
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+        DELETE FROM epg_channel_mappings
+        WHERE source_id = ?;
+    "#,
+        params![id],
+    )?;
     let count = sql.execute(
         r#"
         DELETE FROM sources
@@ -1494,6 +2321,15 @@ fn row_to_source(row: &Row) -> std::result::Result<Source, rusqlite::Error> {
         max_streams: row.get("max_streams")?,
         stream_user_agent: row.get("stream_user_agent")?,
         last_updated: row.get("last_updated")?,
+        epg_url: row.get("epg_url").ok().flatten(),
+        epg_offset: row.get("epg_offset").ok().flatten(),
+        server_utc_offset: row.get("server_utc_offset").ok().flatten(),
+        mac_address: row.get("mac_address").ok().flatten(),
+        account_status: row.get("account_status").ok().flatten(),
+        exp_date: row.get("exp_date").ok().flatten(),
+        max_connections: row.get("max_connections").ok().flatten(),
+        active_cons: row.get("active_cons").ok().flatten(),
+        is_trial: row.get("is_trial").ok().flatten(),
     })
 }
 
@@ -1554,6 +2390,15 @@ pub fn get_custom_source(name: String) -> Source {
         max_streams: None,
         stream_user_agent: None,
         last_updated: None,
+        epg_url: None,
+        epg_offset: None,
+        server_utc_offset: None,
+        mac_address: None,
+        account_status: None,
+        exp_date: None,
+        max_connections: None,
+        active_cons: None,
+        is_trial: None,
     }
 }
 
@@ -1826,6 +2671,15 @@ fn row_to_custom_channel(row: &Row) -> Result<CustomChannel, rusqlite::Error> {
             plot: None,
             cast: None,
             director: None,
+            tvg_id: None,
+            tvg_chno: None,
+            tvg_shift: None,
+            catchup: None,
+            catchup_source: None,
+            catchup_days: None,
+            tvg_rec: None,
+            now_program: None,
+            next_program: None,
         },
         headers: Some(ChannelHttpHeaders {
             http_origin: row.get("http_origin")?,
@@ -1889,10 +2743,28 @@ pub fn update_source(source: Source) -> Result<()> {
         let _ = crate::security::save_password(&source.name, password);
     }
 
+    // The url or credentials may have changed, so cached validators no longer apply
+    sql.execute(
+        "DELETE FROM source_http_cache WHERE source_id = ?",
+        params![source.id],
+    )?;
+    // Stored guide times move along with the offset, so nothing has to be downloaded again
+    let previous_offset: Option<i64> = sql.query_row(
+        "SELECT epg_offset FROM sources WHERE id = ?",
+        params![source.id],
+        |row| row.get(0),
+    )?;
+    let shift = source.epg_offset.unwrap_or(0) - previous_offset.unwrap_or(0);
+    if shift != 0 {
+        sql.execute(
+            "UPDATE epg_programs SET start = start + ?1, stop = stop + ?1 WHERE source_id = ?2",
+            params![shift, source.id],
+        )?;
+    }
     sql.execute(
         r#"
         UPDATE sources
-        SET username = ?, password = ?, url = ?, use_tvg_id = ?, user_agent = ?, max_streams = ?, stream_user_agent = ?
+        SET username = ?, password = ?, url = ?, use_tvg_id = ?, user_agent = ?, max_streams = ?, stream_user_agent = ?, epg_url = ?, epg_offset = ?, mac_address = ?
         WHERE id = ?"#,
         params![
             source.username,
@@ -1902,6 +2774,9 @@ pub fn update_source(source: Source) -> Result<()> {
             source.user_agent,
             source.max_streams,
             source.stream_user_agent,
+            source.epg_url,
+            source.epg_offset,
+            source.mac_address,
             source.id
         ],
     )?;
@@ -1915,13 +2790,17 @@ pub fn wipe(tx: &Transaction, id: i64) -> Result<()> {
     Ok(())
 }
 
-pub fn clean_epgs() -> Result<()> {
+/// Drops reminders that already notified for a programme that started, and the ones that
+/// started before `stale_before` without ever notifying
+pub fn clean_epgs(now: i64, stale_before: i64) -> Result<()> {
     let sql = get_conn()?;
-    sql.execute_batch(
+    sql.execute(
         r#"
           DELETE FROM epg
-          WHERE start_timestamp < strftime('%s', 'now')
+          WHERE (notified = 1 AND start_timestamp < ?1)
+          OR start_timestamp < ?2
         "#,
+        params![now, stale_before],
     )?;
     Ok(())
 }
@@ -1929,8 +2808,82 @@ pub fn clean_epgs() -> Result<()> {
 pub fn add_epg(epg: EPGNotify) -> Result<()> {
     let sql = get_conn()?;
     sql.execute(
-        "INSERT INTO epg (epg_id, channel_name, title, start_timestamp) VALUES (?,?,?,?)",
-        params![epg.epg_id, epg.channel_name, epg.title, epg.start_timestamp],
+        r#"
+        INSERT INTO epg (epg_id, channel_name, title, start_timestamp, channel_id, lead_minutes)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (epg_id) DO UPDATE SET lead_minutes = ?6, notified = 0
+        "#,
+        params![
+            epg.epg_id,
+            epg.channel_name,
+            epg.title,
+            epg.start_timestamp,
+            epg.channel_id,
+            epg.lead_minutes.unwrap_or(0)
+        ],
+    )?;
+    Ok(())
+}
+
+/// Reminders that haven't notified yet
+pub fn get_pending_reminders() -> Result<Vec<EPGNotify>> {
+    let sql = get_conn()?;
+    let epgs = sql
+        .prepare("SELECT * FROM epg WHERE notified = 0")?
+        .query_map(params![], row_to_epg)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(epgs)
+}
+
+pub fn set_reminder_notified(epg_id: &str) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute("UPDATE epg SET notified = 1 WHERE epg_id = ?", params![epg_id])?;
+    Ok(())
+}
+
+/// Start of the stored airing of `title` on the channel closest to `start`, within `window` seconds
+pub fn get_rescheduled_start(
+    channel_id: i64,
+    title: &str,
+    start: i64,
+    window: i64,
+) -> Result<Option<i64>> {
+    let sql = get_conn()?;
+    let start = sql
+        .query_row(
+            r#"
+            SELECT start FROM epg_programs
+            WHERE channel_id = ?1 AND title = ?2 AND ABS(start - ?3) <= ?4
+            ORDER BY ABS(start - ?3), origin
+            LIMIT 1
+            "#,
+            params![channel_id, title, start, window],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(start)
+}
+
+/// Moves a reminder to a new start, notifying again if it has become due later. Guide based ids
+/// follow the start, so the reminder still shows as set on the moved programme.
+pub fn reschedule_reminder(epg: &EPGNotify, channel_id: i64, start: i64) -> Result<()> {
+    let epg_id = match epg.epg_id == format!("{}-{}", channel_id, epg.start_timestamp) {
+        true => format!("{}-{}", channel_id, start),
+        false => epg.epg_id.clone(),
+    };
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+        UPDATE OR REPLACE epg
+        SET epg_id = ?1, start_timestamp = ?2,
+            notified = CASE
+                WHEN ?2 - lead_minutes * 60 > CAST(strftime('%s', 'now') AS INTEGER) THEN 0
+                ELSE notified
+            END
+        WHERE epg_id = ?3
+        "#,
+        params![epg_id, start, epg.epg_id],
     )?;
     Ok(())
 }
@@ -1961,124 +2914,582 @@ pub fn get_epg_ids() -> Result<Vec<String>> {
     Ok(epgs)
 }
 
-fn row_to_epg(row: &Row) -> Result<EPGNotify, rusqlite::Error> {
-    Ok(EPGNotify {
-        epg_id: row.get("epg_id")?,
-        channel_name: row.get("channel_name")?,
-        start_timestamp: row.get("start_timestamp")?,
-        title: row.get("title")?,
-    })
-}
-
-pub fn get_preserve(tx: &Transaction, source_id: i64) -> Result<Vec<ChannelPreserve>> {
-    let mut channels: Vec<ChannelPreserve> = tx
-        .prepare(
-            r#"
-              SELECT name, favorite, last_watched, hidden
-              FROM channels
-              WHERE (favorite = 1 OR last_watched IS NOT NULL OR hidden = 1)
-              AND series_id IS NULL
-              AND source_id = ?
-            "#,
-        )?
-        .query_map(params![source_id], row_to_channel_preserve)?
-        .filter_map(Result::ok)
-        .collect();
-
-    let groups: Vec<ChannelPreserve> = tx
-        .prepare(
-            r#"
-              SELECT name, hidden
-              FROM groups
-              WHERE hidden = 1
-              AND source_id = ?
-            "#,
-        )?
-        .query_map(params![source_id], row_to_group_preserve)?
+/// Live channels of a source that guide data can be mapped to, as (id, name, tvg_id)
+pub fn get_epg_channel_targets(
+    tx: &Transaction,
+    source_id: i64,
+) -> Result<Vec<(i64, String, Option<String>)>> {
+    let targets = tx
+        .prepare("SELECT id, name, tvg_id FROM channels WHERE source_id = ? AND media_type = ?")?
+        .query_map(params![source_id, media_type::LIVESTREAM], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
         .filter_map(Result::ok)
         .collect();
-
-    channels.extend(groups);
-    Ok(channels)
-}
-
-fn row_to_channel_preserve(row: &Row) -> Result<ChannelPreserve, rusqlite::Error> {
-    Ok(ChannelPreserve {
-        name: row.get("name")?,
-        favorite: row.get("favorite")?,
-        last_watched: row.get("last_watched")?,
-        hidden: row.get("hidden")?,
-        is_group: false,
-    })
+    Ok(targets)
 }
 
-fn row_to_group_preserve(row: &Row) -> Result<ChannelPreserve, rusqlite::Error> {
-    Ok(ChannelPreserve {
-        name: row.get("name")?,
-        hidden: row.get("hidden")?,
-        favorite: false,
-        last_watched: None,
-        is_group: true,
-    })
+pub fn delete_epg_channels(tx: &Transaction, source_id: i64) -> Result<()> {
+    tx.execute("DELETE FROM epg_channels WHERE source_id = ?", params![source_id])?;
+    Ok(())
 }
 
-pub fn restore_preserve(
+pub fn insert_epg_channel(
     tx: &Transaction,
     source_id: i64,
-    preserve: Vec<ChannelPreserve>,
+    xmltv_id: &str,
+    display_names: &[String],
 ) -> Result<()> {
-    for item in preserve {
-        if item.is_group {
-            tx.execute(
-                r#"
-                  UPDATE groups
-                  SET hidden = ?
-                  WHERE name = ?
-                  AND source_id = ?
-                "#,
-                params![item.hidden, item.name, source_id],
-            )?;
-        } else {
-            tx.execute(
-                r#"
-                  UPDATE channels
-                  SET favorite = ?, last_watched = ?, hidden = ?
-                  WHERE name = ?
-                  AND source_id = ?
-                "#,
-                params![
-                    item.favorite,
-                    item.last_watched,
-                    item.hidden,
-                    item.name,
-                    source_id
-                ],
-            )?;
-        }
-    }
+    tx.execute(
+        "INSERT INTO epg_channels (source_id, xmltv_id, display_names) VALUES (?, ?, ?)",
+        params![source_id, xmltv_id, display_names.join("\n")],
+    )?;
     Ok(())
 }
 
-pub fn analyze(tx: &Transaction) -> Result<()> {
-    tx.execute("ANALYZE;", params![])?;
-    Ok(())
+/// Channels of the source's last imported guide, with their display names
+pub fn get_epg_channels(source_id: i64) -> Result<Vec<(String, Vec<String>)>> {
+    let sql = get_conn()?;
+    let channels = sql
+        .prepare("SELECT xmltv_id, display_names FROM epg_channels WHERE source_id = ? ORDER BY id")?
+        .query_map(params![source_id], |row| {
+            let display_names: Option<String> = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                display_names
+                    .unwrap_or_default()
+                    .lines()
+                    .filter(|name| !name.is_empty())
+                    .map(|name| name.to_string())
+                    .collect(),
+            ))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(channels)
 }
 
-pub fn add_last_watched(id: i64) -> Result<()> {
+/// Guide channel the user picked for each mapped channel of a source, None meaning no guide
+pub fn get_epg_channel_mappings(
+    tx: &Transaction,
+    source_id: i64,
+) -> Result<HashMap<i64, Option<String>>> {
+    let mappings = tx
+        .prepare("SELECT channel_id, xmltv_id FROM epg_channel_mappings WHERE source_id = ?")?
+        .query_map(params![source_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(mappings)
+}
+
+pub fn set_epg_channel_mapping(channel_id: i64, xmltv_id: Option<&str>) -> Result<()> {
     let sql = get_conn()?;
-    sql.execute(
+    let count = sql.execute(
         r#"
-          UPDATE channels
-          SET last_watched = strftime('%s', 'now')
-          WHERE id = ?
+        INSERT INTO epg_channel_mappings (channel_id, source_id, xmltv_id)
+        SELECT id, source_id, ? FROM channels WHERE id = ?
+        ON CONFLICT (channel_id) DO UPDATE SET xmltv_id = excluded.xmltv_id
         "#,
-        params![id],
+        params![xmltv_id, channel_id],
     )?;
+    if count == 0 {
+        anyhow::bail!("No channel with id {}", channel_id);
+    }
+    Ok(())
+}
+
+pub fn delete_epg_channel_mapping(channel_id: i64) -> Result<()> {
+    let sql = get_conn()?;
     sql.execute(
-        r#"
-		  UPDATE channels
-          SET last_watched = NULL
-          WHERE last_watched IS NOT NULL
+        "DELETE FROM epg_channel_mappings WHERE channel_id = ?",
+        params![channel_id],
+    )?;
+    Ok(())
+}
+
+/// Seconds each live channel of a source shifts its guide by, from its `tvg-shift` hours
+pub fn get_epg_channel_shifts(tx: &Transaction, source_id: i64) -> Result<HashMap<i64, i64>> {
+    let shifts = tx
+        .prepare(
+            "SELECT id, tvg_shift FROM channels WHERE source_id = ? AND media_type = ? AND tvg_shift IS NOT NULL",
+        )?
+        .query_map(params![source_id, media_type::LIVESTREAM], |row| {
+            let hours: f64 = row.get(1)?;
+            Ok((row.get(0)?, (hours * 3600.0).round() as i64))
+        })?
+        .filter_map(Result::ok)
+        .filter(|(_, seconds)| *seconds != 0)
+        .collect();
+    Ok(shifts)
+}
+
+pub fn delete_epg_programs(tx: &Transaction, source_id: i64, origin: u8) -> Result<()> {
+    tx.execute(
+        "DELETE FROM epg_programs WHERE source_id = ? AND origin = ?",
+        params![source_id, origin],
+    )?;
+    Ok(())
+}
+
+pub fn insert_epg_program(tx: &Transaction, program: &EPGProgram) -> Result<()> {
+    tx.execute(
+        r#"
+        INSERT INTO epg_programs (channel_id, source_id, start, stop, title, subtitle, description, episode_num, category, icon, origin, has_archive, server_start)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        params![
+            program.channel_id,
+            program.source_id,
+            program.start,
+            program.stop,
+            program.title,
+            program.subtitle,
+            program.description,
+            program.episode_num,
+            program.category,
+            program.icon,
+            program.origin,
+            program.has_archive,
+            program.server_start
+        ],
+    )?;
+    Ok(())
+}
+
+/// Replaces the cached Xtream listing of a channel and records until when it is fresh
+pub fn set_xtream_epg_programs(
+    tx: &Transaction,
+    channel_id: i64,
+    source_id: i64,
+    programs: &[EPGProgram],
+    expires_at: i64,
+) -> Result<()> {
+    tx.execute(
+        "DELETE FROM epg_programs WHERE channel_id = ? AND origin = ?",
+        params![channel_id, epg_origin::XTREAM],
+    )?;
+    for program in programs {
+        insert_epg_program(tx, program)?;
+    }
+    tx.execute(
+        r#"
+        INSERT INTO epg_fetches (channel_id, source_id, fetched_at, expires_at)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (channel_id)
+        DO UPDATE SET source_id = excluded.source_id, fetched_at = excluded.fetched_at, expires_at = excluded.expires_at
+        "#,
+        params![channel_id, source_id, chrono::Utc::now().timestamp(), expires_at],
+    )?;
+    Ok(())
+}
+
+pub fn get_epg_fetch_expiry(channel_id: i64) -> Result<Option<i64>> {
+    let sql = get_conn()?;
+    Ok(sql
+        .query_row(
+            "SELECT expires_at FROM epg_fetches WHERE channel_id = ?",
+            params![channel_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub fn has_epg_programs(channel_id: i64, origin: u8) -> Result<bool> {
+    let sql = get_conn()?;
+    Ok(sql.query_row(
+        "SELECT EXISTS (SELECT 1 FROM epg_programs WHERE channel_id = ? AND origin = ?)",
+        params![channel_id, origin],
+        |row| row.get(0),
+    )?)
+}
+
+/// Favorited live channels of enabled Xtream sources whose cached guide is missing or expired.
+/// Channels with an imported XMLTV guide are left out, as that guide wins.
+pub fn get_epg_prefetch_channels(now: i64) -> Result<Vec<Channel>> {
+    let sql = get_conn()?;
+    let channels = sql
+        .prepare(
+            r#"
+            SELECT c.* FROM channels c
+            JOIN sources s ON s.id = c.source_id
+            LEFT JOIN epg_fetches f ON f.channel_id = c.id
+            WHERE c.favorite = 1
+            AND c.media_type = ?1
+            AND c.stream_id IS NOT NULL
+            AND s.enabled = 1
+            AND s.source_type = ?2
+            AND (f.expires_at IS NULL OR f.expires_at <= ?3)
+            AND NOT EXISTS (
+                SELECT 1 FROM epg_programs x WHERE x.channel_id = c.id AND x.origin = ?4
+            )
+            "#,
+        )?
+        .query_map(
+            params![media_type::LIVESTREAM, source_type::XTREAM, now, epg_origin::XMLTV],
+            row_to_channel,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(channels)
+}
+
+/// Programmes of a channel that haven't ended by `from` or can still be replayed, in airing order.
+/// Xtream listings are skipped when the channel has an XMLTV guide, as when searching the guide.
+pub fn get_epg_programs(channel_id: i64, from: i64) -> Result<Vec<EPGProgram>> {
+    let sql = get_conn()?;
+    let programs = sql
+        .prepare(
+            r#"
+            SELECT * FROM epg_programs p
+            WHERE p.channel_id = ?1 AND (p.stop > ?2 OR p.has_archive = 1)
+            AND NOT (p.origin = ?3 AND EXISTS (
+                SELECT 1 FROM epg_programs x WHERE x.channel_id = p.channel_id AND x.origin = ?4
+            ))
+            ORDER BY p.start
+            "#,
+        )?
+        .query_map(
+            params![channel_id, from, epg_origin::XTREAM, epg_origin::XMLTV],
+            row_to_epg_program,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(programs)
+}
+
+/// Programmes the panel keeps an archive of that started between `from` and `to`, in airing order
+pub fn get_xtream_archive_programs(channel_id: i64, from: i64, to: i64) -> Result<Vec<EPGProgram>> {
+    let sql = get_conn()?;
+    let programs = sql
+        .prepare(
+            r#"
+            SELECT * FROM epg_programs
+            WHERE channel_id = ? AND origin = ? AND has_archive = 1
+            AND start >= ? AND start < ?
+            ORDER BY start
+            "#,
+        )?
+        .query_map(
+            params![channel_id, epg_origin::XTREAM, from, to],
+            row_to_epg_program,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(programs)
+}
+
+/// Visible live channels of the export's sources and groups, plus favorites when asked for,
+/// with their group names, in channel number order. Stalker portal channels are left out
+pub fn get_export_channels(export: &PlaylistExport) -> Result<Vec<Channel>> {
+    let sql = get_conn()?;
+    let mut filters = Vec::with_capacity(3);
+    let mut params: Vec<&dyn rusqlite::ToSql> = Vec::new();
+    if !export.source_ids.is_empty() {
+        filters.push(format!(
+            "c.source_id IN ({})",
+            generate_placeholders(export.source_ids.len())
+        ));
+        params.extend(export.source_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    if !export.group_ids.is_empty() {
+        filters.push(format!(
+            "c.group_id IN ({})",
+            generate_placeholders(export.group_ids.len())
+        ));
+        params.extend(export.group_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+    if export.favorites {
+        filters.push("c.favorite = 1".to_string());
+    }
+    if filters.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = format!(
+        r#"
+        SELECT c.*, g.name AS group_name FROM channels c
+        JOIN sources s ON s.id = c.source_id
+        LEFT JOIN groups g ON g.id = c.group_id
+        WHERE c.media_type = {}
+        AND s.source_type != {}
+        AND (c.hidden IS NULL OR c.hidden = 0)
+        AND ({})
+        ORDER BY c.tvg_chno IS NULL, c.tvg_chno, c.source_id, c.id
+        "#,
+        media_type::LIVESTREAM,
+        source_type::STALKER,
+        filters.join(" OR ")
+    );
+    let channels = sql
+        .prepare(&query)?
+        .query_map(params_from_iter(params), |row| {
+            let mut channel = row_to_channel(row)?;
+            channel.group = row.get("group_name")?;
+            Ok(channel)
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(channels)
+}
+
+/// Programmes of the given channels overlapping the window, by channel and airing order. Like
+/// now/next, a channel's XMLTV listings win over cached Xtream ones covering the same window.
+pub fn get_epg_programs_in_window(
+    channel_ids: &[i64],
+    start: i64,
+    end: i64,
+) -> Result<Vec<EPGProgram>> {
+    if channel_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = get_conn()?;
+    let query = format!(
+        r#"
+        SELECT * FROM epg_programs p
+        WHERE p.stop > ?1 AND p.start < ?2
+        AND p.channel_id IN ({})
+        AND p.origin = (
+            SELECT MIN(x.origin) FROM epg_programs x
+            WHERE x.channel_id = p.channel_id AND x.stop > ?1 AND x.start < ?2
+        )
+        ORDER BY p.channel_id, p.start
+        "#,
+        generate_placeholders(channel_ids.len())
+    );
+    // The window comes first, so the list's placeholders are numbered after ?1 and ?2
+    let mut params: Vec<&dyn rusqlite::ToSql> = Vec::with_capacity(channel_ids.len() + 2);
+    params.push(&start);
+    params.push(&end);
+    for id in channel_ids {
+        params.push(id);
+    }
+    let programs = sql
+        .prepare(&query)?
+        .query_map(params_from_iter(params), row_to_epg_program)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(programs)
+}
+
+/// Airings matching every word of `query` (as prefixes) in their title or description, soonest
+/// first, along with their channel's name and image. Ended programmes only match while they can
+/// still be replayed, and Xtream listings are skipped for channels that have an XMLTV guide.
+pub fn search_epg_programs(
+    query: &str,
+    from: i64,
+    limit: u32,
+) -> Result<Vec<(EPGProgram, String, Option<String>)>> {
+    let Some(fts_query) = to_fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    let sql = get_conn()?;
+    let results = sql
+        .prepare(
+            r#"
+            SELECT p.*, c.name AS channel_name, c.image AS channel_image
+            FROM epg_programs_fts
+            JOIN epg_programs p ON p.id = epg_programs_fts.rowid
+            JOIN channels c ON c.id = p.channel_id
+            JOIN sources s ON s.id = p.source_id
+            WHERE epg_programs_fts MATCH ?1
+            AND (p.stop > ?2 OR p.has_archive = 1)
+            AND s.enabled = 1
+            AND COALESCE(c.hidden, 0) = 0
+            AND NOT (p.origin = ?3 AND EXISTS (
+                SELECT 1 FROM epg_programs x WHERE x.channel_id = p.channel_id AND x.origin = ?4
+            ))
+            ORDER BY p.start, c.name
+            LIMIT ?5
+            "#,
+        )?
+        .query_map(
+            params![fts_query, from, epg_origin::XTREAM, epg_origin::XMLTV, limit],
+            |row| {
+                Ok((
+                    row_to_epg_program(row)?,
+                    row.get("channel_name")?,
+                    row.get("channel_image")?,
+                ))
+            },
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(results)
+}
+
+/// Turns user input into an FTS5 query where every word must match as a prefix. Words are quoted
+/// so characters like `-` or `:` are searched for rather than parsed as query syntax.
+fn to_fts_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(words.join(" "))
+}
+
+fn row_to_epg_program(row: &Row) -> Result<EPGProgram, rusqlite::Error> {
+    Ok(EPGProgram {
+        id: row.get("id")?,
+        channel_id: row.get("channel_id")?,
+        source_id: row.get("source_id")?,
+        start: row.get("start")?,
+        stop: row.get("stop")?,
+        title: row.get("title")?,
+        subtitle: row.get("subtitle")?,
+        description: row.get("description")?,
+        episode_num: row.get("episode_num")?,
+        category: row.get("category")?,
+        icon: row.get("icon")?,
+        origin: row.get::<_, Option<u8>>("origin")?.unwrap_or(epg_origin::XMLTV),
+        has_archive: row.get::<_, Option<bool>>("has_archive")?.unwrap_or(false),
+        server_start: row.get("server_start")?,
+    })
+}
+
+fn row_to_epg(row: &Row) -> Result<EPGNotify, rusqlite::Error> {
+    Ok(EPGNotify {
+        epg_id: row.get("epg_id")?,
+        channel_name: row.get("channel_name")?,
+        start_timestamp: row.get("start_timestamp")?,
+        title: row.get("title")?,
+        channel_id: row.get("channel_id")?,
+        lead_minutes: row.get("lead_minutes")?,
+    })
+}
+
+pub fn get_preserve(tx: &Transaction, source_id: i64) -> Result<Vec<ChannelPreserve>> {
+    let mut channels: Vec<ChannelPreserve> = tx
+        .prepare(
+            r#"
+              SELECT name, favorite, last_watched, hidden, tvg_id
+              FROM channels
+              WHERE (favorite = 1 OR last_watched IS NOT NULL OR hidden = 1)
+              AND series_id IS NULL
+              AND source_id = ?
+            "#,
+        )?
+        .query_map(params![source_id], row_to_channel_preserve)?
+        .filter_map(Result::ok)
+        .collect();
+
+    let groups: Vec<ChannelPreserve> = tx
+        .prepare(
+            r#"
+              SELECT name, hidden
+              FROM groups
+              WHERE hidden = 1
+              AND source_id = ?
+            "#,
+        )?
+        .query_map(params![source_id], row_to_group_preserve)?
+        .filter_map(Result::ok)
+        .collect();
+
+    channels.extend(groups);
+    Ok(channels)
+}
+
+fn row_to_channel_preserve(row: &Row) -> Result<ChannelPreserve, rusqlite::Error> {
+    Ok(ChannelPreserve {
+        name: row.get("name")?,
+        favorite: row.get("favorite")?,
+        last_watched: row.get("last_watched")?,
+        hidden: row.get("hidden")?,
+        is_group: false,
+        tvg_id: row.get("tvg_id")?,
+    })
+}
+
+fn row_to_group_preserve(row: &Row) -> Result<ChannelPreserve, rusqlite::Error> {
+    Ok(ChannelPreserve {
+        name: row.get("name")?,
+        hidden: row.get("hidden")?,
+        favorite: false,
+        last_watched: None,
+        is_group: true,
+        tvg_id: None,
+    })
+}
+
+pub fn restore_preserve(
+    tx: &Transaction,
+    source_id: i64,
+    preserve: Vec<ChannelPreserve>,
+) -> Result<()> {
+    for item in preserve {
+        if item.is_group {
+            tx.execute(
+                r#"
+                  UPDATE groups
+                  SET hidden = ?
+                  WHERE name = ?
+                  AND source_id = ?
+                "#,
+                params![item.hidden, item.name, source_id],
+            )?;
+        } else {
+            let updated = tx.execute(
+                r#"
+                  UPDATE channels
+                  SET favorite = ?, last_watched = ?, hidden = ?
+                  WHERE name = ?
+                  AND source_id = ?
+                "#,
+                params![
+                    item.favorite,
+                    item.last_watched,
+                    item.hidden,
+                    item.name,
+                    source_id
+                ],
+            )?;
+            // The provider may have renamed the channel, fall back on its tvg-id
+            if updated == 0 && item.tvg_id.is_some() {
+                tx.execute(
+                    r#"
+                      UPDATE channels
+                      SET favorite = ?, last_watched = ?, hidden = ?
+                      WHERE tvg_id = ?
+                      AND source_id = ?
+                    "#,
+                    params![
+                        item.favorite,
+                        item.last_watched,
+                        item.hidden,
+                        item.tvg_id,
+                        source_id
+                    ],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn analyze(tx: &Transaction) -> Result<()> {
+    tx.execute("ANALYZE;", params![])?;
+    Ok(())
+}
+
+pub fn add_last_watched(id: i64) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+          UPDATE channels
+          SET last_watched = strftime('%s', 'now')
+          WHERE id = ?
+        "#,
+        params![id],
+    )?;
+    sql.execute(
+        r#"
+		  UPDATE channels
+          SET last_watched = NULL
+          WHERE last_watched IS NOT NULL
 		  AND id NOT IN (
 				SELECT id
 				FROM channels
@@ -2124,6 +3535,169 @@ pub fn find_all_episodes_after(channel: &Channel) -> Result<Vec<String>> {
     Ok(urls)
 }
 
+pub fn set_source_epg_url(tx: &Transaction, source_id: i64, epg_url: &str) -> Result<()> {
+    tx.execute(
+        "UPDATE sources SET epg_url = ? WHERE id = ?",
+        params![epg_url, source_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_source_server_utc_offset(source_id: i64, offset: i64) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        "UPDATE sources SET server_utc_offset = ? WHERE id = ?",
+        params![offset, source_id],
+    )?;
+    Ok(())
+}
+
+/// Saves the account the panel reported along with the warning it calls for, returning the
+/// warning saved before. The stream limit follows the panel's unless it was changed by hand.
+pub fn set_source_account(
+    source_id: i64,
+    account: &SourceAccount,
+    warning: Option<&str>,
+) -> Result<Option<String>> {
+    let mut sql = get_conn()?;
+    let tx = sql.transaction()?;
+    let previous_warning: Option<String> = tx.query_row(
+        "SELECT account_warning FROM sources WHERE id = ?",
+        params![source_id],
+        |row| row.get(0),
+    )?;
+    // 0 stands for unlimited connections
+    if let Some(max_connections) = account.max_connections.filter(|max| *max > 0) {
+        tx.execute(
+            r#"
+            UPDATE sources SET max_streams = ?1
+            WHERE id = ?2 AND (max_streams IS NULL OR max_streams = max_connections)"#,
+            params![max_connections.min(u8::MAX as i64), source_id],
+        )?;
+    }
+    tx.execute(
+        r#"
+        UPDATE sources
+        SET account_status = ?, exp_date = ?, max_connections = ?, active_cons = ?, is_trial = ?, account_warning = ?
+        WHERE id = ?"#,
+        params![
+            account.status,
+            account.exp_date,
+            account.max_connections,
+            account.active_cons,
+            account.is_trial,
+            warning,
+            source_id
+        ],
+    )?;
+    tx.commit()?;
+    Ok(previous_warning)
+}
+
+pub fn get_http_validators(source_id: i64, resource: &str) -> Result<Option<HttpValidators>> {
+    let sql = get_conn()?;
+    Ok(sql
+        .query_row(
+            "SELECT etag, last_modified FROM source_http_cache WHERE source_id = ? AND resource = ?",
+            params![source_id, resource],
+            |row| {
+                Ok(HttpValidators {
+                    etag: row.get("etag")?,
+                    last_modified: row.get("last_modified")?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+pub fn set_http_validators(
+    tx: &Transaction,
+    source_id: i64,
+    resource: &str,
+    validators: &HttpValidators,
+) -> Result<()> {
+    if validators.etag.is_none() && validators.last_modified.is_none() {
+        tx.execute(
+            "DELETE FROM source_http_cache WHERE source_id = ? AND resource = ?",
+            params![source_id, resource],
+        )?;
+        return Ok(());
+    }
+    tx.execute(
+        r#"
+        INSERT INTO source_http_cache (source_id, resource, etag, last_modified)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (source_id, resource)
+        DO UPDATE SET etag = excluded.etag, last_modified = excluded.last_modified
+        "#,
+        params![source_id, resource, validators.etag, validators.last_modified],
+    )?;
+    Ok(())
+}
+
+/// Saves a refresh report, keeping only the latest ones of the source
+pub fn insert_refresh_report(source_id: i64, report: &RefreshReport) -> Result<i64> {
+    let mut sql = get_conn()?;
+    let tx = sql.transaction()?;
+    let id = tx.query_row(
+        r#"
+        INSERT INTO refresh_reports (source_id, started_at, success, report)
+        VALUES (?1, ?2, ?3, ?4)
+        RETURNING id
+        "#,
+        params![
+            source_id,
+            report.started_at,
+            report.success,
+            serde_json::to_string(report)?
+        ],
+        |row| row.get(0),
+    )?;
+    tx.execute(
+        r#"
+        DELETE FROM refresh_reports
+        WHERE source_id = ?1
+        AND id NOT IN (
+            SELECT id FROM refresh_reports
+            WHERE source_id = ?1
+            ORDER BY started_at DESC, id DESC
+            LIMIT ?2
+        )
+        "#,
+        params![source_id, REFRESH_REPORTS_KEPT],
+    )?;
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Latest refresh reports of a source, newest first
+pub fn get_refresh_reports(source_id: i64) -> Result<Vec<RefreshReport>> {
+    let sql = get_conn()?;
+    let reports = sql
+        .prepare(
+            r#"
+            SELECT id, report FROM refresh_reports
+            WHERE source_id = ?
+            ORDER BY started_at DESC, id DESC
+            "#,
+        )?
+        .query_map(params![source_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .filter_map(Result::ok)
+        .filter_map(|(id, report)| {
+            serde_json::from_str::<RefreshReport>(&report)
+                .map(|mut report| {
+                    report.id = Some(id);
+                    report
+                })
+                .map_err(|e| log(format!("Failed to read refresh report {id}: {:?}", e)))
+                .ok()
+        })
+        .collect();
+    Ok(reports)
+}
+
 pub fn update_source_last_updated(source_id: i64) -> Result<()> {
     let sql = get_conn()?;
     sql.execute(
@@ -2361,3 +3935,268 @@ fn row_to_omdb_cache(row: &Row) -> Result<OmdbCachedMovie, rusqlite::Error> {
         fetched_at: row.get("fetched_at")?,
     })
 }
+
+pub fn get_channel_by_id(id: i64) -> Result<Channel> {
+    let sql = get_conn()?;
+    let channel = sql
+        .query_row("SELECT * FROM channels WHERE id = ?", params![id], row_to_channel)
+        .optional()?
+        .context("Channel no longer exists")?;
+    Ok(channel)
+}
+
+pub fn insert_recording(recording: &Recording) -> Result<i64> {
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+        INSERT INTO recordings (channel_id, source_id, channel_name, title, subtitle, episode_num, start, stop, pre_padding, post_padding, status, rule_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        params![
+            recording.channel_id,
+            recording.source_id,
+            recording.channel_name,
+            recording.title,
+            recording.subtitle,
+            recording.episode_num,
+            recording.start,
+            recording.stop,
+            recording.pre_padding,
+            recording.post_padding,
+            recording.status,
+            recording.rule_id
+        ],
+    )?;
+    Ok(sql.last_insert_rowid())
+}
+
+/// Every recording, upcoming ones first then past ones from the most recent
+pub fn get_recordings() -> Result<Vec<Recording>> {
+    let sql = get_conn()?;
+    let recordings = sql
+        .prepare(
+            r#"
+            SELECT * FROM recordings
+            ORDER BY status = ? DESC, CASE WHEN status = ? THEN start ELSE -start END
+            "#,
+        )?
+        .query_map(
+            params![recording_status::SCHEDULED, recording_status::SCHEDULED],
+            row_to_recording,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(recordings)
+}
+
+pub fn get_recording(id: i64) -> Result<Recording> {
+    let sql = get_conn()?;
+    let recording = sql
+        .query_row("SELECT * FROM recordings WHERE id = ?", params![id], row_to_recording)
+        .optional()?
+        .context("Recording not found")?;
+    Ok(recording)
+}
+
+pub fn get_scheduled_recordings() -> Result<Vec<Recording>> {
+    let sql = get_conn()?;
+    let recordings = sql
+        .prepare("SELECT * FROM recordings WHERE status = ? ORDER BY start")?
+        .query_map(params![recording_status::SCHEDULED], row_to_recording)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(recordings)
+}
+
+pub fn set_recording_status(
+    id: i64,
+    status: u8,
+    file_path: Option<&str>,
+    error: Option<&str>,
+) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+        UPDATE recordings
+        SET status = ?, file_path = COALESCE(?, file_path), error = ?
+        WHERE id = ?
+        "#,
+        params![status, file_path, error, id],
+    )?;
+    Ok(())
+}
+
+pub fn delete_recording(id: i64) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute("DELETE FROM recordings WHERE id = ?", params![id])?;
+    Ok(())
+}
+
+/// Fails recordings the app was closed in the middle of
+pub fn fail_interrupted_recordings() -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        "UPDATE recordings SET status = ?, error = ? WHERE status = ?",
+        params![
+            recording_status::FAILED,
+            "The app was closed while recording",
+            recording_status::RECORDING
+        ],
+    )?;
+    Ok(())
+}
+
+/// Fails scheduled recordings whose window ended without them starting
+pub fn fail_missed_recordings(now: i64) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+        UPDATE recordings SET status = ?, error = ?
+        WHERE status = ? AND stop + COALESCE(post_padding, 0) * 60 <= ?
+        "#,
+        params![
+            recording_status::FAILED,
+            "The app wasn't running at the scheduled time",
+            recording_status::SCHEDULED,
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+fn row_to_recording(row: &Row) -> Result<Recording, rusqlite::Error> {
+    Ok(Recording {
+        id: row.get("id")?,
+        channel_id: row.get("channel_id")?,
+        source_id: row.get("source_id")?,
+        channel_name: row.get("channel_name")?,
+        title: row.get("title")?,
+        subtitle: row.get("subtitle")?,
+        episode_num: row.get("episode_num")?,
+        start: row.get("start")?,
+        stop: row.get("stop")?,
+        pre_padding: row.get("pre_padding")?,
+        post_padding: row.get("post_padding")?,
+        status: row.get("status")?,
+        file_path: row.get("file_path")?,
+        error: row.get("error")?,
+        rule_id: row.get("rule_id")?,
+    })
+}
+
+pub fn insert_recording_rule(rule: &RecordingRule) -> Result<i64> {
+    let sql = get_conn()?;
+    sql.execute(
+        r#"
+        INSERT INTO recording_rules (title, channel_id, pre_padding, post_padding, enabled)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+        params![
+            rule.title,
+            rule.channel_id,
+            rule.pre_padding,
+            rule.post_padding,
+            rule.enabled
+        ],
+    )?;
+    Ok(sql.last_insert_rowid())
+}
+
+pub fn get_recording_rules() -> Result<Vec<RecordingRule>> {
+    let sql = get_conn()?;
+    let rules = sql
+        .prepare("SELECT * FROM recording_rules ORDER BY title")?
+        .query_map(params![], row_to_recording_rule)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(rules)
+}
+
+pub fn set_recording_rule_enabled(id: i64, enabled: bool) -> Result<()> {
+    let sql = get_conn()?;
+    sql.execute(
+        "UPDATE recording_rules SET enabled = ? WHERE id = ?",
+        params![enabled, id],
+    )?;
+    Ok(())
+}
+
+/// Deletes a rule along with the recordings it scheduled that haven't started yet
+pub fn delete_recording_rule(id: i64) -> Result<()> {
+    let mut sql = get_conn()?;
+    let tx = sql.transaction()?;
+    tx.execute(
+        "DELETE FROM recordings WHERE rule_id = ? AND status IN (?, ?)",
+        params![id, recording_status::SCHEDULED, recording_status::CANCELLED],
+    )?;
+    tx.execute("DELETE FROM recording_rules WHERE id = ?", params![id])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Upcoming programmes matching a rule that no recording exists for yet, soonest first. Xtream
+/// listings are skipped for channels that have an XMLTV guide, as when searching the guide.
+pub fn get_recording_rule_candidates(rule: &RecordingRule, from: i64) -> Result<Vec<EPGProgram>> {
+    let sql = get_conn()?;
+    let programs = sql
+        .prepare(
+            r#"
+            SELECT p.* FROM epg_programs p
+            WHERE p.start > ?1
+            AND lower(p.title) = lower(?2)
+            AND (?3 IS NULL OR p.channel_id = ?3)
+            AND NOT (p.origin = ?4 AND EXISTS (
+                SELECT 1 FROM epg_programs x WHERE x.channel_id = p.channel_id AND x.origin = ?5
+            ))
+            AND NOT EXISTS (
+                SELECT 1 FROM recordings r WHERE r.channel_id = p.channel_id AND r.start = p.start
+            )
+            ORDER BY p.start
+            "#,
+        )?
+        .query_map(
+            params![from, rule.title, rule.channel_id, epg_origin::XTREAM, epg_origin::XMLTV],
+            row_to_epg_program,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(programs)
+}
+
+/// Whether an episode was recorded or is set to be, known by its episode number or else its
+/// subtitle. Airings with neither are only known by their start, which catches the same airing
+/// on another copy of the channel. Failed recordings don't count, so another airing can make up
+/// for them.
+pub fn has_episode_recording(
+    title: &str,
+    episode_num: Option<&str>,
+    subtitle: Option<&str>,
+    start: i64,
+) -> Result<bool> {
+    let sql = get_conn()?;
+    let exists = sql.query_row(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM recordings
+            WHERE lower(title) = lower(?1) AND status != ?2
+            AND ((?3 IS NOT NULL AND episode_num = ?3)
+                OR (?3 IS NULL AND ?4 IS NOT NULL AND lower(subtitle) = lower(?4))
+                OR (?3 IS NULL AND ?4 IS NULL AND start = ?5))
+        )
+        "#,
+        params![title, recording_status::FAILED, episode_num, subtitle, start],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+fn row_to_recording_rule(row: &Row) -> Result<RecordingRule, rusqlite::Error> {
+    Ok(RecordingRule {
+        id: row.get("id")?,
+        title: row.get("title")?,
+        channel_id: row.get("channel_id")?,
+        pre_padding: row.get("pre_padding")?,
+        post_padding: row.get("post_padding")?,
+        enabled: row.get("enabled")?,
+    })
+}
@@ -0,0 +1,928 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+//! Stalker middleware client, as used by MAG set-top box portals. The portal hands out a token
+//! for the box's MAC address, then lists channels, movies and series page by page. Stored
+//! channels keep the portal's command instead of a url, as commands only become playable
+//! through `create_link` right before playing.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{Context, Result};
+use reqwest::{Client, StatusCode};
+use rusqlite::Transaction;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use tauri::Emitter;
+use url::Url;
+
+use crate::{
+    log, media_type, source_type,
+    sql::{self, insert_season},
+    types::{Channel, RefreshReport, Season, Source},
+    utils::{
+        add_refresh_failed_action, add_refresh_skipped_line, check_refresh_drop,
+        get_refresh_drop_threshold, get_unique_key, set_refresh_report_counts,
+    },
+    xtream::{get_serde_json_f32, get_serde_json_i64, get_serde_json_string, get_serde_json_u64},
+};
+
+const MAG_USER_AGENT: &str = "Mozilla/5.0 (QtEmbedded; U; Linux; C) AppleWebKit/533.3 (KHTML, like Gecko) MAG200 stbapp ver: 2 rev: 250 Safari/533.3";
+const MAG_X_USER_AGENT: &str = "Model: MAG250; Link: WiFi";
+const AUTHORIZATION_FAILED: &str = "Authorization failed";
+/// Portals that keep reporting more items than they serve would otherwise be paged forever
+const MAX_PAGES: u32 = 1000;
+const ALL_CATEGORIES: &str = "*";
+const ITV: &str = "itv";
+const VOD: &str = "vod";
+const SERIES: &str = "series";
+/// Prefixes portals put in front of stream urls, meant for the box's player
+const CMD_PREFIXES: [&str; 3] = ["ffmpeg ", "ffrt ", "auto "];
+
+/// Tokens of the sources, so playing a channel doesn't need a handshake every time
+static TOKENS: LazyLock<Mutex<HashMap<i64, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Deserialize, Debug)]
+struct StalkerResponse<T> {
+    js: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct StalkerToken {
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct StalkerCategory {
+    #[serde(default)]
+    id: Value,
+    title: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StalkerPage {
+    #[serde(default)]
+    total_items: Value,
+    #[serde(default)]
+    data: Vec<StalkerItem>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct StalkerItem {
+    #[serde(default)]
+    id: Value,
+    name: Option<String>,
+    #[serde(default)]
+    number: Value,
+    cmd: Option<String>,
+    #[serde(default)]
+    tv_genre_id: Value,
+    #[serde(default)]
+    category_id: Value,
+    xmltv_id: Option<String>,
+    logo: Option<String>,
+    screenshot_uri: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    year: Value,
+    director: Option<String>,
+    actors: Option<String>,
+    #[serde(default)]
+    rating_imdb: Value,
+    genres_str: Option<String>,
+    /// Episode numbers, when the item is a season of a series
+    #[serde(default)]
+    series: Vec<Value>,
+}
+
+/// One of the portal's lists, as fetched during a refresh
+struct StalkerList {
+    kind: &'static str,
+    stream_type: u8,
+    description: &'static str,
+    /// Category titles by id
+    categories: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StalkerLink {
+    cmd: String,
+}
+
+pub struct StalkerClient {
+    client: Client,
+    api_url: Url,
+    referer: String,
+    mac: String,
+    source_id: Option<i64>,
+    token: Option<String>,
+}
+
+impl StalkerClient {
+    pub fn new(source: &Source) -> Result<Self> {
+        let portal_url = source.url.as_deref().context("Missing portal URL")?.trim();
+        let mac = normalize_mac(
+            source
+                .mac_address
+                .as_deref()
+                .context("Missing MAC address")?,
+        )?;
+        let user_agent = source
+            .user_agent
+            .as_deref()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or(MAG_USER_AGENT);
+        let token = source.id.and_then(|id| {
+            TOKENS
+                .lock()
+                .ok()
+                .and_then(|tokens| tokens.get(&id).cloned())
+        });
+        Ok(StalkerClient {
+            client: Client::builder().user_agent(user_agent).build()?,
+            api_url: get_api_url(portal_url)?,
+            referer: portal_url.to_string(),
+            mac,
+            source_id: source.id,
+            token,
+        })
+    }
+
+    /// Asks the portal for a new token for the MAC address
+    pub async fn handshake(&mut self) -> Result<()> {
+        self.token = None;
+        let token = self
+            .request::<StalkerToken>("stb", "handshake", &[("token", "")])
+            .await?
+            .context("The portal refused the MAC address")?
+            .token;
+        self.token = Some(token.clone());
+        // Some portals only accept the token once the box has sent its profile
+        if let Err(e) = self
+            .request::<Value>("stb", "get_profile", &[("hd", "1")])
+            .await
+        {
+            log::log(format!("Stalker get_profile failed: {:?}", e));
+        }
+        if let (Some(id), Ok(mut tokens)) = (self.source_id, TOKENS.lock()) {
+            tokens.insert(id, token);
+        }
+        Ok(())
+    }
+
+    /// Makes a portal request, getting a new token once when the current one is refused
+    async fn call<T: DeserializeOwned>(
+        &mut self,
+        kind: &str,
+        action: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        if self.token.is_none() {
+            self.handshake().await?;
+        } else if let Some(data) = self.request(kind, action, params).await? {
+            return Ok(data);
+        } else {
+            self.handshake().await?;
+        }
+        self.request(kind, action, params)
+            .await?
+            .with_context(|| format!("{AUTHORIZATION_FAILED} for {kind}:{action}"))
+    }
+
+    /// Returns None when the portal refused the token
+    async fn request<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        action: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Option<T>> {
+        let mut url = self.api_url.clone();
+        url.query_pairs_mut()
+            .append_pair("type", kind)
+            .append_pair("action", action)
+            .extend_pairs(params)
+            .append_pair("JsHttpRequest", "1-xml");
+        let mut request = self
+            .client
+            .get(url)
+            .header("X-User-Agent", MAG_X_USER_AGENT)
+            .header("Referer", &self.referer)
+            .header("Cookie", get_cookie(&self.mac));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Ok(None);
+        }
+        let text = response.error_for_status()?.text().await?;
+        if text.contains(AUTHORIZATION_FAILED) {
+            return Ok(None);
+        }
+        let response = serde_json::from_str::<StalkerResponse<T>>(&text).with_context(|| {
+            format!(
+                "Unexpected portal response for {kind}:{action}: {}",
+                text.chars().take(200).collect::<String>()
+            )
+        })?;
+        Ok(Some(response.js))
+    }
+
+    async fn get_categories(&mut self, kind: &str) -> Result<Vec<StalkerCategory>> {
+        let action = match kind {
+            ITV => "get_genres",
+            _ => "get_categories",
+        };
+        let categories: Vec<StalkerCategory> = self.call(kind, action, &[]).await?;
+        Ok(categories
+            .into_iter()
+            .filter(|c| get_serde_json_string(&c.id).is_some_and(|id| id != ALL_CATEGORIES))
+            .collect())
+    }
+
+    /// Reads every page of a list
+    async fn get_ordered_list(
+        &mut self,
+        kind: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Vec<StalkerItem>> {
+        let mut items = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let page = page.to_string();
+            let mut page_params = params.to_vec();
+            page_params.push(("p", &page));
+            let response: StalkerPage = self.call(kind, "get_ordered_list", &page_params).await?;
+            let total = get_serde_json_u64(&response.total_items).unwrap_or(0) as usize;
+            if response.data.is_empty() {
+                break;
+            }
+            items.extend(response.data);
+            if items.len() >= total {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Turns a stored command into a playable url, `series` being the episode number
+    async fn create_link(&mut self, kind: &str, cmd: &str, series: Option<i64>) -> Result<String> {
+        let series = series.map(|n| n.to_string()).unwrap_or_default();
+        let link: StalkerLink = self
+            .call(
+                kind,
+                "create_link",
+                &[
+                    ("cmd", cmd),
+                    ("series", &series),
+                    ("forced_storage", "undefined"),
+                    ("disable_ad", "0"),
+                    ("download", "0"),
+                ],
+            )
+            .await?;
+        let url = strip_cmd_prefix(&link.cmd);
+        if url.is_empty() {
+            anyhow::bail!("The portal returned no stream url");
+        }
+        Ok(url.to_string())
+    }
+}
+
+/// Portal urls are usually given as the box would open them, `http://host/c/` or
+/// `http://host/stalker_portal/c/`, while requests go to the server script next to them
+fn get_api_url(portal_url: &str) -> Result<Url> {
+    let mut url = Url::parse(portal_url).context("Invalid portal URL")?;
+    if url.path().ends_with(".php") {
+        return Ok(url);
+    }
+    let path = url.path().trim_end_matches('/');
+    let path = path.strip_suffix("/c").unwrap_or(path);
+    let path = match path.ends_with("stalker_portal") {
+        true => format!("{path}/server/load.php"),
+        false => format!("{path}/portal.php"),
+    };
+    url.set_path(&path);
+    url.set_query(None);
+    Ok(url)
+}
+
+fn normalize_mac(mac: &str) -> Result<String> {
+    let mac = mac.trim().to_uppercase().replace('-', ":");
+    let valid = mac.split(':').count() == 6
+        && mac
+            .split(':')
+            .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        anyhow::bail!("Invalid MAC address: {mac}");
+    }
+    Ok(mac)
+}
+
+fn get_cookie(mac: &str) -> String {
+    let mac: String = url::form_urlencoded::byte_serialize(mac.as_bytes()).collect();
+    format!("mac={mac}; stb_lang=en; timezone=UTC")
+}
+
+fn strip_cmd_prefix(cmd: &str) -> &str {
+    let cmd = cmd.trim();
+    CMD_PREFIXES
+        .iter()
+        .find_map(|prefix| cmd.strip_prefix(prefix))
+        .unwrap_or(cmd)
+        .trim()
+}
+
+pub async fn get_stalker<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    mut source: Source,
+    refresh: bool,
+    report: &mut RefreshReport,
+) -> Result<()> {
+    let source_name = source.name.clone();
+    let mut client = StalkerClient::new(&source)?;
+    let _ = app.emit(
+        "refresh-progress",
+        format!("[{}] Connecting to portal...", source_name),
+    );
+    // A refused MAC address fails the refresh before anything is touched
+    client.handshake().await?;
+
+    let mut lists = Vec::new();
+    for (kind, stream_type, description) in [
+        (ITV, media_type::LIVESTREAM, "Live TV"),
+        (VOD, media_type::MOVIE, "Movies"),
+        (SERIES, media_type::SERIE, "Series"),
+    ] {
+        let _ = app.emit(
+            "refresh-progress",
+            format!("[{}] Fetching {}...", source_name, description),
+        );
+        // Proceed with no categories when they couldn't be fetched
+        let categories = client.get_categories(kind).await.unwrap_or_else(|e| {
+            log::log(format!(
+                "[Backend] [{}] Failed to fetch {} categories: {:?}",
+                source_name, description, e
+            ));
+            add_refresh_failed_action(report, &format!("{kind}:get_categories"), &e);
+            Vec::new()
+        });
+        let params: &[(&str, &str)] = match kind {
+            ITV => &[("genre", ALL_CATEGORIES), ("sortby", "number")],
+            _ => &[("category", ALL_CATEGORIES), ("sortby", "added")],
+        };
+        let categories = categories
+            .into_iter()
+            .filter_map(|c| Some((get_serde_json_string(&c.id)?, c.title?)))
+            .collect();
+        let items = client.get_ordered_list(kind, params).await;
+        lists.push((
+            StalkerList {
+                kind,
+                stream_type,
+                description,
+                categories,
+            },
+            items,
+        ));
+    }
+
+    let mut sql = sql::get_conn()?;
+    let tx = sql.transaction()?;
+    if !refresh {
+        source.id = Some(sql::create_or_find_source_by_name(&tx, &source)?);
+    }
+    let source_id = source.id.context("no source id")?;
+    let previous_counts = sql::get_refresh_counts(&tx, source_id, None)?;
+    let max_id = sql::get_max_channel_id(&tx)?;
+    let last_seen = chrono::Utc::now().timestamp_millis();
+    // Only media types that were fully fetched get their vanished entries removed
+    let mut refreshed_types: Vec<u8> = Vec::new();
+    let mut last_error = None;
+    for (list, items) in lists {
+        match items {
+            Ok(items) => {
+                let _ = app.emit(
+                    "refresh-progress",
+                    format!(
+                        "[{}] Saving {} ({} items)...",
+                        source_name,
+                        list.description,
+                        items.len()
+                    ),
+                );
+                process_stalker(
+                    &tx,
+                    &list,
+                    items,
+                    &source,
+                    &client.api_url,
+                    last_seen,
+                    report,
+                );
+                refreshed_types.push(list.stream_type);
+            }
+            Err(e) => {
+                log::log(format!(
+                    "[Backend] [{}] Failed to fetch {}: {:?}",
+                    source_name, list.description, e
+                ));
+                add_refresh_failed_action(report, &format!("{}:get_ordered_list", list.kind), &e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if let (true, Some(e)) = (refreshed_types.is_empty(), last_error) {
+        let _ = tx.rollback();
+        return Err(anyhow::anyhow!(
+            "Total refresh failed for '{}'. Last error: {:#}",
+            source_name,
+            e
+        ));
+    }
+    let counts = sql::get_refresh_counts(&tx, source_id, Some(last_seen))?;
+    if let Err(e) = check_refresh_drop(
+        &previous_counts,
+        &counts,
+        &refreshed_types,
+        get_refresh_drop_threshold(),
+    ) {
+        let _ = tx.rollback();
+        return Err(e);
+    }
+    let changes = sql::get_refresh_changes(&tx, source_id, last_seen, max_id)?;
+    set_refresh_report_counts(report, &previous_counts, &changes, &refreshed_types);
+    sql::delete_stale_channels(&tx, source_id, &refreshed_types, last_seen, false)?;
+    sql::delete_refresh_orphans(&tx, source_id)?;
+    sql::analyze(&tx)?;
+    tx.commit()?;
+    if let (Some(token), Ok(mut tokens)) = (client.token, TOKENS.lock()) {
+        tokens.insert(source_id, token);
+    }
+    Ok(())
+}
+
+fn process_stalker(
+    tx: &Transaction,
+    list: &StalkerList,
+    items: Vec<StalkerItem>,
+    source: &Source,
+    base_url: &Url,
+    last_seen: i64,
+    report: &mut RefreshReport,
+) {
+    let stream_type = list.stream_type;
+    let mut groups: HashMap<String, i64> = HashMap::new();
+    let mut keys: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        let result = item_to_channel(item, source, base_url, stream_type, &list.categories)
+            .and_then(|(mut channel, id)| {
+                if let Some(source_id) = source.id {
+                    sql::set_channel_group_id(&mut groups, &mut channel, tx, &source_id)
+                        .unwrap_or_else(|e| log::log(format!("{:?}", e)));
+                }
+                let key = get_unique_key(&mut keys, format!("{stream_type}:{id}"));
+                sql::upsert_channel(tx, channel, &key, last_seen)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            log::log(format!("{:?}", e));
+            add_refresh_skipped_line(report, None, format!("{}: {:#}", list.description, e));
+        }
+    }
+}
+
+/// Returns the channel along with the portal's id for it
+fn item_to_channel(
+    item: StalkerItem,
+    source: &Source,
+    base_url: &Url,
+    stream_type: u8,
+    categories: &HashMap<String, String>,
+) -> Result<(Channel, String)> {
+    let id = get_serde_json_string(&item.id)
+        .filter(|id| !id.is_empty())
+        .context("no id")?;
+    let name = get_text(item.name).context("No name")?;
+    // Series are listed by id, their episodes being fetched when opened
+    let url = match stream_type {
+        media_type::SERIE => id.clone(),
+        _ => get_text(item.cmd).context("no cmd")?,
+    };
+    let category_id = match stream_type {
+        media_type::LIVESTREAM => &item.tv_genre_id,
+        _ => &item.category_id,
+    };
+    let channel = Channel {
+        group: get_serde_json_string(category_id).and_then(|id| categories.get(&id).cloned()),
+        image: get_text(item.logo)
+            .or_else(|| get_text(item.screenshot_uri))
+            .map(|image| get_image_url(base_url, image)),
+        rating: get_serde_json_f32(&item.rating_imdb),
+        genre: get_text(item.genres_str),
+        release_date: get_serde_json_string(&item.year).filter(|year| !year.is_empty()),
+        plot: get_text(item.description),
+        cast: get_text(item.actors),
+        director: get_text(item.director),
+        tvg_id: get_text(item.xmltv_id),
+        tvg_chno: get_serde_json_i64(&item.number)
+            .filter(|_| stream_type == media_type::LIVESTREAM),
+        ..get_channel(name, url, stream_type, source)
+    };
+    Ok((channel, id))
+}
+
+fn get_channel(name: String, url: String, stream_type: u8, source: &Source) -> Channel {
+    Channel {
+        id: None,
+        name,
+        url: Some(url),
+        group: None,
+        image: None,
+        media_type: stream_type,
+        source_id: source.id,
+        series_id: None,
+        group_id: None,
+        favorite: false,
+        stream_id: None,
+        tv_archive: None,
+        season_id: None,
+        episode_num: None,
+        hidden: Some(false),
+        rating: None,
+        genre: None,
+        release_date: None,
+        plot: None,
+        cast: None,
+        director: None,
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        catchup_days: None,
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
+    }
+}
+
+fn get_text(value: Option<String>) -> Option<String> {
+    value
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Portals often give logos relative to themselves
+fn get_image_url(base_url: &Url, image: String) -> String {
+    base_url
+        .join(&image)
+        .map(|url| url.to_string())
+        .unwrap_or(image)
+}
+
+/// Lists the seasons of a series, each season holding the numbers of its episodes
+pub async fn get_episodes(channel: Channel) -> Result<()> {
+    let source_id = channel.source_id.context("no source id")?;
+    let series_id: u64 = channel.url.as_ref().context("no url")?.parse()?;
+    if sql::series_has_episodes(series_id, source_id).unwrap_or_else(|e| {
+        log::log(format!("{:?}", e));
+        false
+    }) {
+        return Ok(());
+    }
+    let source = sql::get_source_from_id(source_id)?;
+    let mut client = StalkerClient::new(&source)?;
+    let movie_id = series_id.to_string();
+    let seasons = client
+        .get_ordered_list(
+            SERIES,
+            &[
+                ("movie_id", &movie_id),
+                ("season_id", "0"),
+                ("episode_id", "0"),
+            ],
+        )
+        .await?;
+    let last_seen = chrono::Utc::now().timestamp_millis();
+    sql::do_tx(|tx| {
+        for (index, season) in seasons.into_iter().enumerate() {
+            // Season ids read `<series id>:<season number>`
+            let season_number = get_serde_json_string(&season.id)
+                .and_then(|id| id.rsplit(':').next()?.parse::<i64>().ok())
+                .unwrap_or(index as i64 + 1);
+            let image = get_text(season.screenshot_uri.clone())
+                .map(|image| get_image_url(&client.api_url, image))
+                .or_else(|| channel.image.clone());
+            if let Err(e) = insert_season_episodes(
+                tx,
+                season,
+                season_number,
+                image,
+                &source,
+                series_id,
+                last_seen,
+            ) {
+                log::log(format!("Failed to insert season: {:?}", e));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn insert_season_episodes(
+    tx: &Transaction,
+    season: StalkerItem,
+    season_number: i64,
+    image: Option<String>,
+    source: &Source,
+    series_id: u64,
+    last_seen: i64,
+) -> Result<()> {
+    let source_id = source.id.context("no source id")?;
+    let cmd = get_text(season.cmd).context("no cmd")?;
+    let season_id = insert_season(
+        tx,
+        Season {
+            name: get_text(season.name).unwrap_or_else(|| format!("Season {season_number}")),
+            season_number,
+            image,
+            series_id,
+            source_id,
+            ..Default::default()
+        },
+    )?;
+    for episode in season.series.iter().filter_map(get_serde_json_i64) {
+        let key = format!("episode:{series_id}:{season_number}:{episode}");
+        let channel = Channel {
+            series_id: Some(series_id),
+            season_id: Some(season_id),
+            episode_num: Some(episode),
+            ..get_channel(
+                format!("Episode {episode}"),
+                cmd.clone(),
+                media_type::MOVIE,
+                source,
+            )
+        };
+        sql::upsert_channel(tx, channel, &key, last_seen)?;
+    }
+    Ok(())
+}
+
+/// Replaces the stored portal command of a Stalker channel with a playable url.
+/// Channels of other sources already hold their url and are left as they are.
+pub async fn resolve_stream_url(channel: &mut Channel, source: Option<&Source>) -> Result<()> {
+    let Some(source) = source.filter(|s| s.source_type == source_type::STALKER) else {
+        return Ok(());
+    };
+    let cmd = channel.url.as_deref().context("no channel url")?;
+    let kind = match channel.media_type {
+        media_type::LIVESTREAM => ITV,
+        _ => VOD,
+    };
+    let mut client = StalkerClient::new(source)?;
+    let url = client.create_link(kind, cmd, channel.episode_num).await?;
+    channel.url = Some(url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_stalker {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use warp::Filter;
+
+    const MAC: &str = "00:1A:79:12:34:56";
+
+    fn get_source(url: &str, mac: &str) -> Source {
+        let mut source = sql::get_custom_source("Portal".to_string());
+        source.source_type = source_type::STALKER;
+        source.url = Some(url.to_string());
+        source.mac_address = Some(mac.to_string());
+        source
+    }
+
+    fn get_channels(page: usize) -> Value {
+        let channels = [
+            json!({"id": "10", "name": "News One", "number": "1", "cmd": "ffmpeg http://localhost/ch/10", "tv_genre_id": "1", "logo": "/misc/logos/10.png"}),
+            json!({"id": 11, "name": "Sports One", "number": 2, "cmd": "ffrt http://localhost/ch/11", "tv_genre_id": "2"}),
+            json!({"id": "12", "name": "Sports Two", "number": "3", "cmd": "ffrt http://localhost/ch/12", "tv_genre_id": "2"}),
+        ];
+        let data: Vec<&Value> = channels.iter().skip((page - 1) * 2).take(2).collect();
+        json!({"total_items": "3", "max_page_items": 2, "data": data})
+    }
+
+    /// Serves a small portal, each handshake handing out a new token
+    fn start_portal(handshakes: Arc<AtomicUsize>) -> String {
+        let route = warp::path("portal.php")
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(warp::header::optional::<String>("cookie"))
+            .map(move |query: HashMap<String, String>, auth: Option<String>, cookie: Option<String>| {
+                let get = |key: &str| query.get(key).cloned().unwrap_or_default();
+                if !cookie.unwrap_or_default().contains("mac=00%3A1A%3A79%3A12%3A34%3A56") {
+                    return "Authorization failed.".to_string();
+                }
+                if get("action") == "handshake" {
+                    let n = handshakes.fetch_add(1, Ordering::SeqCst) + 1;
+                    return json!({"js": {"token": format!("token-{n}")}}).to_string();
+                }
+                let token = format!("Bearer token-{}", handshakes.load(Ordering::SeqCst));
+                if auth.as_deref() != Some(token.as_str()) {
+                    return "Authorization failed.".to_string();
+                }
+                let js = match (get("type").as_str(), get("action").as_str()) {
+                    ("stb", "get_profile") => json!({}),
+                    ("itv", "get_genres") => json!([
+                        {"id": "*", "title": "All"},
+                        {"id": "1", "title": "News"},
+                        {"id": "2", "title": "Sports"}
+                    ]),
+                    ("itv", "get_ordered_list") => get_channels(get("p").parse().unwrap_or(1)),
+                    ("itv", "create_link") => json!({"cmd": format!("ffmpeg http://stream.local/live/{}", get("cmd").rsplit('/').next().unwrap_or_default())}),
+                    ("vod", "create_link") => json!({"cmd": format!("http://stream.local/vod/{}.mkv", get("series"))}),
+                    _ => Value::Null,
+                };
+                json!({"js": js}).to_string()
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{addr}/c/")
+    }
+
+    #[test]
+    fn test_get_api_url() {
+        let get = |url: &str| get_api_url(url).unwrap().to_string();
+        assert_eq!(
+            get("http://portal.tv:8080/c/"),
+            "http://portal.tv:8080/portal.php"
+        );
+        assert_eq!(get("http://portal.tv"), "http://portal.tv/portal.php");
+        assert_eq!(
+            get("http://portal.tv/stalker_portal/c/"),
+            "http://portal.tv/stalker_portal/server/load.php"
+        );
+        assert_eq!(
+            get("http://portal.tv/stalker_portal/server/load.php"),
+            "http://portal.tv/stalker_portal/server/load.php"
+        );
+        assert!(get_api_url("portal.tv/c/").is_err());
+    }
+
+    #[test]
+    fn test_normalize_mac() {
+        assert_eq!(normalize_mac(" 00:1a:79:12:34:56 ").unwrap(), MAC);
+        assert_eq!(normalize_mac("00-1A-79-12-34-56").unwrap(), MAC);
+        assert!(normalize_mac("00:1A:79:12:34").is_err());
+        assert!(normalize_mac("00:1A:79:12:34:5G").is_err());
+    }
+
+    #[test]
+    fn test_strip_cmd_prefix() {
+        assert_eq!(strip_cmd_prefix("ffmpeg http://a/b.ts"), "http://a/b.ts");
+        assert_eq!(strip_cmd_prefix(" ffrt http://a/b.ts "), "http://a/b.ts");
+        assert_eq!(strip_cmd_prefix("http://a/b.ts"), "http://a/b.ts");
+    }
+
+    #[test]
+    fn test_item_to_channel() {
+        let source = get_source("http://portal.tv/c/", MAC);
+        let base_url = get_api_url("http://portal.tv/c/").unwrap();
+        let categories = HashMap::from([("1".to_string(), "News".to_string())]);
+        let item: StalkerItem = serde_json::from_value(json!({
+            "id": 10, "name": " News One ", "number": "5", "cmd": "ffmpeg http://localhost/ch/10",
+            "tv_genre_id": "1", "logo": "/misc/logos/10.png", "xmltv_id": "news.one"
+        }))
+        .unwrap();
+        let (channel, id) = item_to_channel(
+            item,
+            &source,
+            &base_url,
+            media_type::LIVESTREAM,
+            &categories,
+        )
+        .unwrap();
+        assert_eq!(id, "10");
+        assert_eq!(channel.name, "News One");
+        assert_eq!(
+            channel.url.as_deref(),
+            Some("ffmpeg http://localhost/ch/10")
+        );
+        assert_eq!(channel.group.as_deref(), Some("News"));
+        assert_eq!(
+            channel.image.as_deref(),
+            Some("http://portal.tv/misc/logos/10.png")
+        );
+        assert_eq!(channel.tvg_id.as_deref(), Some("news.one"));
+        assert_eq!(channel.tvg_chno, Some(5));
+
+        let item: StalkerItem = serde_json::from_value(json!({
+            "id": "77", "name": "Show", "category_id": "3", "year": 2021,
+            "screenshot_uri": "http://cdn.tv/77.jpg", "rating_imdb": "7.5"
+        }))
+        .unwrap();
+        let (channel, _) =
+            item_to_channel(item, &source, &base_url, media_type::SERIE, &categories).unwrap();
+        assert_eq!(channel.url.as_deref(), Some("77"));
+        assert_eq!(channel.group, None);
+        assert_eq!(channel.image.as_deref(), Some("http://cdn.tv/77.jpg"));
+        assert_eq!(channel.release_date.as_deref(), Some("2021"));
+        assert_eq!(channel.rating, Some(7.5));
+
+        let item: StalkerItem = serde_json::from_value(json!({"id": "78", "name": " "})).unwrap();
+        assert!(item_to_channel(item, &source, &base_url, media_type::MOVIE, &categories).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_portal_lists() {
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let url = start_portal(handshakes.clone());
+        let mut client = StalkerClient::new(&get_source(&url, MAC)).unwrap();
+        let genres = client.get_categories(ITV).await.unwrap();
+        let genres: Vec<_> = genres.into_iter().filter_map(|g| g.title).collect();
+        assert_eq!(genres, vec!["News", "Sports"]);
+        let channels = client
+            .get_ordered_list(ITV, &[("genre", ALL_CATEGORIES)])
+            .await
+            .unwrap();
+        let names: Vec<_> = channels.into_iter().filter_map(|c| c.name).collect();
+        assert_eq!(names, vec!["News One", "Sports One", "Sports Two"]);
+        assert_eq!(handshakes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh() {
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let url = start_portal(handshakes.clone());
+        let mut client = StalkerClient::new(&get_source(&url, MAC)).unwrap();
+        client.token = Some("expired".to_string());
+        assert_eq!(client.get_categories(ITV).await.unwrap().len(), 2);
+        assert_eq!(client.token.as_deref(), Some("token-1"));
+        client.get_categories(ITV).await.unwrap();
+        assert_eq!(handshakes.load(Ordering::SeqCst), 1);
+
+        let mut client = StalkerClient::new(&get_source(&url, "00:1A:79:00:00:00")).unwrap();
+        assert!(client.handshake().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_link() {
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let url = start_portal(handshakes.clone());
+        let source = get_source(&url, MAC);
+        let mut client = StalkerClient::new(&source).unwrap();
+        let link = client
+            .create_link(ITV, "ffmpeg http://localhost/ch/10", None)
+            .await
+            .unwrap();
+        assert_eq!(link, "http://stream.local/live/10");
+
+        let mut episode = get_channel(
+            "Episode 4".to_string(),
+            "eyJ0eXBlIjoic2VyaWVzIn0=".to_string(),
+            media_type::MOVIE,
+            &source,
+        );
+        episode.episode_num = Some(4);
+        resolve_stream_url(&mut episode, Some(&source))
+            .await
+            .unwrap();
+        assert_eq!(
+            episode.url.as_deref(),
+            Some("http://stream.local/vod/4.mkv")
+        );
+
+        let mut channel = episode.clone();
+        let custom = sql::get_custom_source("Custom".to_string());
+        resolve_stream_url(&mut channel, Some(&custom))
+            .await
+            .unwrap();
+        assert_eq!(channel.url, episode.url);
+    }
+}
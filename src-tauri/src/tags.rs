@@ -26,6 +26,49 @@ use std::collections::HashMap;
 
 use regex::Regex;
 
+// List of common ISO country codes and languages (and common IPTV variations)
+// Extended list to cover major regions.
+const VALID_TAGS: &[&str] = &[
+    // Languages
+    "EN", "English", "MULTI-LANG",
+    // North America
+    "USA", "US", "United States", "CA", "Canada", "MX", "Mexico",
+    // Europe
+    "UK", "Great Britain", "England", "IE", "Ireland",
+    "FR", "France", "French", "DE", "Germany", "German",
+    "IT", "Italy", "Italian", "ES", "Spain", "Spanish",
+    "PT", "Portugal", "Portuguese", "NL", "Netherlands", "Dutch",
+    "BE", "Belgium", "CH", "Switzerland", "AT", "Austria",
+    "SE", "Sweden", "NO", "Norway", "DK", "Denmark", "FI", "Finland",
+    "PL", "Poland", "Polish", "CZ", "Czech", "HU", "Hungary",
+    "RO", "Romania", "BG", "Bulgaria", "GR", "Greece", "Greek",
+    "TR", "Turkey", "Turkish", "RU", "Russia", "Russian",
+    "UA", "Ukraine",
+    // Asia
+    "CN", "China", "Chinese", "JP", "Japan", "Japanese",
+    "KR", "Korea", "Korean", "IN", "India", "Hindi",
+    "TH", "Thailand", "VN", "Vietnam", "PH", "Philippines",
+    "ID", "Indonesia", "MY", "Malaysia", "SG", "Singapore",
+    "HK", "Hong Kong", "TW", "Taiwan",
+    // South America / LATAM
+    "BR", "Brazil", "AR", "Argentina", "CO", "Colombia",
+    "CL", "Chile", "PE", "Peru", "UY", "Uruguay",
+    "LATAM", "Latin",
+    // Middle East
+    "AE", "UAE", "SA", "Saudi Arabia", "Arabic", "Arab",
+    "IL", "Israel", "Hebrew", "IR", "Iran", "Persian",
+    // Oceania
+    "AU", "Australia", "NZ", "New Zealand",
+    // Africa
+    "ZA", "South Africa", "Africa",
+    "VIP", "PPV"
+];
+
+/// Whether a name fragment is a country, language or access tag channel names are marked with
+pub fn is_valid_tag(tag: &str) -> bool {
+    VALID_TAGS.iter().any(|&vt| vt.eq_ignore_ascii_case(tag))
+}
+
 pub fn detect_tags(conn: &Connection) -> Result<Vec<Tag>> {
     let mut stmt = conn.prepare("SELECT name, hidden, media_type FROM channels")?;
     let rows = stmt.query_map([], |row| {
@@ -47,44 +90,6 @@ pub fn detect_tags(conn: &Connection) -> Result<Vec<Tag>> {
     let re_brackets = Regex::new(r"\[(.*?)\]|\((.*?)\)").expect("Failed to compile re_brackets regex");
     let re_prefix = Regex::new(r"^([A-Za-z0-9 ]{2,15})(:|\|| - )").expect("Failed to compile re_prefix regex");
 
-    // List of common ISO country codes and languages (and common IPTV variations)
-    // Extended list to cover major regions.
-    let valid_tags: Vec<&str> = vec![
-        // Languages
-        "EN", "English", "MULTI-LANG",
-        // North America
-        "USA", "US", "United States", "CA", "Canada", "MX", "Mexico",
-        // Europe
-        "UK", "Great Britain", "England", "IE", "Ireland",
-        "FR", "France", "French", "DE", "Germany", "German",
-        "IT", "Italy", "Italian", "ES", "Spain", "Spanish",
-        "PT", "Portugal", "Portuguese", "NL", "Netherlands", "Dutch",
-        "BE", "Belgium", "CH", "Switzerland", "AT", "Austria",
-        "SE", "Sweden", "NO", "Norway", "DK", "Denmark", "FI", "Finland",
-        "PL", "Poland", "Polish", "CZ", "Czech", "HU", "Hungary",
-        "RO", "Romania", "BG", "Bulgaria", "GR", "Greece", "Greek",
-        "TR", "Turkey", "Turkish", "RU", "Russia", "Russian",
-        "UA", "Ukraine",
-        // Asia
-        "CN", "China", "Chinese", "JP", "Japan", "Japanese",
-        "KR", "Korea", "Korean", "IN", "India", "Hindi",
-        "TH", "Thailand", "VN", "Vietnam", "PH", "Philippines",
-        "ID", "Indonesia", "MY", "Malaysia", "SG", "Singapore",
-        "HK", "Hong Kong", "TW", "Taiwan",
-        // South America / LATAM
-        "BR", "Brazil", "AR", "Argentina", "CO", "Colombia",
-        "CL", "Chile", "PE", "Peru", "UY", "Uruguay",
-        "LATAM", "Latin",
-        // Middle East
-        "AE", "UAE", "SA", "Saudi Arabia", "Arabic", "Arab",
-        "IL", "Israel", "Hebrew", "IR", "Iran", "Persian",
-        // Oceania
-        "AU", "Australia", "NZ", "New Zealand",
-        // Africa
-        "ZA", "South Africa", "Africa",
-        "VIP", "PPV"
-    ];
-
     for row in rows {
         if let Ok((name, hidden, media_type)) = row {
             let mut found_tags = Vec::new();
@@ -94,7 +99,7 @@ pub fn detect_tags(conn: &Connection) -> Result<Vec<Tag>> {
                 if let Some(m) = caps.get(1).or(caps.get(2)) {
                      let tag = m.as_str().trim().to_string();
                      // Filter: Must be in our valid list (case-insensitive check)
-                     if !tag.is_empty() && valid_tags.iter().any(|&vt| vt.eq_ignore_ascii_case(&tag)) {
+                     if !tag.is_empty() && is_valid_tag(&tag) {
                         found_tags.push(tag); // Keep original casing for now
                      }
                 }
@@ -104,7 +109,7 @@ pub fn detect_tags(conn: &Connection) -> Result<Vec<Tag>> {
             if let Some(caps) = re_prefix.captures(&name) {
                 if let Some(m) = caps.get(1) {
                     let tag = m.as_str().trim().to_string();
-                     if !tag.is_empty() && valid_tags.iter().any(|&vt| vt.eq_ignore_ascii_case(&tag)) {
+                     if !tag.is_empty() && is_valid_tag(&tag) {
                         found_tags.push(tag);
                      }
                 }
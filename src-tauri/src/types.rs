@@ -22,7 +22,6 @@
 use std::{
     collections::HashMap,
     sync::{Arc, atomic::AtomicBool},
-    thread::JoinHandle,
 };
 
 use indexmap::IndexMap;
@@ -66,6 +65,32 @@ pub struct Channel {
     pub cast: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub director: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tvg_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tvg_chno: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tvg_shift: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catchup: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catchup_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catchup_days: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tvg_rec: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub now_program: Option<ChannelProgram>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_program: Option<ChannelProgram>,
+}
+
+/// Short guide entry shown alongside a live channel in search results
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ChannelProgram {
+    pub title: String,
+    pub start: i64,
+    pub stop: i64,
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize, Default)]
@@ -99,6 +124,87 @@ pub struct Source {
     pub stream_user_agent: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_updated: Option<i64>,
+    /// Comma separated XMLTV guide urls, as declared by the playlist header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epg_url: Option<String>,
+    /// Seconds added to the guide times of the source, to correct a guide that runs early or late
+    pub epg_offset: Option<i64>,
+    /// Seconds the provider's clock is ahead of UTC, as read from the Xtream panel on refresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_utc_offset: Option<i64>,
+    /// MAC address a Stalker portal knows the subscription by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<String>,
+    /// Account status the Xtream panel reported on the last refresh, such as "Active" or "Banned"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_status: Option<String>,
+    /// End of the subscription, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp_date: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_cons: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_trial: Option<bool>,
+}
+
+/// Subscription of a source, as reported by its Xtream panel
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SourceAccount {
+    pub status: Option<String>,
+    pub exp_date: Option<i64>,
+    pub max_connections: Option<i64>,
+    pub active_cons: Option<i64>,
+    pub is_trial: Option<bool>,
+}
+
+/// Validators of the last successful download of a source resource
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct HttpValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Outcome of a source refresh, kept as history per source
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize, Default)]
+pub struct RefreshReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub source_id: Option<i64>,
+    pub source_name: String,
+    pub started_at: i64,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    /// The server reported nothing changed since the last refresh
+    pub not_modified: bool,
+    pub media_types: Vec<RefreshMediaTypeCounts>,
+    /// Total of skipped entries, only the first ones are kept in `skipped_lines`
+    pub skipped_count: usize,
+    pub skipped_lines: Vec<RefreshSkippedLine>,
+    pub failed_actions: Vec<RefreshFailedAction>,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize, Default)]
+pub struct RefreshMediaTypeCounts {
+    pub media_type: u8,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct RefreshSkippedLine {
+    /// Playlist line number, None for Xtream entries
+    pub line: Option<usize>,
+    pub reason: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct RefreshFailedAction {
+    pub action: String,
+    pub error: String,
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
@@ -132,6 +238,11 @@ pub struct Settings {
     pub theme: Option<u8>,
     pub vpn_mode: Option<bool>,  // VPN mode for unstable connections
     pub tmdb_api_key: Option<String>,  // TMDB API key for movie metadata
+    pub refresh_drop_threshold: Option<u8>,  // Max % of items a refresh may remove per media type
+    pub recording_pre_padding: Option<u16>,  // Minutes recorded before a scheduled start
+    pub recording_post_padding: Option<u16>, // Minutes recorded after a scheduled end
+    pub account_expiry_warning_days: Option<u16>, // Days before a subscription ends to warn, 0 to never
+    pub series_prefetch: Option<u8>, // Series whose episodes are fetched after a refresh
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
@@ -165,6 +276,17 @@ pub struct ChannelHttpHeaders {
     pub ignore_ssl: Option<bool>,
 }
 
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ChannelStreamOption {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<i64>,
+    pub option_type: u8,
+    pub key: String,
+    pub value: String,
+}
+
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct CustomChannel {
     pub data: Channel,
@@ -208,6 +330,18 @@ pub struct ExportedSource {
     pub channels: Vec<CustomChannel>,
 }
 
+/// Live channels to write out as M3U and XMLTV for other players. A channel is exported when it
+/// belongs to one of the sources or groups, or is a favorite and favorites are asked for.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct PlaylistExport {
+    #[serde(default)]
+    pub source_ids: Vec<i64>,
+    #[serde(default)]
+    pub group_ids: Vec<i64>,
+    #[serde(default)]
+    pub favorites: bool,
+}
+
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct EPG {
     pub epg_id: String,
@@ -216,23 +350,130 @@ pub struct EPG {
     pub start_time: String,
     pub start_timestamp: i64,
     pub end_time: String,
+    pub end_timestamp: i64,
     pub timeshift_url: Option<String>,
     pub has_archive: bool,
     pub now_playing: bool,
 }
 
+/// Guide entry imported from XMLTV, stored per channel
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize, Default)]
+pub struct EPGProgram {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub channel_id: i64,
+    pub source_id: i64,
+    pub start: i64,
+    pub stop: i64,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub description: Option<String>,
+    pub episode_num: Option<String>,
+    pub category: Option<String>,
+    pub icon: Option<String>,
+    pub origin: u8,
+    pub has_archive: bool,
+    /// Xtream start time in the panel's own timezone, as timeshift urls expect it
+    pub server_start: Option<String>,
+}
+
+/// Airing found by a guide search, with enough of its channel to play, remind or record it
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct EPGSearchResult {
+    pub channel_id: i64,
+    pub channel_name: String,
+    pub channel_image: Option<String>,
+    pub source_id: i64,
+    pub program: EPG,
+}
+
+/// Replayable programmes of an Xtream channel that started on one local day
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ArchiveDay {
+    /// Local date, as YYYY-MM-DD
+    pub date: String,
+    pub programs: Vec<EPG>,
+}
+
+/// Guide of one channel over the window of an EPG grid
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct EPGGridChannel {
+    pub channel_id: i64,
+    pub programs: Vec<EPG>,
+}
+
+/// XMLTV guide channel proposed for a live channel, with how sure the matcher is from 0 to 1
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct EPGChannelSuggestion {
+    pub xmltv_id: String,
+    pub display_name: Option<String>,
+    pub confidence: f32,
+}
+
+/// Guide channel a live channel is mapped to, and the candidates it could be mapped to instead
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct EPGChannelMatch {
+    pub channel_id: i64,
+    pub channel_name: String,
+    pub tvg_id: Option<String>,
+    /// None when the channel gets no guide
+    pub xmltv_id: Option<String>,
+    /// Whether the user picked `xmltv_id`, rather than the matcher
+    pub confirmed: bool,
+    pub suggestions: Vec<EPGChannelSuggestion>,
+}
+
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct EPGNotify {
     pub epg_id: String,
     pub title: String,
     pub start_timestamp: i64,
     pub channel_name: String,
+    /// Lets the reminder follow its programme when the guide reschedules it
+    pub channel_id: Option<i64>,
+    /// Minutes before the start to notify at, 0 when missing
+    pub lead_minutes: Option<i64>,
+}
+
+/// Headless recording of a channel over a time window, padding included in minutes
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct Recording {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub channel_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<i64>,
+    pub channel_name: String,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub episode_num: Option<String>,
+    pub start: i64,
+    pub stop: i64,
+    pub pre_padding: Option<i64>,
+    pub post_padding: Option<i64>,
+    /// One of `recording_status`
+    pub status: u8,
+    pub file_path: Option<String>,
+    pub error: Option<String>,
+    /// Rule that scheduled it, None when scheduled by hand
+    pub rule_id: Option<i64>,
+}
+
+/// Series link: records every upcoming airing titled `title`, ignoring case, on one channel or
+/// any. Airings of an episode already recorded, scheduled or skipped are left out.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct RecordingRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub title: String,
+    pub channel_id: Option<i64>,
+    pub pre_padding: Option<i64>,
+    pub post_padding: Option<i64>,
+    pub enabled: bool,
 }
 
 #[derive(Debug, Default)]
 pub struct AppState {
-    pub notify_stop: Arc<AtomicBool>,
-    pub thread_handle: Option<JoinHandle<Result<(), anyhow::Error>>>,
     pub restream_stop_signal: Arc<AtomicBool>,
 
     pub play_stop: HashMap<i64, IndexMap<String, CancellationToken>>,
@@ -253,6 +494,8 @@ pub struct ChannelPreserve {
     pub hidden: bool,
     #[serde(default)]
     pub is_group: bool,
+    #[serde(default)]
+    pub tvg_id: Option<String>,
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
@@ -19,31 +19,43 @@
  * This project is a fork of Open TV by Fredolx.
  */
 
-use crate::types::{AppState, Channel, ChannelPreserve};
+use crate::types::{
+    AppState, Channel, ChannelPreserve, ChannelStreamOption, HttpValidators,
+    RefreshFailedAction, RefreshMediaTypeCounts, RefreshReport, RefreshSkippedLine,
+};
 use crate::{
     log::log,
     m3u,
-    settings::{get_default_record_path, get_settings},
-    source_type, sql,
+    settings::{
+        get_default_record_path, get_settings, DEFAULT_ACCOUNT_EXPIRY_WARNING_DAYS,
+        DEFAULT_REFRESH_DROP_THRESHOLD,
+    },
+    media_type, recording, series_prefetch_type, source_type, sql, stalker, stream_option_type,
     types::Source,
-    xtream,
+    xmltv, xtream,
 };
 use ::log::{info, warn, error};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Local, Utc};
 use directories::ProjectDirs;
+use flate2::bufread::MultiGzDecoder;
 use indexmap::IndexMap;
 use regex::Regex;
 use reqwest::{
-    Client,
-    header::{HeaderMap, HeaderValue},
+    Client, RequestBuilder, Response,
+    header::{ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
 };
 use serde::Serialize;
 use std::{
+    collections::{HashMap, HashSet},
     env::consts::OS,
     fs::File,
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 #[cfg(not(target_os = "macos"))]
 use std::env::current_exe;
@@ -52,6 +64,7 @@ use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 use which::which;
+use xz2::bufread::XzDecoder;
 
 #[cfg(target_os = "macos")]
 const MACOS_POTENTIAL_PATHS: [&str; 3] = [
@@ -66,7 +79,14 @@ static ILLEGAL_CHARS_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"[<>:"/\\|?*\x00-\x1F]"#).expect("Failed to compile ILLEGAL_CHARS_REGEX - this is a static pattern and should never fail")
 });
 
-pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source: Source) -> Result<()> {
+/// Sources whose series episodes are being prefetched
+static SERIES_PREFETCHES: LazyLock<std::sync::Mutex<HashSet<i64>>> =
+    LazyLock::new(|| std::sync::Mutex::new(HashSet::new()));
+
+pub async fn refresh_source<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    source: Source,
+) -> Result<RefreshReport> {
     let id = source.id;
     let source_name = source.name.clone();
     let _source_type_name = match source.source_type {
@@ -74,11 +94,18 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
         source_type::M3U_LINK => "M3U URL",
         source_type::XTREAM => "Xtream Codes",
         source_type::CUSTOM => "Custom",
+        source_type::STALKER => "Stalker Portal",
         _ => "Unknown",
     };
     
     
     let start_time = std::time::Instant::now();
+    let mut report = RefreshReport {
+        source_id: id,
+        source_name: source_name.clone(),
+        started_at: Utc::now().timestamp(),
+        ..Default::default()
+    };
     
     let result = match source.source_type {
         source_type::M3U => {
@@ -87,7 +114,7 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
                 "activity": "Reading M3U file...",
                 "percent": 0
             }).to_string());
-            m3u::read_m3u8(source.clone(), true)
+            m3u::read_m3u8(source.clone(), true, &mut report)
         },
         source_type::M3U_LINK => {
             let _ = app.emit("refresh-progress", serde_json::json!({
@@ -95,7 +122,7 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
                 "activity": "Downloading M3U playlist...",
                 "percent": 0
             }).to_string());
-            m3u::get_m3u8_from_link(source.clone(), true).await
+            m3u::get_m3u8_from_link(source.clone(), true, &mut report).await
         },
         source_type::XTREAM => {
             let _ = app.emit("refresh-progress", serde_json::json!({
@@ -103,7 +130,15 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
                 "activity": "Connecting to Xtream server...",
                 "percent": 0
             }).to_string());
-            xtream::get_xtream(app, source.clone(), true).await
+            xtream::get_xtream(app, source.clone(), true, &mut report).await
+        },
+        source_type::STALKER => {
+            let _ = app.emit("refresh-progress", serde_json::json!({
+                "playlist": source_name,
+                "activity": "Connecting to Stalker portal...",
+                "percent": 0
+            }).to_string());
+            stalker::get_stalker(app, source.clone(), true, &mut report).await
         },
         source_type::CUSTOM => {
             Ok(())
@@ -113,7 +148,24 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
         }
     };
 
+    if let (Ok(_), Some(id)) = (&result, id) {
+        refresh_source_epg(app, id, &mut report).await;
+    }
+    if let (Ok(_), Some(id), source_type::XTREAM) = (&result, id, source.source_type) {
+        spawn_series_prefetch(id);
+    }
+
     let elapsed = start_time.elapsed();
+    report.duration_ms = elapsed.as_millis() as u64;
+    report.success = result.is_ok();
+    report.error = result.as_ref().err().map(|e| e.to_string());
+    if let Some(id) = id {
+        match sql::insert_refresh_report(id, &report) {
+            Ok(report_id) => report.id = Some(report_id),
+            Err(e) => error!("[Backend] [{}] Failed to save refresh report: {:?}", source_name, e),
+        }
+    }
+    let _ = app.emit("refresh-report", &report);
 
     match &result {
         Ok(_) => {
@@ -139,7 +191,63 @@ pub async fn refresh_source<R: tauri::Runtime>(app: &tauri::AppHandle<R>, source
         }
     }
     
-    result
+    result.map(|_| report)
+}
+
+/// Fetches series episodes in the background after a refresh, when the settings ask for it.
+/// A source already being prefetched isn't started again.
+fn spawn_series_prefetch(source_id: i64) {
+    let favorites_only = match get_settings().ok().and_then(|s| s.series_prefetch) {
+        Some(series_prefetch_type::FAVORITES) => true,
+        Some(series_prefetch_type::ALL) => false,
+        _ => return,
+    };
+    if !SERIES_PREFETCHES
+        .lock()
+        .is_ok_and(|mut sources| sources.insert(source_id))
+    {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        match xtream::prefetch_episodes(source_id, favorites_only).await {
+            Ok(count) => info!("[Backend] Prefetched the episodes of {} series of source {}", count, source_id),
+            Err(e) => error!("[Backend] Series prefetch failed for source {}: {:?}", source_id, e),
+        }
+        if let Ok(mut sources) = SERIES_PREFETCHES.lock() {
+            sources.remove(&source_id);
+        }
+    });
+}
+
+/// Imports the source's XMLTV guide after a refresh. A broken guide doesn't fail the refresh,
+/// it is listed in the report instead.
+async fn refresh_source_epg<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    source_id: i64,
+    report: &mut RefreshReport,
+) {
+    let source = match sql::get_source_from_id(source_id) {
+        Ok(source) => source,
+        Err(e) => {
+            error!("[Backend] Failed to load source {} for EPG import: {:?}", source_id, e);
+            return;
+        }
+    };
+    if source.epg_url.is_none() {
+        return;
+    }
+    let _ = app.emit("refresh-progress", serde_json::json!({
+        "playlist": source.name,
+        "activity": "Importing EPG...",
+        "percent": 99
+    }).to_string());
+    match xmltv::import_source_epg(&source).await {
+        Ok(count) => info!("[Backend] [{}] Imported {} EPG programmes", source.name, count),
+        Err(e) => {
+            error!("[Backend] [{}] EPG import failed: {:?}", source.name, e);
+            add_refresh_failed_action(report, "xmltv", &e);
+        }
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -246,17 +354,16 @@ pub fn get_local_time(timestamp: i64) -> Result<DateTime<Local>> {
 pub async fn download(
     state: State<'_, Mutex<AppState>>,
     app: AppHandle,
-    channel: Channel,
+    mut channel: Channel,
     download_id: &str,
     path: Option<String>,
 ) -> Result<()> {
     let source_id = channel.source_id.context("no source id provided")?;
     let source = sql::get_source_from_id(source_id)
         .with_context(|| format!("failed to fetch source with id {}", source_id))?;
+    stalker::resolve_stream_url(&mut channel, Some(&source)).await?;
 
-    _ = handle_max_streams(&source, &state)
-        .await
-        .map_err(|e| log(format!("{:?}", e)));
+    handle_max_streams(&source, &state).await?;
 
     let token = CancellationToken::new();
     _ = insert_play_token(source_id, download_id.to_string(), token.clone(), &state)
@@ -366,6 +473,8 @@ pub async fn remove_from_play_stop(
     Ok(map.shift_remove(key))
 }
 
+/// Makes room for one more stream of the source by stopping its oldest playback. Recordings are
+/// never stopped for another stream, so this fails once they use every stream of the source.
 pub async fn handle_max_streams(source: &Source, state: &State<'_, Mutex<AppState>>) -> Result<()> {
     let max_streams = source.max_streams.unwrap_or(1);
     let mut guard = state.lock().await;
@@ -379,8 +488,12 @@ pub async fn handle_max_streams(source: &Source, state: &State<'_, Mutex<AppStat
     if channels.len() < max_streams.into() {
         return Ok(());
     }
+    let index = channels
+        .keys()
+        .position(|key| !recording::is_recording_key(key))
+        .with_context(|| format!("All streams of {} are in use by recordings", source.name))?;
     let (_, token) = channels
-        .shift_remove_index(0)
+        .shift_remove_index(index)
         .context("failed to remove channel from indexMap")?;
     token.cancel();
     Ok(())
@@ -425,7 +538,7 @@ pub fn sanitize(str: String) -> String {
     ILLEGAL_CHARS_REGEX.replace_all(&str, "").to_string()
 }
 
-fn get_download_path(file_name: String) -> Result<String> {
+pub fn get_download_path(file_name: String) -> Result<String> {
     let settings = get_settings()?;
     let path = match settings.recording_path {
         Some(path) => path,
@@ -565,6 +678,37 @@ pub fn check_nuke() -> Result<()> {
     Ok(())
 }
 
+/// Makes the request conditional on the resource having changed since it was last downloaded
+pub fn add_validator_headers(
+    request: RequestBuilder,
+    validators: Option<&HttpValidators>,
+) -> RequestBuilder {
+    let mut request = request;
+    if let Some(validators) = validators {
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    request
+}
+
+pub fn get_response_validators(response: &Response) -> HttpValidators {
+    let get = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    HttpValidators {
+        etag: get(ETAG),
+        last_modified: get(LAST_MODIFIED),
+    }
+}
+
 pub fn get_user_agent_from_source(source: &Source) -> Result<String> {
     let user_agent: &str = source
         .user_agent
@@ -574,9 +718,224 @@ pub fn get_user_agent_from_source(source: &Source) -> Result<String> {
     Ok(user_agent.to_string())
 }
 
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Downloaded file that is removed from the cache once dropped
+pub struct TempFile(pub PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            log(format!("Failed to remove temp file {:?}: {:?}", self.0, e));
+        }
+    }
+}
+
+/// Unique per download, so concurrent refreshes never share an input file
+pub fn get_tmp_path(prefix: &str, extension: &str) -> Result<PathBuf> {
+    let mut path = ProjectDirs::from("com", "beatstv", "app")
+        .context("Failed to get project directories")?
+        .cache_dir()
+        .to_owned();
+    if !path.exists() {
+        std::fs::create_dir_all(&path).context("Failed to create cache directory")?;
+    }
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    path.push(format!(
+        "{}-{}-{}-{}.{}",
+        prefix,
+        std::process::id(),
+        nanos,
+        TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    ));
+    Ok(path)
+}
+
+/// Opens a file, transparently decompressing gzip and xz content
+pub fn open_compressed_file(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let header = reader.fill_buf()?;
+    if header.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if header.starts_with(XZ_MAGIC) {
+        Ok(Box::new(BufReader::new(XzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Skipped entries beyond this many are only counted in a refresh report
+const REFRESH_REPORT_MAX_SKIPPED_LINES: usize = 200;
+/// Below this many items a source is only protected against coming back empty
+const REFRESH_DROP_MIN_ITEMS: usize = 20;
+
+/// Fails when a refresh would remove more than `threshold` percent of the items of a media type,
+/// which usually means the provider served a truncated playlist. A threshold of 100 disables the check.
+pub fn check_refresh_drop(
+    previous: &HashMap<u8, usize>,
+    current: &HashMap<u8, usize>,
+    media_types: &[u8],
+    threshold: u8,
+) -> Result<()> {
+    if threshold >= 100 {
+        return Ok(());
+    }
+    for media_type in media_types {
+        let before = previous.get(media_type).copied().unwrap_or(0);
+        let after = current.get(media_type).copied().unwrap_or(0);
+        if before == 0 || after >= before {
+            continue;
+        }
+        let drop = (before - after) * 100 / before;
+        if after == 0 || (before >= REFRESH_DROP_MIN_ITEMS && drop > threshold as usize) {
+            anyhow::bail!(
+                "{} went from {} to {} items ({}% fewer, the limit is {}%). The provider probably returned a truncated playlist, so the previous data was kept",
+                get_media_type_name(*media_type),
+                before,
+                after,
+                drop,
+                threshold
+            );
+        }
+    }
+    Ok(())
+}
+
+fn get_media_type_name(media_type: u8) -> &'static str {
+    match media_type {
+        media_type::LIVESTREAM => "Live TV",
+        media_type::MOVIE => "Movies",
+        media_type::SERIE => "Series",
+        _ => "Channels",
+    }
+}
+
+pub fn get_account_expiry_warning_days() -> u16 {
+    get_settings()
+        .ok()
+        .and_then(|s| s.account_expiry_warning_days)
+        .unwrap_or(DEFAULT_ACCOUNT_EXPIRY_WARNING_DAYS)
+}
+
+pub fn get_refresh_drop_threshold() -> u8 {
+    get_settings()
+        .ok()
+        .and_then(|s| s.refresh_drop_threshold)
+        .unwrap_or(DEFAULT_REFRESH_DROP_THRESHOLD)
+}
+
+/// Fills the per media type counts of a refresh report. Items of a refreshed media type that
+/// existed before but weren't seen again are the ones the refresh removes.
+pub fn set_refresh_report_counts(
+    report: &mut RefreshReport,
+    previous: &HashMap<u8, usize>,
+    changes: &HashMap<u8, (usize, usize)>,
+    refreshed_types: &[u8],
+) {
+    let mut media_types: Vec<u8> = previous.keys().chain(changes.keys()).copied().collect();
+    media_types.sort();
+    media_types.dedup();
+    report.media_types = media_types
+        .into_iter()
+        .map(|media_type| {
+            let (added, updated) = changes.get(&media_type).copied().unwrap_or((0, 0));
+            let removed = if refreshed_types.contains(&media_type) {
+                previous
+                    .get(&media_type)
+                    .copied()
+                    .unwrap_or(0)
+                    .saturating_sub(updated)
+            } else {
+                0
+            };
+            RefreshMediaTypeCounts {
+                media_type,
+                added,
+                updated,
+                removed,
+            }
+        })
+        .collect();
+}
+
+pub fn add_refresh_skipped_line(report: &mut RefreshReport, line: Option<usize>, reason: String) {
+    report.skipped_count += 1;
+    if report.skipped_lines.len() < REFRESH_REPORT_MAX_SKIPPED_LINES {
+        report.skipped_lines.push(RefreshSkippedLine { line, reason });
+    }
+}
+
+pub fn add_refresh_failed_action(report: &mut RefreshReport, action: &str, error: &anyhow::Error) {
+    report.failed_actions.push(RefreshFailedAction {
+        action: action.to_string(),
+        error: format!("{:#}", error),
+    });
+}
+
+/// Makes a refresh key unique within a refresh by numbering its repeated occurrences
+pub fn get_unique_key(seen: &mut HashMap<String, usize>, key: String) -> String {
+    let count = seen.entry(key.clone()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => key,
+        n => format!("{key}#{n}"),
+    }
+}
+
+/// Extracts extra HTTP headers from a channel's stream options as (name, value) pairs
+pub fn get_option_http_headers(options: &[ChannelStreamOption]) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    for option in options {
+        match (option.option_type, option.key.to_lowercase().as_str()) {
+            (stream_option_type::VLC, "http-cookie") => {
+                headers.push(("Cookie".to_string(), option.value.clone()));
+            }
+            (stream_option_type::VLC, "http-header") => {
+                if let Some((name, value)) = option
+                    .value
+                    .split_once(':')
+                    .or_else(|| option.value.split_once('='))
+                {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            (
+                stream_option_type::KODI,
+                "inputstream.adaptive.stream_headers"
+                | "inputstream.adaptive.manifest_headers"
+                | "inputstream.adaptive.common_headers",
+            ) => {
+                for pair in option.value.split('&') {
+                    if let Some((name, value)) = pair.split_once('=') {
+                        let value = urlencoding::decode(value)
+                            .map(|v| v.into_owned())
+                            .unwrap_or_else(|_| value.to_string());
+                        headers.push((name.trim().to_string(), value.trim().to_string()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    headers.retain(|(name, value)| !name.is_empty() && !value.is_empty());
+    headers
+}
+
 #[cfg(test)]
 mod test_utils {
-    use super::sanitize;
+    use std::collections::HashMap;
+    use std::io::{BufRead, Write};
+
+    use super::{check_refresh_drop, open_compressed_file, sanitize, set_refresh_report_counts};
+    use crate::media_type;
+    use crate::types::{RefreshMediaTypeCounts, RefreshReport};
 
     #[test]
     fn test_sanitize() {
@@ -585,4 +944,72 @@ mod test_utils {
             sanitize("SuperShow: Who will win the million?".to_string())
         );
     }
+
+    #[test]
+    fn test_open_compressed_file() {
+        let playlist = "#EXTM3U\n#EXTINF:-1,Amazing Channel\nhttp://myurl.local/1.ts\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(playlist.as_bytes()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(playlist.as_bytes()).unwrap();
+        let files = [
+            ("plain.m3u", playlist.as_bytes().to_vec()),
+            ("gzip.m3u.gz", gzip.finish().unwrap()),
+            ("xz.m3u.xz", xz.finish().unwrap()),
+        ];
+        let dir = std::env::temp_dir().join(format!("m3u-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, bytes) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            let lines: Vec<String> = open_compressed_file(&path)
+                .unwrap()
+                .lines()
+                .map(|l| l.unwrap())
+                .collect();
+            assert_eq!(lines, playlist.lines().collect::<Vec<_>>(), "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_refresh_drop() {
+        let counts = |live: usize, movies: usize| {
+            HashMap::from([(media_type::LIVESTREAM, live), (media_type::MOVIE, movies)])
+        };
+        let types = [media_type::LIVESTREAM, media_type::MOVIE];
+        assert!(check_refresh_drop(&counts(1000, 500), &counts(900, 500), &types, 50).is_ok());
+        assert!(check_refresh_drop(&counts(1000, 500), &counts(400, 500), &types, 50).is_err());
+        assert!(check_refresh_drop(&counts(1000, 500), &counts(1000, 0), &types, 50).is_err());
+        assert!(check_refresh_drop(&counts(1000, 500), &counts(1000, 0), &[media_type::LIVESTREAM], 50).is_ok());
+        assert!(check_refresh_drop(&counts(10, 5), &counts(2, 1), &types, 50).is_ok());
+        assert!(check_refresh_drop(&counts(10, 5), &counts(0, 5), &types, 50).is_err());
+        assert!(check_refresh_drop(&HashMap::new(), &counts(0, 0), &types, 50).is_ok());
+        assert!(check_refresh_drop(&counts(1000, 500), &counts(1, 1), &types, 100).is_ok());
+    }
+
+    #[test]
+    fn test_set_refresh_report_counts() {
+        let mut report = RefreshReport::default();
+        let previous = HashMap::from([(media_type::LIVESTREAM, 10), (media_type::MOVIE, 5)]);
+        let changes = HashMap::from([(media_type::LIVESTREAM, (3, 8))]);
+        set_refresh_report_counts(&mut report, &previous, &changes, &[media_type::LIVESTREAM]);
+        assert_eq!(
+            report.media_types,
+            vec![
+                RefreshMediaTypeCounts {
+                    media_type: media_type::LIVESTREAM,
+                    added: 3,
+                    updated: 8,
+                    removed: 2
+                },
+                RefreshMediaTypeCounts {
+                    media_type: media_type::MOVIE,
+                    added: 0,
+                    updated: 0,
+                    removed: 0
+                },
+            ]
+        );
+    }
 }
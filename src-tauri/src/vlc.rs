@@ -1,7 +1,7 @@
 use crate::settings::get_default_record_path;
 use crate::types::{AppState, ChannelHttpHeaders, Source};
 use crate::utils::get_bin;
-use crate::{log, sql};
+use crate::{log, sql, stalker};
 use crate::{media_type, settings::get_settings, types::Channel};
 use anyhow::{Context, Result};
 use chrono::Local;
@@ -23,15 +23,11 @@ const VLC_BIN_NAME: &str = "vlc";
 static VLC_PATH: LazyLock<String> = LazyLock::new(|| get_bin(VLC_BIN_NAME));
 
 pub async fn play(
-    channel: Channel,
+    mut channel: Channel,
     record: bool,
     record_path: Option<String>,
     state: State<'_, Mutex<AppState>>,
 ) -> Result<()> {
-    eprintln!(
-        "{} playing with VLC",
-        channel.url.as_ref().context("no channel url")?
-    );
     let source = channel
         .source_id
         .and_then(|id| {
@@ -40,13 +36,16 @@ pub async fn play(
                 .ok()
         })
         .or(None);
+    stalker::resolve_stream_url(&mut channel, source.as_ref()).await?;
+    eprintln!(
+        "{} playing with VLC",
+        channel.url.as_ref().context("no channel url")?
+    );
     let args = get_play_args(&channel, record, record_path, &source)?;
     eprintln!("with args: {:?}", args);
 
     if let Some(source) = source.as_ref() {
-        _ = crate::utils::handle_max_streams(source, &state)
-            .await
-            .map_err(|e| log::log(format!("{:?}", e)));
+        crate::utils::handle_max_streams(source, &state).await?;
     }
 
     let mut cmd = Command::new(VLC_PATH.clone())
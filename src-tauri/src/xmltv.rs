@@ -0,0 +1,545 @@
+/*
+ * Beats TV - Premium IPTV Player
+ * Copyright (C) 2026 Beats TV Team
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ *
+ * This project is a fork of Open TV by Fredolx.
+ */
+
+use std::{collections::HashMap, io::BufRead, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use quick_xml::{
+    Reader,
+    events::{BytesStart, Event},
+};
+use rusqlite::Transaction;
+
+use crate::{
+    epg_matcher, epg_origin, scheduler, sql,
+    types::{EPGProgram, Source},
+    utils::{TempFile, get_tmp_path, get_user_agent_from_source, open_compressed_file},
+};
+
+/// Programmes that ended longer ago than this are not imported
+const EPG_KEEP_PAST_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Default, Debug, PartialEq)]
+struct XmltvChannel {
+    id: String,
+    display_names: Vec<String>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct XmltvProgramme {
+    channel: String,
+    start: Option<i64>,
+    stop: Option<i64>,
+    title: Option<String>,
+    subtitle: Option<String>,
+    description: Option<String>,
+    episode_num: Option<String>,
+    category: Option<String>,
+    icon: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum XmltvItem {
+    Channel(XmltvChannel),
+    Programme(XmltvProgramme),
+}
+
+/// Maps XMLTV channel ids to the live channels of a source. Mappings the user confirmed win, then
+/// tvg-id, then names with their country and quality tags left out.
+struct ChannelMapper {
+    by_tvg_id: HashMap<String, Vec<i64>>,
+    by_name: HashMap<String, Vec<i64>>,
+    confirmed: HashMap<String, Vec<i64>>,
+    channels: Vec<XmltvChannel>,
+    targets: Option<HashMap<String, Vec<i64>>>,
+}
+
+impl ChannelMapper {
+    fn new(
+        channels: Vec<(i64, String, Option<String>)>,
+        mappings: HashMap<i64, Option<String>>,
+    ) -> Self {
+        let mut by_tvg_id: HashMap<String, Vec<i64>> = HashMap::new();
+        let mut by_name: HashMap<String, Vec<i64>> = HashMap::new();
+        let mut confirmed: HashMap<String, Vec<i64>> = HashMap::new();
+        for (id, name, tvg_id) in channels {
+            // Channels the user mapped, even to no guide at all, are left out of automatic matching
+            if let Some(mapping) = mappings.get(&id) {
+                if let Some(xmltv_id) = mapping {
+                    confirmed.entry(xmltv_id.trim().to_lowercase()).or_default().push(id);
+                }
+                continue;
+            }
+            if let Some(tvg_id) = tvg_id.filter(|t| !t.trim().is_empty()) {
+                by_tvg_id.entry(tvg_id.trim().to_lowercase()).or_default().push(id);
+            }
+            by_name.entry(epg_matcher::get_match_key(&name)).or_default().push(id);
+        }
+        ChannelMapper {
+            by_tvg_id,
+            by_name,
+            confirmed,
+            channels: Vec::new(),
+            targets: None,
+        }
+    }
+
+    fn add_channel(&mut self, channel: XmltvChannel) {
+        self.channels.push(channel);
+        self.targets = None;
+    }
+
+    /// Channels always come before programmes, so the mapping is settled on the first lookup.
+    /// Names only pick up channels no tvg-id matched.
+    fn get(&mut self, xmltv_id: &str) -> Vec<i64> {
+        let targets = self.targets.get_or_insert_with(|| {
+            let mut targets: HashMap<String, Vec<i64>> = HashMap::new();
+            let mut matched: Vec<i64> = Vec::new();
+            for channel in &self.channels {
+                if let Some(ids) = self.by_tvg_id.get(&channel.id.trim().to_lowercase()) {
+                    targets.insert(channel.id.clone(), ids.clone());
+                    matched.extend(ids);
+                }
+            }
+            for channel in &self.channels {
+                if targets.contains_key(&channel.id) {
+                    continue;
+                }
+                let ids: Vec<i64> = channel
+                    .display_names
+                    .iter()
+                    .filter_map(|name| self.by_name.get(&epg_matcher::get_match_key(name)))
+                    .flatten()
+                    .filter(|id| !matched.contains(id))
+                    .copied()
+                    .collect();
+                if !ids.is_empty() {
+                    targets.insert(channel.id.clone(), ids);
+                }
+            }
+            targets
+        });
+        let xmltv_id_key = xmltv_id.trim().to_lowercase();
+        let mut ids = targets
+            .get(xmltv_id)
+            .or_else(|| self.by_tvg_id.get(&xmltv_id_key))
+            .cloned()
+            .unwrap_or_default();
+        if let Some(confirmed) = self.confirmed.get(&xmltv_id_key) {
+            ids.extend(confirmed);
+        }
+        ids
+    }
+}
+
+/// Imports the guides listed in the source's `epg_url`, replacing its previous programmes.
+/// Entries can be http(s) urls or local files, plain or compressed. Times are moved by the source's
+/// EPG offset and each channel's `tvg-shift`. Returns the programme count.
+pub async fn import_source_epg(source: &Source) -> Result<usize> {
+    let source_id = source.id.context("no source id")?;
+    let locations: Vec<String> = source
+        .epg_url
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if locations.is_empty() {
+        return Ok(0);
+    }
+    // Downloads are removed once the import is done
+    let mut files: Vec<TempFile> = Vec::new();
+    let mut paths = Vec::new();
+    for location in locations {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            let file = download_epg(source, &location)
+                .await
+                .with_context(|| format!("Failed to download EPG {location}"))?;
+            paths.push(file.0.clone());
+            files.push(file);
+        } else {
+            paths.push(location.into());
+        }
+    }
+    let mut sql = sql::get_conn()?;
+    let tx = sql.transaction()?;
+    sql::delete_epg_programs(&tx, source_id, epg_origin::XMLTV)?;
+    sql::delete_epg_channels(&tx, source_id)?;
+    let mut mapper = ChannelMapper::new(
+        sql::get_epg_channel_targets(&tx, source_id)?,
+        sql::get_epg_channel_mappings(&tx, source_id)?,
+    );
+    let shifts = sql::get_epg_channel_shifts(&tx, source_id)?;
+    let epg_offset = source.epg_offset.unwrap_or(0);
+    let get_shift = |channel_id: i64| epg_offset + shifts.get(&channel_id).copied().unwrap_or(0);
+    let mut count = 0;
+    for path in paths {
+        count += import_xmltv_file(&tx, source_id, &path, &mut mapper, &get_shift)
+            .with_context(|| format!("Failed to import EPG {:?}", path))?;
+    }
+    tx.commit()?;
+    // Reminders follow programmes the new guide moved
+    scheduler::wake();
+    Ok(count)
+}
+
+async fn download_epg(source: &Source, url: &str) -> Result<TempFile> {
+    let user_agent = get_user_agent_from_source(source)?;
+    let client = reqwest::Client::builder().user_agent(user_agent).build()?;
+    let mut response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to get EPG, status: {}", response.status());
+    }
+    let tmp_file = TempFile(get_tmp_path(
+        &format!("epg-{}", source.id.unwrap_or_default()),
+        "xml",
+    )?);
+    let mut file = std::fs::File::create(&tmp_file.0)?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)?;
+    }
+    file.flush()?;
+    Ok(tmp_file)
+}
+
+fn import_xmltv_file(
+    tx: &Transaction,
+    source_id: i64,
+    path: &Path,
+    mapper: &mut ChannelMapper,
+    get_shift: &impl Fn(i64) -> i64,
+) -> Result<usize> {
+    let reader = open_compressed_file(path)?;
+    let oldest_stop = chrono::Utc::now().timestamp() - EPG_KEEP_PAST_SECONDS;
+    let mut count = 0;
+    parse_xmltv(reader, |item| {
+        match item {
+            XmltvItem::Channel(channel) => {
+                // Kept for the channel matching assistant
+                sql::insert_epg_channel(tx, source_id, &channel.id, &channel.display_names)?;
+                mapper.add_channel(channel);
+            }
+            XmltvItem::Programme(programme) => {
+                let (Some(start), Some(stop), Some(title)) =
+                    (programme.start, programme.stop, programme.title)
+                else {
+                    return Ok(());
+                };
+                if stop < oldest_stop {
+                    return Ok(());
+                }
+                for channel_id in mapper.get(&programme.channel) {
+                    let shift = get_shift(channel_id);
+                    sql::insert_epg_program(
+                        tx,
+                        &EPGProgram {
+                            id: None,
+                            channel_id,
+                            source_id,
+                            start: start + shift,
+                            stop: stop + shift,
+                            title: title.clone(),
+                            subtitle: programme.subtitle.clone(),
+                            description: programme.description.clone(),
+                            episode_num: programme.episode_num.clone(),
+                            category: programme.category.clone(),
+                            icon: programme.icon.clone(),
+                            origin: epg_origin::XMLTV,
+                            has_archive: false,
+                            server_start: None,
+                        },
+                    )?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Streams an XMLTV document, handing out channels and programmes as they are closed
+fn parse_xmltv(
+    reader: impl BufRead,
+    mut on_item: impl FnMut(XmltvItem) -> Result<()>,
+) -> Result<()> {
+    let mut reader = Reader::from_reader(reader);
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    let mut channel: Option<XmltvChannel> = None;
+    let mut programme: Option<XmltvProgramme> = None;
+    // Element whose text is being read, with the episode-num system when relevant
+    let mut field: Option<(Vec<u8>, Option<String>)> = None;
+    let mut text = String::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"channel" => {
+                    channel = Some(XmltvChannel {
+                        id: get_attribute(&e, b"id").unwrap_or_default(),
+                        ..Default::default()
+                    })
+                }
+                b"programme" => {
+                    programme = Some(XmltvProgramme {
+                        channel: get_attribute(&e, b"channel").unwrap_or_default(),
+                        start: get_attribute(&e, b"start").and_then(|t| parse_xmltv_time(&t)),
+                        stop: get_attribute(&e, b"stop").and_then(|t| parse_xmltv_time(&t)),
+                        ..Default::default()
+                    })
+                }
+                b"icon" => set_icon(&e, &mut programme),
+                name => {
+                    field = Some((name.to_vec(), get_attribute(&e, b"system")));
+                    text.clear();
+                }
+            },
+            Event::Empty(e) if e.local_name().as_ref() == b"icon" => {
+                set_icon(&e, &mut programme)
+            }
+            Event::Text(e) if field.is_some() => text.push_str(&e.unescape()?),
+            Event::CData(e) if field.is_some() => text.push_str(&e.decode()?),
+            Event::End(e) => match e.local_name().as_ref() {
+                b"channel" => {
+                    if let Some(channel) = channel.take() {
+                        on_item(XmltvItem::Channel(channel))?;
+                    }
+                }
+                b"programme" => {
+                    if let Some(programme) = programme.take() {
+                        on_item(XmltvItem::Programme(programme))?;
+                    }
+                }
+                name => {
+                    if let Some((field_name, system)) = field.take() {
+                        if field_name == name {
+                            set_field(&field_name, system, &text, &mut channel, &mut programme);
+                        }
+                    }
+                }
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+fn get_attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.unescape_value().ok().map(|v| v.trim().to_string()))
+        .filter(|v| !v.is_empty())
+}
+
+/// Channel logos already come from the playlist, so only programme icons are kept
+fn set_icon(e: &BytesStart, programme: &mut Option<XmltvProgramme>) {
+    if let Some(programme) = programme.as_mut() {
+        if programme.icon.is_none() {
+            programme.icon = get_attribute(e, b"src");
+        }
+    }
+}
+
+/// Keeps the first value of each programme field, the rest are usually other languages
+fn set_field(
+    name: &[u8],
+    system: Option<String>,
+    text: &str,
+    channel: &mut Option<XmltvChannel>,
+    programme: &mut Option<XmltvProgramme>,
+) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if let Some(channel) = channel.as_mut() {
+        if name == b"display-name" {
+            channel.display_names.push(text.to_string());
+        }
+        return;
+    }
+    let Some(programme) = programme.as_mut() else {
+        return;
+    };
+    let value = match name {
+        b"title" => &mut programme.title,
+        b"sub-title" => &mut programme.subtitle,
+        b"desc" => &mut programme.description,
+        b"category" => &mut programme.category,
+        b"episode-num" => {
+            let episode_num = match system.as_deref() {
+                Some("xmltv_ns") => parse_xmltv_ns(text),
+                _ => Some(text.to_string()),
+            };
+            // An onscreen number is the most readable one, so it wins over earlier ones
+            if system.as_deref() == Some("onscreen") || programme.episode_num.is_none() {
+                programme.episode_num = episode_num;
+            }
+            return;
+        }
+        _ => return,
+    };
+    if value.is_none() {
+        *value = Some(text.to_string());
+    }
+}
+
+/// Parses "20240131203000 +0100", where the offset and trailing fields may be left out
+fn parse_xmltv_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (datetime, offset) = match value.split_once(' ') {
+        Some((datetime, offset)) => (datetime, Some(offset.trim())),
+        None => (value, None),
+    };
+    let digits: String = datetime.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 12 {
+        return None;
+    }
+    let digits = format!("{:0<14}", &digits[..digits.len().min(14)]);
+    let naive = NaiveDateTime::parse_from_str(&digits, "%Y%m%d%H%M%S").ok()?;
+    let offset = match offset {
+        Some(offset) => parse_offset(offset)?,
+        None => 0,
+    };
+    Some(naive.and_utc().timestamp() - offset)
+}
+
+fn parse_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.chars().next()? {
+        '+' => (1, &offset[1..]),
+        '-' => (-1, &offset[1..]),
+        _ => (1, offset),
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Turns the zero based "season.episode.part" numbering into S01E02
+fn parse_xmltv_ns(value: &str) -> Option<String> {
+    let mut parts = value.split('.');
+    let season = parts.next()?.split('/').next()?.trim().parse::<u32>().ok();
+    let episode = parts.next()?.split('/').next()?.trim().parse::<u32>().ok();
+    match (season, episode) {
+        (Some(season), Some(episode)) => Some(format!("S{:02}E{:02}", season + 1, episode + 1)),
+        (None, Some(episode)) => Some(format!("E{:02}", episode + 1)),
+        (Some(season), None) => Some(format!("S{:02}", season + 1)),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod test_xmltv {
+    use std::collections::HashMap;
+
+    use crate::xmltv::{
+        ChannelMapper, XmltvChannel, XmltvItem, XmltvProgramme, parse_xmltv, parse_xmltv_ns,
+        parse_xmltv_time,
+    };
+
+    #[test]
+    fn test_parse_xmltv_time() {
+        assert_eq!(parse_xmltv_time("20240131203000 +0000"), Some(1706733000));
+        assert_eq!(parse_xmltv_time("20240131213000 +0100"), Some(1706733000));
+        assert_eq!(parse_xmltv_time("20240131153000 -0500"), Some(1706733000));
+        assert_eq!(parse_xmltv_time("202401312030"), Some(1706733000));
+        assert_eq!(parse_xmltv_time("2024"), None);
+        assert_eq!(parse_xmltv_ns("0.4.0/1"), Some("S01E05".to_string()));
+        assert_eq!(parse_xmltv_ns(".11."), Some("E12".to_string()));
+    }
+
+    #[test]
+    fn test_parse_xmltv() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tv generator-info-name="test">
+  <channel id="amazing.local">
+    <display-name lang="en">Amazing Channel</display-name>
+    <icon src="http://myurl.local/amazing.png" />
+  </channel>
+  <programme start="20240131203000 +0000" stop="20240131213000 +0000" channel="amazing.local">
+    <title lang="en">News &amp; Weather</title>
+    <title lang="fr">Nouvelles</title>
+    <desc><![CDATA[Today's <news>]]></desc>
+    <episode-num system="xmltv_ns">0.4.</episode-num>
+    <icon src="http://myurl.local/news.png"/>
+  </programme>
+</tv>"#;
+        let mut items = Vec::new();
+        parse_xmltv(xml.as_bytes(), |item| {
+            items.push(item);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                XmltvItem::Channel(XmltvChannel {
+                    id: "amazing.local".to_string(),
+                    display_names: vec!["Amazing Channel".to_string()],
+                }),
+                XmltvItem::Programme(XmltvProgramme {
+                    channel: "amazing.local".to_string(),
+                    start: Some(1706733000),
+                    stop: Some(1706736600),
+                    title: Some("News & Weather".to_string()),
+                    description: Some("Today's <news>".to_string()),
+                    episode_num: Some("S01E05".to_string()),
+                    icon: Some("http://myurl.local/news.png".to_string()),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_channel_mapper() {
+        let mut mapper = ChannelMapper::new(
+            vec![
+                (1, "Amazing HD".to_string(), Some("amazing.local".to_string())),
+                (2, "Amazing".to_string(), None),
+                (3, "UK: Other Channel FHD".to_string(), None),
+                (4, "Amazing Plus".to_string(), Some("amazing.local".to_string())),
+                (5, "Mapped".to_string(), None),
+            ],
+            HashMap::from([(4, None), (5, Some("Other".to_string()))]),
+        );
+        mapper.add_channel(XmltvChannel {
+            id: "Amazing.local".to_string(),
+            display_names: vec!["Amazing".to_string()],
+        });
+        mapper.add_channel(XmltvChannel {
+            id: "other".to_string(),
+            display_names: vec!["OTHER channel".to_string()],
+        });
+        assert_eq!(mapper.get("Amazing.local"), vec![1]);
+        assert_eq!(mapper.get("other"), vec![3, 5]);
+        assert_eq!(mapper.get("amazing.local"), vec![1]);
+        assert!(mapper.get("missing").is_empty());
+    }
+}
@@ -19,25 +19,33 @@
  * This project is a fork of Open TV by Fredolx.
  */
 
-use crate::types::{Channel, ChannelPreserve, EPG, Season, Source};
-use crate::utils::get_user_agent_from_source;
+use crate::types::{
+    Channel, EPGProgram, HttpValidators, RefreshReport, Season, Source, SourceAccount,
+};
+use crate::utils::{
+    add_refresh_failed_action, add_refresh_skipped_line, add_validator_headers, check_refresh_drop,
+    get_account_expiry_warning_days, get_refresh_drop_threshold, get_response_validators, get_unique_key,
+    get_user_agent_from_source, set_refresh_report_counts,
+};
 use crate::{
-    log, media_type,
+    epg_origin, log, media_type, scheduler,
     sql::{self, insert_season},
 };
 use anyhow::{Context, Result};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use chrono::{DateTime, Local, NaiveDateTime};
-use reqwest::Client;
+use chrono::{DateTime, NaiveDateTime};
+use reqwest::{Client, StatusCode};
 use rusqlite::Transaction;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Duration;
 use tokio::join;
 use url::Url;
 use tauri::Emitter;
+use tauri_plugin_notification::NotificationExt;
 
 const GET_LIVE_STREAMS: &str = "get_live_streams";
 const GET_VODS: &str = "get_vod_streams";
@@ -47,8 +55,15 @@ const GET_SERIES_CATEGORIES: &str = "get_series_categories";
 const GET_LIVE_STREAM_CATEGORIES: &str = "get_live_categories";
 const GET_VOD_CATEGORIES: &str = "get_vod_categories";
 const GET_EPG: &str = "get_simple_data_table";
+/// Format of the panel's local times in guide listings
+const SERVER_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const SERVER_OFFSET_STEP: i64 = 15 * 60;
 const LIVE_STREAM_EXTENSION: &str = "ts";
 const NO_SEASON_NUMBER: i64 = -9999;
+const ACTIVE_STATUS: &str = "Active";
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+/// Pause between two series info requests of the background prefetch
+const SERIES_PREFETCH_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct XtreamStream {
@@ -64,6 +79,8 @@ struct XtreamStream {
     container_extension: Option<String>,
     #[serde(default)]
     tv_archive: serde_json::Value,
+    #[serde(default)]
+    tv_archive_duration: serde_json::Value,
     // Metadata fields from Xtream API
     #[serde(default)]
     rating: serde_json::Value,
@@ -81,6 +98,8 @@ struct XtreamStream {
     director: serde_json::Value,
     #[serde(default)]
     added: serde_json::Value,
+    #[serde(default)]
+    last_modified: serde_json::Value,
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct XtreamSeries {
@@ -129,15 +148,12 @@ struct XtreamEPG {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct XtreamEPGItem {
-    id: serde_json::Value,
     title: String,
     description: String,
     start_timestamp: serde_json::Value,
     stop_timestamp: serde_json::Value,
-    now_playing: u8,
     has_archive: u8,
     start: String,
-    end: String,
 }
 
 fn build_xtream_url(source: &mut Source) -> Result<Url> {
@@ -168,7 +184,12 @@ fn build_xtream_url(source: &mut Source) -> Result<Url> {
     Ok(url)
 }
 
-pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source: Source, wipe: bool) -> Result<()> {
+pub async fn get_xtream<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    mut source: Source,
+    refresh: bool,
+    report: &mut RefreshReport,
+) -> Result<()> {
     let url = build_xtream_url(&mut source)?;
     let user_agent = get_user_agent_from_source(&source)?;
     let client = Client::builder().user_agent(&user_agent).build()?;
@@ -176,19 +197,39 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
 
     let _ = app.emit("refresh-progress", format!("[{}] Fetching all playlist data...", source_name));
 
+    // Stream lists are requested conditionally, categories are small and always fetched
+    let get_validators = |action: &str| match source.id.filter(|_| refresh) {
+        Some(id) => sql::get_http_validators(id, action).unwrap_or_else(|e| {
+            log::log(format!("{:?}", e));
+            None
+        }),
+        None => None,
+    };
+    let (live_validators, vods_validators, series_validators) = (
+        get_validators(GET_LIVE_STREAMS),
+        get_validators(GET_VODS),
+        get_validators(GET_SERIES),
+    );
+
     // Fetch ALL data concurrently
     let (live, live_cats, vods, vods_cats, series, series_cats) = join!(
-        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "Live Streams", &client, url.clone(), GET_LIVE_STREAMS),
-        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "Live Categories", &client, url.clone(), GET_LIVE_STREAM_CATEGORIES),
-        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "VOD Info", &client, url.clone(), GET_VODS),
-        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "VOD Categories", &client, url.clone(), GET_VOD_CATEGORIES),
-        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "Series Info", &client, url.clone(), GET_SERIES),
-        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "Series Categories", &client, url.clone(), GET_SERIES_CATEGORIES),
+        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "Live Streams", &client, url.clone(), GET_LIVE_STREAMS, live_validators.as_ref()),
+        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "Live Categories", &client, url.clone(), GET_LIVE_STREAM_CATEGORIES, None),
+        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "VOD Info", &client, url.clone(), GET_VODS, vods_validators.as_ref()),
+        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "VOD Categories", &client, url.clone(), GET_VOD_CATEGORIES, None),
+        get_xtream_http_data_with_progress::<Vec<XtreamStream>, R>(app, &source_name, "Series Info", &client, url.clone(), GET_SERIES, series_validators.as_ref()),
+        get_xtream_http_data_with_progress::<Vec<XtreamCategory>, R>(app, &source_name, "Series Categories", &client, url.clone(), GET_SERIES_CATEGORIES, None),
     );
 
-    let live_count = live.as_ref().map(|v| v.len()).unwrap_or(0);
-    let vods_count = vods.as_ref().map(|v| v.len()).unwrap_or(0);
-    let series_count = series.as_ref().map(|v| v.len()).unwrap_or(0);
+    let get_count = |data: &Result<Option<(Vec<XtreamStream>, HttpValidators)>>| {
+        data.as_ref().ok().and_then(|d| d.as_ref()).map(|(v, _)| v.len()).unwrap_or(0)
+    };
+    let live_count = get_count(&live);
+    let vods_count = get_count(&vods);
+    let series_count = get_count(&series);
+    report.not_modified = [&live, &vods, &series]
+        .iter()
+        .all(|data| matches!(data, Ok(None)));
     
     let grand_total = live_count + vods_count + series_count;
 
@@ -208,25 +249,25 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
     )?;
     
     let tx = sql.transaction()?;
-    let mut channel_preserve: Vec<ChannelPreserve> = Vec::new();
-    if wipe {
-        channel_preserve =
-            sql::get_preserve(&tx, source.id.context("no source id")?).unwrap_or_default();
-        sql::wipe(&tx, source.id.context("Source should have id")?)?;
-    } else {
+    if !refresh {
         source.id = Some(sql::create_or_find_source_by_name(&tx, &source)?);
     }
+    let source_id = source.id.context("no source id")?;
+    let previous_counts = sql::get_refresh_counts(&tx, source_id, None)?;
+    let max_id = sql::get_max_channel_id(&tx)?;
+    let last_seen = chrono::Utc::now().timestamp_millis();
+    // Only media types that were fully fetched get their vanished entries removed
+    let mut refreshed_types: Vec<u8> = Vec::new();
+    let mut new_validators: Vec<(&str, HttpValidators)> = Vec::new();
     let mut fail_count = 0;
     let mut last_error = String::from("Too many Xtream requests failed");
 
     let mut current_offset = 0;
     
     // --- LIVE TV ---
-    if let Ok(streams) = live {
-        let cats = live_cats.unwrap_or_else(|_e| {
-            // Log warning internally if needed, or just proceed with empty cats
-            Vec::new()
-        });
+    // Lists that come back unchanged (Ok(None)) keep their current rows
+    if let Ok(Some((streams, validators))) = live {
+        let cats = get_categories(live_cats, GET_LIVE_STREAM_CATEGORIES, report);
         
         let _ = app.emit("refresh-progress", serde_json::json!({
             "playlist": source_name,
@@ -234,25 +275,29 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
             "percent": (current_offset as f32 / grand_total as f32 * 100.0) as u32
         }).to_string());
         
-        match process_xtream(app, &source_name, "Live TV", &tx, streams.clone(), cats, &source, media_type::LIVESTREAM, current_offset, grand_total) {
-            Ok(_) => current_offset += streams.len(),
+        match process_xtream(app, &source_name, "Live TV", &tx, streams.clone(), cats, &source, media_type::LIVESTREAM, current_offset, grand_total, last_seen, report) {
+            Ok(_) => {
+                current_offset += streams.len();
+                refreshed_types.push(media_type::LIVESTREAM);
+                new_validators.push((GET_LIVE_STREAMS, validators));
+            }
             Err(e) => {
                 log::log(format!("[Backend] [{}] Error processing Live TV: {:?}", source_name, e));
+                add_refresh_failed_action(report, GET_LIVE_STREAMS, &e);
                 last_error = e.to_string();
                 fail_count += 1;
             }
         }
     } else if let Err(e) = live {
         log::log(format!("[Backend] [{}] Failed to fetch Live Streams: {:?}", source_name, e));
+        add_refresh_failed_action(report, GET_LIVE_STREAMS, &e);
         last_error = e.to_string();
         fail_count += 1;
     }
 
     // --- MOVIES ---
-    if let Ok(streams) = vods {
-        let cats = vods_cats.unwrap_or_else(|_e| {
-            Vec::new()
-        });
+    if let Ok(Some((streams, validators))) = vods {
+        let cats = get_categories(vods_cats, GET_VOD_CATEGORIES, report);
 
         let _ = app.emit("refresh-progress", serde_json::json!({
             "playlist": source_name,
@@ -260,25 +305,29 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
             "percent": (current_offset as f32 / grand_total as f32 * 100.0) as u32
         }).to_string());
 
-        match process_xtream(app, &source_name, "Movies", &tx, streams.clone(), cats, &source, media_type::MOVIE, current_offset, grand_total) {
-            Ok(_) => current_offset += streams.len(),
+        match process_xtream(app, &source_name, "Movies", &tx, streams.clone(), cats, &source, media_type::MOVIE, current_offset, grand_total, last_seen, report) {
+            Ok(_) => {
+                current_offset += streams.len();
+                refreshed_types.push(media_type::MOVIE);
+                new_validators.push((GET_VODS, validators));
+            }
             Err(e) => {
                 log::log(format!("[Backend] [{}] Error processing Movies: {:?}", source_name, e));
+                add_refresh_failed_action(report, GET_VODS, &e);
                 if fail_count == 1 { last_error = e.to_string(); }
                 fail_count += 1;
             }
         }
     } else if let Err(e) = vods {
          log::log(format!("[Backend] [{}] Failed to fetch VOD Streams: {:?}", source_name, e));
+         add_refresh_failed_action(report, GET_VODS, &e);
          if fail_count == 1 { last_error = e.to_string(); }
          fail_count += 1;
     }
 
     // --- SERIES ---
-    if let Ok(streams) = series {
-        let cats = series_cats.unwrap_or_else(|_e| {
-            Vec::new()
-        });
+    if let Ok(Some((streams, validators))) = series {
+        let cats = get_categories(series_cats, GET_SERIES_CATEGORIES, report);
 
         let _ = app.emit("refresh-progress", serde_json::json!({
             "playlist": source_name,
@@ -286,16 +335,22 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
             "percent": (current_offset as f32 / grand_total as f32 * 100.0) as u32
         }).to_string());
 
-        match process_xtream(app, &source_name, "Series", &tx, streams.clone(), cats, &source, media_type::SERIE, current_offset, grand_total) {
-            Ok(_) => {}, // Last block, no need to update offset
+        match process_xtream(app, &source_name, "Series", &tx, streams.clone(), cats, &source, media_type::SERIE, current_offset, grand_total, last_seen, report) {
+            Ok(_) => {
+                // Last block, no need to update offset
+                refreshed_types.push(media_type::SERIE);
+                new_validators.push((GET_SERIES, validators));
+            }
             Err(e) => {
                 log::log(format!("[Backend] [{}] Error processing Series: {:?}", source_name, e));
+                add_refresh_failed_action(report, GET_SERIES, &e);
                 if fail_count == 2 { last_error = e.to_string(); }
                 fail_count += 1;
             }
         }
     } else if let Err(e) = series {
          log::log(format!("[Backend] [{}] Failed to fetch Series Streams: {:?}", source_name, e));
+         add_refresh_failed_action(report, GET_SERIES, &e);
          if fail_count == 2 { last_error = e.to_string(); }
          fail_count += 1;
     }
@@ -304,9 +359,18 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
         let _ = tx.rollback();
         return Err(anyhow::anyhow!("Total refresh failed for '{}'. Last error: {}", source_name, last_error));
     }
-    if wipe {
-        sql::restore_preserve(&tx, source.id.context("no source id")?, channel_preserve)?;
+    let counts = sql::get_refresh_counts(&tx, source_id, Some(last_seen))?;
+    if let Err(e) = check_refresh_drop(&previous_counts, &counts, &refreshed_types, get_refresh_drop_threshold()) {
+        let _ = tx.rollback();
+        return Err(e);
+    }
+    let changes = sql::get_refresh_changes(&tx, source_id, last_seen, max_id)?;
+    set_refresh_report_counts(report, &previous_counts, &changes, &refreshed_types);
+    sql::delete_stale_channels(&tx, source_id, &refreshed_types, last_seen, false)?;
+    for (action, validators) in &new_validators {
+        sql::set_http_validators(&tx, source_id, action, validators)?;
     }
+    sql::delete_refresh_orphans(&tx, source_id)?;
     
     let _ = app.emit("refresh-progress", serde_json::json!({
         "playlist": source_name,
@@ -318,6 +382,10 @@ pub async fn get_xtream<R: tauri::Runtime>(app: &tauri::AppHandle<R>, mut source
     
     // Reset pragmas to safe defaults
     let _ = sql.execute_batch("PRAGMA synchronous = NORMAL; PRAGMA journal_mode = WAL;");
+
+    if let Err(e) = update_panel_info(app, &client, url, source_id, &source_name).await {
+        log::log(format!("[Backend] [{}] Failed to read the panel info: {:?}", source_name, e));
+    }
     
     let _ = app.emit("refresh-progress", serde_json::json!({
         "playlist": source_name,
@@ -343,19 +411,28 @@ async fn get_xtream_http_data_with_progress<T, R: tauri::Runtime>(
     client: &Client,
     mut url: Url,
     action: &str,
-) -> Result<T>
+    validators: Option<&HttpValidators>,
+) -> Result<Option<(T, HttpValidators)>>
 where
     T: serde::de::DeserializeOwned,
 {
     url.query_pairs_mut().append_pair("action", action);
     
     // Read text first for debugging
-    let response = client
-        .get(url.clone())
+    let response = add_validator_headers(client.get(url.clone()), validators)
         .header("Accept", "application/json")
         .send()
         .await?;
     let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        let _ = app.emit("refresh-progress", serde_json::json!({
+            "playlist": source_name,
+            "activity": format!("{} unchanged, skipping.", category),
+            "percent": 0
+        }).to_string());
+        return Ok(None);
+    }
+    let new_validators = get_response_validators(&response);
     let text = response.text().await?;
     
     if !status.is_success() {
@@ -377,7 +454,23 @@ where
         "activity": format!("Downloaded {}.", category),
         "percent": 0
     }).to_string());
-    Ok(data)
+    Ok(Some((data, new_validators)))
+}
+
+fn get_categories(
+    cats: Result<Option<(Vec<XtreamCategory>, HttpValidators)>>,
+    action: &str,
+    report: &mut RefreshReport,
+) -> Vec<XtreamCategory> {
+    // Proceed with no categories when they couldn't be fetched
+    match cats {
+        Ok(cats) => cats.map(|(cats, _)| cats).unwrap_or_default(),
+        Err(e) => {
+            log::log(format!("Failed to fetch {}: {:?}", action, e));
+            add_refresh_failed_action(report, action, &e);
+            Vec::new()
+        }
+    }
 }
 
 fn process_xtream<R: tauri::Runtime>(
@@ -391,6 +484,8 @@ fn process_xtream<R: tauri::Runtime>(
     stream_type: u8,
     offset: usize,
     grand_total: usize,
+    last_seen: i64,
+    report: &mut RefreshReport,
 ) -> Result<()> {
     let cats: HashMap<String, String> = cats
         .into_iter()
@@ -400,6 +495,7 @@ fn process_xtream<R: tauri::Runtime>(
         })
         .collect();
     let mut groups: HashMap<String, i64> = HashMap::new();
+    let mut keys: HashMap<String, usize> = HashMap::new();
     let total = streams.len();
     for (i, live) in streams.into_iter().enumerate() {
         if i % 500 == 0 && i > 0 {
@@ -411,6 +507,7 @@ fn process_xtream<R: tauri::Runtime>(
             }).to_string());
         }
         let category_name = get_cat_name(&cats, get_serde_json_string(&live.category_id).as_deref());
+        let last_modified = get_serde_json_i64(&live.last_modified);
         match convert_xtream_live_to_channel(live, source, stream_type, category_name)
             .and_then(|mut channel| {
                 if let Some(source_id) = source.id {
@@ -422,12 +519,28 @@ fn process_xtream<R: tauri::Runtime>(
                     )
                     .unwrap_or_else(|e| log::log(format!("{:?}", e)));
                 }
-                sql::insert_channel(tx, channel)?;
+                let key = channel
+                    .stream_id
+                    .map(|id| id.to_string())
+                    .or(channel.url.clone())
+                    .context("no stream id or url")?;
+                let key = get_unique_key(&mut keys, format!("{stream_type}:{key}"));
+                let series_id = channel
+                    .url
+                    .as_deref()
+                    .filter(|_| stream_type == media_type::SERIE)
+                    .and_then(|url| url.parse::<u64>().ok());
+                sql::upsert_channel(tx, channel, &key, last_seen)?;
+                // Kept to tell which episode lists the background prefetch has to fetch again
+                if let (Some(series_id), Some(source_id)) = (series_id, source.id) {
+                    sql::set_series_last_modified(tx, source_id, series_id, last_modified)?;
+                }
                 Ok(())
             }) {
                 Ok(_) => {},
                 Err(e) => {
                     log::log(format!("{:?}", e));
+                    add_refresh_skipped_line(report, None, format!("{}: {:#}", description, e));
                 }
             }
     }
@@ -483,6 +596,18 @@ fn convert_xtream_live_to_channel(
         plot: get_serde_json_string(&stream.plot),
         cast: get_serde_json_string(&stream.cast),
         director: get_serde_json_string(&stream.director),
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        // Days the panel keeps in its archive
+        catchup_days: get_serde_json_u64(&stream.tv_archive_duration)
+            .filter(|_| stream_type == media_type::LIVESTREAM)
+            .map(|x| x as i64),
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
     })
 }
 
@@ -572,13 +697,54 @@ pub async fn get_episodes(channel: Channel) -> Result<()> {
         return Ok(());
     }
     let mut source = sql::get_source_from_id(channel.source_id.context("no source id")?)?;
-    let mut url = build_xtream_url(&mut source)?;
+    let url = build_xtream_url(&mut source)?;
     let user_agent = get_user_agent_from_source(&source)?;
     let client = Client::builder().user_agent(&user_agent).build()?;
+    fetch_episodes(&client, url, &source, series_id, channel.image).await
+}
+
+/// Fetches the episodes of the source's series that changed on the panel since they were last
+/// fetched, one series at a time so the panel isn't flooded. Returns how many were fetched.
+pub async fn prefetch_episodes(source_id: i64, favorites_only: bool) -> Result<usize> {
+    let series = sql::get_series_to_prefetch(source_id, favorites_only)?;
+    if series.is_empty() {
+        return Ok(0);
+    }
+    let mut source = sql::get_source_from_id(source_id)?;
+    let url = build_xtream_url(&mut source)?;
+    let user_agent = get_user_agent_from_source(&source)?;
+    let client = Client::builder().user_agent(&user_agent).build()?;
+    let mut fetched = 0;
+    for (i, (series_id, image)) in series.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(SERIES_PREFETCH_DELAY).await;
+            // The source may be disabled or removed while the job runs
+            if !sql::get_source_from_id(source_id).is_ok_and(|s| s.enabled) {
+                break;
+            }
+        }
+        match fetch_episodes(&client, url.clone(), &source, series_id, image).await {
+            Ok(_) => fetched += 1,
+            Err(e) => log::log(format!(
+                "[Backend] [{}] Failed to prefetch series {}: {:?}",
+                source.name, series_id, e
+            )),
+        }
+    }
+    Ok(fetched)
+}
+
+async fn fetch_episodes(
+    client: &Client,
+    mut url: Url,
+    source: &Source,
+    series_id: u64,
+    default_season_image: Option<String>,
+) -> Result<()> {
     url.query_pairs_mut()
         .append_pair("series_id", &series_id.to_string());
     let series =
-        get_xtream_http_data::<XtreamSeries>(&client, url, GET_SERIES_INFO).await?;
+        get_xtream_http_data::<XtreamSeries>(client, url, GET_SERIES_INFO).await?;
     let mut episodes: Vec<XtreamEpisode> = series
         .episodes
         .into_values()
@@ -595,7 +761,7 @@ pub async fn get_episodes(channel: Channel) -> Result<()> {
             get_serde_json_i64(&x.episode_num).unwrap_or(0),
         )
     });
-    insert_episodes(&source, seasons, episodes, series_id, channel.image)?;
+    insert_episodes(source, seasons, episodes, series_id, default_season_image)?;
     Ok(())
 }
 
@@ -607,6 +773,7 @@ fn insert_episodes(
     default_season_image: Option<String>,
 ) -> Result<()> {
     let mut seasons_db: HashMap<i64, i64> = HashMap::new();
+    let last_seen = chrono::Utc::now().timestamp_millis();
     sql::do_tx(|tx| {
         for episode in &episodes {
             match insert_episode(
@@ -617,6 +784,7 @@ fn insert_episodes(
                 &seasons,
                 series_id,
                 default_season_image.clone(),
+                last_seen,
             )
             .with_context(|| format!("Failed to insert episode {:?}", episode))
             {
@@ -627,7 +795,7 @@ fn insert_episodes(
                 }
             }
         }
-        Ok(())
+        sql::set_series_episodes_fetched(tx, source.id.context("no source id")?, series_id)
     })
 }
 
@@ -639,6 +807,7 @@ fn insert_episode(
     seasons: &HashMap<i64, XtreamSeason>,
     series_id: u64,
     default_season_image: Option<String>,
+    last_seen: i64,
 ) -> Result<()> {
     let season_number = get_serde_json_i64(&episode.season).unwrap_or(NO_SEASON_NUMBER);
     let season_id = seasons_db.get(&season_number);
@@ -667,8 +836,9 @@ fn insert_episode(
             id
         }
     };
+    let key = format!("episode:{}", get_serde_json_string(&episode.id).context("no id")?);
     let episode = episode_to_channel(episode, source, series_id, season_id)?;
-    sql::insert_channel(tx, episode)?;
+    sql::upsert_channel(tx, episode, &key, last_seen)?;
     Ok(())
 }
 
@@ -767,70 +937,222 @@ fn episode_to_channel(
         plot: None,
         cast: None,
         director: None,
+        tvg_id: None,
+        tvg_chno: None,
+        tvg_shift: None,
+        catchup: None,
+        catchup_source: None,
+        catchup_days: None,
+        tvg_rec: None,
+        now_program: None,
+        next_program: None,
     })
 }
 
-pub async fn get_epg(channel: Channel) -> Result<Vec<EPG>> {
-    let mut source = sql::get_source_from_id(channel.source_id.context("no source id")?)?;
+/// Downloads the guide of a channel from the panel and caches it for `ttl` seconds
+pub async fn fetch_epg(channel: &Channel, mut source: Source, ttl: i64) -> Result<()> {
+    let channel_id = channel.id.context("no channel id")?;
+    let source_id = source.id.context("no source id")?;
     let mut url = build_xtream_url(&mut source)?;
     let user_agent = get_user_agent_from_source(&source)?;
     let client = Client::builder().user_agent(&user_agent).build()?;
     let stream_id = channel.stream_id.context("No stream id")?.to_string();
     url.query_pairs_mut().append_pair("stream_id", &stream_id);
     let epg: XtreamEPG = get_xtream_http_data(&client, url, GET_EPG).await?;
-    let timeshift_url = get_timeshift_url_base(&source)?;
-    let current_time = Local::now();
-    let mut otv_epgs = Vec::with_capacity(epg.epg_listings.len());
-    for item in epg.epg_listings {
-        let epg_item = xtream_epg_to_epg(item, &timeshift_url, &stream_id)?;
-        if is_valid_epg(&epg_item, &current_time)? {
-            otv_epgs.push(epg_item);
-        }
+    let epg_offset = source.epg_offset.unwrap_or(0);
+    let programs: Vec<EPGProgram> = epg
+        .epg_listings
+        .into_iter()
+        .filter_map(|item| {
+            xtream_epg_to_program(item, channel_id, source_id, epg_offset)
+                .map_err(|e| log::log(format!("{:?}", e)))
+                .ok()
+        })
+        .collect();
+    let expires_at = chrono::Utc::now().timestamp() + ttl;
+    sql::do_tx(|tx| sql::set_xtream_epg_programs(tx, channel_id, source_id, &programs, expires_at))?;
+    scheduler::wake();
+    Ok(())
+}
+
+fn xtream_epg_to_program(
+    epg: XtreamEPGItem,
+    channel_id: i64,
+    source_id: i64,
+    epg_offset: i64,
+) -> Result<EPGProgram> {
+    let start = get_serde_json_i64(&epg.start_timestamp).context("no valid start timestamp")?;
+    let stop = get_serde_json_i64(&epg.stop_timestamp).context("no valid end timestamp")?;
+    let description = String::from_utf8(BASE64_STANDARD.decode(&epg.description)?)?;
+    Ok(EPGProgram {
+        id: None,
+        channel_id,
+        source_id,
+        start: start + epg_offset,
+        stop: stop + epg_offset,
+        title: String::from_utf8(BASE64_STANDARD.decode(&epg.title)?)?,
+        subtitle: None,
+        description: Some(description).filter(|d| !d.trim().is_empty()),
+        episode_num: None,
+        category: None,
+        icon: None,
+        origin: epg_origin::XTREAM,
+        has_archive: epg.has_archive == 1,
+        server_start: Some(epg.start),
+    })
+}
+
+/// Timeshift url of a cached programme, when the panel keeps an archive of it
+pub fn get_program_timeshift_url(
+    source: &mut Source,
+    program: &EPGProgram,
+    stream_id: u64,
+) -> Result<Option<String>> {
+    if !program.has_archive {
+        return Ok(None);
     }
-    Ok(otv_epgs)
+    let server_offset = get_server_utc_offset(source, Some(program));
+    let server_start = DateTime::from_timestamp(program.start + server_offset, 0)
+        .context("invalid programme start")?
+        .naive_utc();
+    let duration = (program.stop - program.start) / 60;
+    Ok(Some(get_archive_timeshift_url(source, stream_id, server_start, duration)?))
 }
 
-fn is_valid_epg(epg: &EPG, now: &DateTime<Local>) -> Result<bool> {
-    let epg_start_local = DateTime::from_timestamp(epg.start_timestamp, 0)
-        .map(|dt| dt.with_timezone(&Local))
-        .context("Invalid timestamp")?;
-    if epg_start_local < *now && !epg.has_archive && !epg.now_playing {
-        return Ok(false);
+/// Timeshift url of `duration` minutes of a channel from `server_start`, in the panel's timezone
+pub fn get_archive_timeshift_url(
+    source: &mut Source,
+    stream_id: u64,
+    server_start: NaiveDateTime,
+    duration: i64,
+) -> Result<String> {
+    build_xtream_url(source)?;
+    let url = get_timeshift_url_base(source)?;
+    Ok(get_timeshift_url(url, server_start, duration, &stream_id.to_string()))
+}
+
+/// Seconds the panel's clock is ahead of UTC. The reading taken on refresh wins, a cached
+/// programme's server time is used otherwise.
+pub fn get_server_utc_offset(source: &Source, program: Option<&EPGProgram>) -> i64 {
+    source
+        .server_utc_offset
+        .or_else(|| {
+            let program = program?;
+            let server_start = program.server_start.as_ref()?;
+            let server_start = NaiveDateTime::parse_from_str(server_start, SERVER_TIME_FORMAT).ok()?;
+            // Cached programmes were already moved by the source's EPG offset
+            let start = program.start - source.epg_offset.unwrap_or(0);
+            Some(server_start.and_utc().timestamp() - start)
+        })
+        .unwrap_or(0)
+}
+
+/// Saves the panel's clock and the account's subscription, which may have changed since the last
+/// refresh, and warns about a subscription that ends soon or no longer works
+async fn update_panel_info<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    client: &Client,
+    url: Url,
+    source_id: i64,
+    source_name: &str,
+) -> Result<()> {
+    let info = client.get(url).send().await?.json::<XtreamPanelInfo>().await?;
+    // Archive requests are made in the panel's time
+    if let Some(offset) = get_server_info_utc_offset(&info.server_info) {
+        sql::set_source_server_utc_offset(source_id, offset)?;
+    }
+    let account = get_source_account(&info.user_info);
+    let warning = get_account_warning(
+        &account,
+        chrono::Utc::now().timestamp(),
+        get_account_expiry_warning_days(),
+    );
+    let previous_warning = sql::set_source_account(source_id, &account, warning.as_deref())?;
+    // The same warning is only given once
+    if let Some(warning) = warning.filter(|w| previous_warning.as_ref() != Some(w)) {
+        log::log(format!("[Backend] [{}] {}", source_name, warning));
+        app.notification()
+            .builder()
+            .title(source_name)
+            .body(warning)
+            .show()?;
     }
-    Ok(true)
+    Ok(())
 }
 
-fn xtream_epg_to_epg(epg: XtreamEPGItem, url: &Url, stream_id: &str) -> Result<EPG> {
-    let start_timestamp =
-        get_serde_json_i64(&epg.start_timestamp).context("no valid start timestamp")?;
-    let stop_timestamp = get_serde_json_i64(&epg.stop_timestamp).context("no valid end timestamp")?;
+fn get_source_account(user_info: &XtreamUserInfo) -> SourceAccount {
+    SourceAccount {
+        status: user_info
+            .status
+            .as_deref()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty()),
+        exp_date: get_serde_json_i64(&user_info.exp_date).filter(|date| *date > 0),
+        max_connections: get_serde_json_i64(&user_info.max_connections),
+        active_cons: get_serde_json_i64(&user_info.active_cons),
+        is_trial: get_serde_json_i64(&user_info.is_trial).map(|trial| trial == 1),
+    }
+}
 
-    Ok(EPG {
-        epg_id: get_serde_json_string(&epg.id).context("no epg id")?,
-        title: String::from_utf8(BASE64_STANDARD.decode(&epg.title)?)?,
-        description: String::from_utf8(BASE64_STANDARD.decode(&epg.description)?)?,
-        start_time: DateTime::from_timestamp(start_timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%B %d, %H:%M").to_string())
-            .unwrap_or_default(),
-        end_time: DateTime::from_timestamp(stop_timestamp, 0)
-            .map(|dt| dt.with_timezone(&Local).format("%B %d, %H:%M").to_string())
-            .unwrap_or_default(),
-        start_timestamp,
-        timeshift_url: if epg.has_archive == 1 {
-            Some(get_timeshift_url(
-                url.clone(),
-                epg.start,
-                epg.end,
-                stream_id,
-            )?)
-        } else {
-            None
-        },
-        has_archive: epg.has_archive == 1,
-        now_playing: epg.now_playing == 1,
+/// Warns about an account the panel no longer serves, or that ends within `warning_days`
+fn get_account_warning(account: &SourceAccount, now: i64, warning_days: u16) -> Option<String> {
+    if let Some(status) = account
+        .status
+        .as_deref()
+        .filter(|status| !status.eq_ignore_ascii_case(ACTIVE_STATUS))
+    {
+        return Some(format!("Subscription is {}", status.to_lowercase()));
+    }
+    let left = account.exp_date? - now;
+    if warning_days == 0 || left > warning_days as i64 * DAY_SECONDS {
+        return None;
+    }
+    Some(match (left + DAY_SECONDS - 1) / DAY_SECONDS {
+        ..=0 => "Subscription has expired".to_string(),
+        1 => "Subscription expires within a day".to_string(),
+        days => format!("Subscription expires in {days} days"),
     })
 }
 
+/// Offset of the panel's clock from its current time, or from its timezone name when it doesn't
+/// tell. Only fixed offset names can be read without a timezone database.
+fn get_server_info_utc_offset(server_info: &XtreamServerInfo) -> Option<i64> {
+    let from_clock = server_info
+        .time_now
+        .as_deref()
+        .zip(server_info.timestamp_now)
+        .and_then(|(time_now, timestamp_now)| {
+            let time_now = NaiveDateTime::parse_from_str(time_now.trim(), SERVER_TIME_FORMAT).ok()?;
+            let offset = time_now.and_utc().timestamp() - timestamp_now;
+            // Zones are a whole number of quarter hours, the rest is the delay between the two reads
+            Some((offset as f64 / SERVER_OFFSET_STEP as f64).round() as i64 * SERVER_OFFSET_STEP)
+        });
+    from_clock.or_else(|| parse_timezone_offset(server_info.timezone.as_deref()?))
+}
+
+/// Reads "UTC", "GMT+2", "UTC-03:30" or "Etc/GMT-2", whose sign is inverted by POSIX convention
+fn parse_timezone_offset(timezone: &str) -> Option<i64> {
+    let timezone = timezone.trim();
+    let (offset, inverted) = match timezone.strip_prefix("Etc/") {
+        Some(offset) => (offset, true),
+        None => (timezone, false),
+    };
+    let offset = offset
+        .strip_prefix("UTC")
+        .or_else(|| offset.strip_prefix("GMT"))?;
+    if offset.is_empty() {
+        return Some(0);
+    }
+    let (sign, offset) = match offset.chars().next()? {
+        '+' => (1, &offset[1..]),
+        '-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+    let seconds = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+    Some(if inverted { -sign * seconds } else { sign * seconds })
+}
+
 fn get_timeshift_url_base(source: &Source) -> Result<Url> {
     let mut url = Url::parse(source.url_origin.as_ref().context("no origin")?)?;
     url.path_segments_mut()
@@ -842,30 +1164,31 @@ fn get_timeshift_url_base(source: &Source) -> Result<Url> {
     Ok(url)
 }
 
-fn get_timeshift_url(mut url: Url, start: String, end: String, stream_id: &str) -> Result<String> {
-    let start_dt = NaiveDateTime::parse_from_str(&start, "%Y-%m-%d %H:%M:%S")?;
-    let end_dt = NaiveDateTime::parse_from_str(&end, "%Y-%m-%d %H:%M:%S")?;
-    let duration = end_dt
-        .signed_duration_since(start_dt)
-        .num_minutes()
-        .to_string();
-    let start_str = start_dt.format("%Y-%m-%d:%H-%M").to_string();
+fn get_timeshift_url(mut url: Url, start: NaiveDateTime, duration: i64, stream_id: &str) -> String {
+    let duration = duration.to_string();
+    let start_str = start.format("%Y-%m-%d:%H-%M").to_string();
     url.query_pairs_mut()
         .append_pair("stream", stream_id)
         .append_pair("start", &start_str)
         .append_pair("duration", &duration);
-    Ok(url.to_string())
+    url.to_string()
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct XtreamUserInfo {
     pub username: Option<String>,
     pub status: Option<String>,
-    pub active_cons: Option<String>,
-    pub is_trial: Option<String>,
-    pub created_at: Option<String>,
-    pub exp_date: Option<String>,
-    pub max_connections: Option<String>,
+    // Panels send these as strings or numbers
+    #[serde(default)]
+    pub active_cons: serde_json::Value,
+    #[serde(default)]
+    pub is_trial: serde_json::Value,
+    #[serde(default)]
+    pub created_at: serde_json::Value,
+    #[serde(default)]
+    pub exp_date: serde_json::Value,
+    #[serde(default)]
+    pub max_connections: serde_json::Value,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -929,3 +1252,115 @@ pub async fn fetch_vod_info(channel: Channel) -> Result<XtreamVodInfo> {
     let data = client.get(url).send().await?.json::<XtreamVodInfo>().await?;
     Ok(data)
 }
+
+#[cfg(test)]
+mod test_xtream {
+    use super::*;
+
+    fn server_info(timezone: Option<&str>, time_now: Option<&str>, timestamp_now: Option<i64>) -> XtreamServerInfo {
+        XtreamServerInfo {
+            url: None,
+            port: None,
+            https_port: None,
+            server_protocol: None,
+            rtmp_port: None,
+            timezone: timezone.map(|t| t.to_string()),
+            timestamp_now,
+            time_now: time_now.map(|t| t.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_server_info_utc_offset() {
+        // 2024-03-05 20:30:00 UTC, read a couple of seconds apart
+        let info = server_info(Some("Europe/Paris"), Some("2024-03-05 21:30:02"), Some(1709670600));
+        assert_eq!(get_server_info_utc_offset(&info), Some(3600));
+        let info = server_info(Some("America/St_Johns"), Some("2024-03-05 17:00:00"), Some(1709670600));
+        assert_eq!(get_server_info_utc_offset(&info), Some(-12600));
+        assert_eq!(get_server_info_utc_offset(&server_info(Some("UTC"), None, None)), Some(0));
+        assert_eq!(get_server_info_utc_offset(&server_info(Some("Europe/Paris"), None, None)), None);
+        assert_eq!(get_server_info_utc_offset(&server_info(None, None, None)), None);
+    }
+
+    #[test]
+    fn test_parse_timezone_offset() {
+        assert_eq!(parse_timezone_offset("GMT"), Some(0));
+        assert_eq!(parse_timezone_offset("Etc/UTC"), Some(0));
+        assert_eq!(parse_timezone_offset("UTC+2"), Some(7200));
+        assert_eq!(parse_timezone_offset("GMT-03:30"), Some(-12600));
+        assert_eq!(parse_timezone_offset("Etc/GMT-2"), Some(7200));
+        assert_eq!(parse_timezone_offset("UTCx"), None);
+        assert_eq!(parse_timezone_offset("Asia/Tokyo"), None);
+    }
+
+    #[test]
+    fn test_get_source_account() {
+        let user_info: XtreamUserInfo = serde_json::from_value(serde_json::json!({
+            "username": "user",
+            "status": " Active ",
+            "exp_date": "1709670600",
+            "is_trial": "0",
+            "active_cons": 1,
+            "max_connections": "2"
+        }))
+        .unwrap();
+        let account = get_source_account(&user_info);
+        assert_eq!(account.status.as_deref(), Some("Active"));
+        assert_eq!(account.exp_date, Some(1709670600));
+        assert_eq!(account.is_trial, Some(false));
+        assert_eq!(account.active_cons, Some(1));
+        assert_eq!(account.max_connections, Some(2));
+
+        let user_info: XtreamUserInfo =
+            serde_json::from_value(serde_json::json!({"status": "", "exp_date": null})).unwrap();
+        assert_eq!(get_source_account(&user_info), SourceAccount::default());
+    }
+
+    #[test]
+    fn test_get_account_warning() {
+        let now = 1709670600;
+        let account = |status: &str, exp_date: Option<i64>| SourceAccount {
+            status: Some(status.to_string()),
+            exp_date,
+            ..Default::default()
+        };
+        assert_eq!(get_account_warning(&account("Active", None), now, 7), None);
+        assert_eq!(
+            get_account_warning(&account("Active", Some(now + 8 * DAY_SECONDS)), now, 7),
+            None
+        );
+        assert_eq!(
+            get_account_warning(&account("Active", Some(now + 3 * DAY_SECONDS - 60)), now, 7).as_deref(),
+            Some("Subscription expires in 3 days")
+        );
+        assert_eq!(
+            get_account_warning(&account("active", Some(now + 60)), now, 7).as_deref(),
+            Some("Subscription expires within a day")
+        );
+        assert_eq!(
+            get_account_warning(&account("Active", Some(now - 60)), now, 7).as_deref(),
+            Some("Subscription has expired")
+        );
+        assert_eq!(get_account_warning(&account("Active", Some(now - 60)), now, 0), None);
+        assert_eq!(
+            get_account_warning(&account("Banned", None), now, 0).as_deref(),
+            Some("Subscription is banned")
+        );
+    }
+
+    #[test]
+    fn test_get_server_utc_offset() {
+        let mut source = sql::get_custom_source("Panel".to_string());
+        let program = EPGProgram {
+            start: 1709670600 + 600,
+            stop: 1709674200 + 600,
+            server_start: Some("2024-03-05 22:30:00".to_string()),
+            ..Default::default()
+        };
+        source.epg_offset = Some(600);
+        assert_eq!(get_server_utc_offset(&source, Some(&program)), 7200);
+        assert_eq!(get_server_utc_offset(&source, None), 0);
+        source.server_utc_offset = Some(3600);
+        assert_eq!(get_server_utc_offset(&source, Some(&program)), 3600);
+    }
+}
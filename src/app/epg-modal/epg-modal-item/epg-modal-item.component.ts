@@ -74,6 +74,7 @@ export class EpgModalItemComponent implements OnDestroy {
       epg_id: epg.epg_id,
       start_timestamp: epg.start_timestamp,
       title: epg.title,
+      channel_id: this.channelId,
     };
   }
 
@@ -0,0 +1,6 @@
+import { EPG } from "./epg";
+
+export class ArchiveDay {
+  date!: string;
+  programs!: Array<EPG>;
+}
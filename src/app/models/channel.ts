@@ -1,3 +1,4 @@
+import { ChannelProgram } from './channelProgram';
 import { MediaType } from './mediaType';
 
 export class Channel {
@@ -20,6 +21,17 @@ export class Channel {
   cast?: string;
   director?: string;
   added?: string;
+  // Extended EXTINF attributes
+  tvg_id?: string;
+  tvg_chno?: number;
+  tvg_shift?: number;
+  catchup?: string;
+  catchup_source?: string;
+  catchup_days?: number;
+  tvg_rec?: number;
+  // Guide data for live channels in search results
+  now_program?: ChannelProgram;
+  next_program?: ChannelProgram;
 
   /**
    * Validates that the channel has required fields for playback
@@ -0,0 +1,5 @@
+export class ChannelProgram {
+  title!: string;
+  start!: number;
+  stop!: number;
+}
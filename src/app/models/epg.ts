@@ -5,6 +5,7 @@ export class EPG {
   start_time!: string;
   start_timestamp!: number;
   end_time!: string;
+  end_timestamp!: number;
   has_archive!: boolean;
   now_playing!: boolean;
   timeshift_url?: string;
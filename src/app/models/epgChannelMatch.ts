@@ -0,0 +1,14 @@
+export class EPGChannelSuggestion {
+  xmltv_id!: string;
+  display_name?: string;
+  confidence!: number;
+}
+
+export class EPGChannelMatch {
+  channel_id!: number;
+  channel_name!: string;
+  tvg_id?: string;
+  xmltv_id?: string;
+  confirmed!: boolean;
+  suggestions!: Array<EPGChannelSuggestion>;
+}
@@ -0,0 +1,6 @@
+import { EPG } from "./epg";
+
+export class EPGGridChannel {
+  channel_id!: number;
+  programs!: Array<EPG>;
+}
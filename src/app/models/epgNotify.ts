@@ -3,4 +3,6 @@ export class EPGNotify {
   title!: string;
   channel_name!: string;
   start_timestamp!: number;
+  channel_id?: number;
+  lead_minutes?: number;
 }
@@ -0,0 +1,9 @@
+import { EPG } from "./epg";
+
+export class EPGSearchResult {
+  channel_id!: number;
+  channel_name!: string;
+  channel_image?: string;
+  source_id!: number;
+  program!: EPG;
+}
@@ -0,0 +1,5 @@
+export class PlaylistExport {
+  source_ids: Array<number> = [];
+  group_ids: Array<number> = [];
+  favorites: boolean = false;
+}
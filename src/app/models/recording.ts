@@ -0,0 +1,19 @@
+import { RecordingStatus } from './recordingStatus';
+
+export class Recording {
+  id?: number;
+  channel_id!: number;
+  source_id?: number;
+  channel_name!: string;
+  title!: string;
+  subtitle?: string;
+  episode_num?: string;
+  start!: number;
+  stop!: number;
+  pre_padding?: number;
+  post_padding?: number;
+  status!: RecordingStatus;
+  file_path?: string;
+  error?: string;
+  rule_id?: number;
+}
@@ -0,0 +1,8 @@
+export class RecordingRule {
+  id?: number;
+  title!: string;
+  channel_id?: number;
+  pre_padding?: number;
+  post_padding?: number;
+  enabled!: boolean;
+}
@@ -0,0 +1,7 @@
+export enum RecordingStatus {
+  scheduled = 0,
+  recording = 1,
+  done = 2,
+  failed = 3,
+  cancelled = 4,
+}
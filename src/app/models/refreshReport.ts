@@ -0,0 +1,33 @@
+import { MediaType } from "./mediaType";
+
+export class RefreshReport {
+  id?: number;
+  source_id?: number;
+  source_name!: string;
+  started_at!: number;
+  duration_ms!: number;
+  success!: boolean;
+  error?: string;
+  not_modified!: boolean;
+  media_types!: Array<RefreshMediaTypeCounts>;
+  skipped_count!: number;
+  skipped_lines!: Array<RefreshSkippedLine>;
+  failed_actions!: Array<RefreshFailedAction>;
+}
+
+export class RefreshMediaTypeCounts {
+  media_type!: MediaType;
+  added!: number;
+  updated!: number;
+  removed!: number;
+}
+
+export class RefreshSkippedLine {
+  line?: number;
+  reason!: string;
+}
+
+export class RefreshFailedAction {
+  action!: string;
+  error!: string;
+}
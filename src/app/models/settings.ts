@@ -20,6 +20,11 @@ export class Settings {
   enhanced_video?: boolean;
   theme?: number; // 0=Smooth Glass, 1=Matrix Terminal (Deprecated, locked to 0)
   vpn_mode?: boolean; // VPN mode for unstable connections
+  refresh_drop_threshold?: number; // Max % of items a refresh may remove per media type
+  recording_pre_padding?: number; // Minutes recorded before a scheduled start
+  recording_post_padding?: number; // Minutes recorded after a scheduled end
+  account_expiry_warning_days?: number; // Days before a subscription ends to warn, 0 to never
+  series_prefetch?: number; // 0=Off, 1=Favorite series, 2=All series
 
   /**
    * Validates settings values
@@ -46,6 +51,13 @@ export class Settings {
       errors.push('Refresh interval must be non-negative');
     }
 
+    if (
+      settings.refresh_drop_threshold !== undefined &&
+      (settings.refresh_drop_threshold < 0 || settings.refresh_drop_threshold > 100)
+    ) {
+      errors.push('Refresh drop threshold must be between 0 and 100');
+    }
+
     if (settings.max_text_lines !== undefined && settings.max_text_lines < 1) {
       errors.push('Max text lines must be at least 1');
     }
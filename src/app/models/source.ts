@@ -13,4 +13,13 @@ export class Source {
   max_streams?: number;
   stream_user_agent?: string;
   last_updated?: number;
+  epg_url?: string;
+  epg_offset?: number;
+  server_utc_offset?: number;
+  mac_address?: string;
+  account_status?: string;
+  exp_date?: number;
+  max_connections?: number;
+  active_cons?: number;
+  is_trial?: boolean;
 }
@@ -1,11 +1,11 @@
 export interface XtreamUserInfo {
   username?: string;
   status?: string;
-  active_cons?: string;
-  is_trial?: string;
-  created_at?: string;
-  exp_date?: string;
-  max_connections?: string;
+  active_cons?: string | number;
+  is_trial?: string | number;
+  created_at?: string | number;
+  exp_date?: string | number;
+  max_connections?: string | number;
 }
 
 export interface XtreamServerInfo {
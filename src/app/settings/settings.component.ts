@@ -188,6 +188,12 @@ export class SettingsComponent {
     { value: 24, label: 'Every 24 Hours' },
   ];
 
+  seriesPrefetchOptions = [
+    { value: 0, label: 'Disabled' },
+    { value: 1, label: 'Favorite series' },
+    { value: 2, label: 'All series' },
+  ];
+
   getSources() {
     this.tauri.call('get_sources').then((x) => {
       this.sources = x as Source[];
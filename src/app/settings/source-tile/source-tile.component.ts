@@ -75,9 +75,9 @@ export class SourceTileComponent {
     }
   }
 
-  parseDate(dateStr?: string): number | null {
+  parseDate(dateStr?: string | number): number | null {
     if (!dateStr) return null;
-    const num = parseInt(dateStr, 10);
+    const num = parseInt(String(dateStr), 10);
     return isNaN(num) ? null : num * 1000;
   }
 
@@ -111,6 +111,9 @@ export class SetupComponent {
       case SourceType.Xtream:
         await this.getXtream();
         break;
+      case SourceType.Stalker:
+        await this.getStalker();
+        break;
       case SourceType.Custom:
         await this.custom();
         break;
@@ -201,6 +204,24 @@ export class SetupComponent {
     this.loading = false;
   }
 
+  async getStalker() {
+    this.removeUnusedFieldsFromSource();
+    this.source.url = this.source.url?.trim();
+    this.source.mac_address = this.source.mac_address?.trim();
+    if (!this.source?.url?.startsWith('http://') && !this.source?.url?.startsWith('https://')) {
+      this.source.url = `http://${this.source.url}`;
+      this.toastr.info('Since the given URL lacked a protocol, http was assumed');
+    }
+    this.loading = true;
+    try {
+      await this.tauri.call('get_stalker', { source: this.source });
+      this.success();
+    } catch (e) {
+      this.error.handleError(e, 'Invalid portal URL or MAC address. Please try again');
+    }
+    this.loading = false;
+  }
+
   async nuke() {
     const modalRef = this.modal.open(ConfirmDeleteModalComponent, {
       backdrop: 'static',